@@ -3,24 +3,147 @@
 
 //! State for the Cranko CLI application.
 
-use anyhow::{anyhow, Context};
-use log::{error, info, warn};
-use std::collections::HashMap;
+use anyhow::{anyhow, bail, Context};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+    thread,
+};
 use thiserror::Error as ThisError;
 
 use crate::{
     atry,
-    config::{ConfigurationFile, NpmConfiguration},
-    errors::Result,
+    config::{ConfigurationFile, NpmConfiguration, ProjectConfiguration},
+    env::maybe_var,
+    errors::{AnnotatedReport, Error, Result},
     graph::{ProjectGraph, ProjectGraphBuilder, RepoHistories},
-    project::{DepRequirement, ProjectId},
+    lock::RepoLock,
+    project::{DepRequirement, Project, ProjectId},
     repository::{
         ChangeList, CommitId, PathMatcher, RcCommitInfo, RcProjectInfo, ReleaseAvailability,
-        ReleaseCommitInfo, Repository,
+        ReleaseCommitInfo, ReleasedProjectInfo, RepoPath, RepoPathBuf, Repository,
+        TagCollisionError,
     },
+    rewriters::RewriteContext,
     version::Version,
 };
 
+/// Fill in information about Bitbucket Pipelines CI builds.
+///
+/// The `ci_info` crate doesn't reliably characterize Bitbucket builds, which
+/// would disable many of our workflow safety checks, so we examine its
+/// environment variables ourselves. Fields that `ci_info` managed to fill in
+/// are left alone.
+fn augment_bitbucket_ci_info(ci_info: &mut ci_info::types::CiInfo) -> Result<()> {
+    if maybe_var("BITBUCKET_BUILD_NUMBER")?.is_none() {
+        return Ok(());
+    }
+
+    ci_info.ci = true;
+
+    if ci_info.name.is_none() {
+        ci_info.name = Some("Bitbucket Pipelines".to_owned());
+    }
+
+    // As with other CI services, for a pull request we report the name of the
+    // branch that it targets.
+    let pr_dest = maybe_var("BITBUCKET_PR_DESTINATION_BRANCH")?;
+
+    if ci_info.pr.is_none() {
+        ci_info.pr = Some(pr_dest.is_some() || maybe_var("BITBUCKET_PR_ID")?.is_some());
+    }
+
+    if ci_info.branch_name.is_none() {
+        ci_info.branch_name = match pr_dest {
+            Some(b) => Some(b),
+            None => maybe_var("BITBUCKET_BRANCH")?,
+        };
+    }
+
+    Ok(())
+}
+
+/// Fill in information about GitHub Actions builds with a detached HEAD.
+///
+/// Pull request builds on GitHub Actions check out a synthetic merge commit
+/// (`refs/pull/N/merge`) in detached-HEAD state, and in that situation the
+/// `ci_info` crate's branch and PR information can't be relied upon. So if
+/// HEAD is detached, we reconstruct them from `$GITHUB_REF` and friends,
+/// analogously to what we do for Bitbucket.
+fn augment_github_ci_info(ci_info: &mut ci_info::types::CiInfo, repo: &Repository) -> Result<()> {
+    if maybe_var("GITHUB_ACTIONS")?.as_deref() != Some("true") {
+        return Ok(());
+    }
+
+    if repo.current_branch_name()?.is_some() {
+        return Ok(());
+    }
+
+    ci_info.ci = true;
+
+    if ci_info.name.is_none() {
+        ci_info.name = Some("GitHub Actions".to_owned());
+    }
+
+    let gh_ref = maybe_var("GITHUB_REF")?.unwrap_or_default();
+    let head_ref = maybe_var("GITHUB_HEAD_REF")?.filter(|s| !s.is_empty());
+
+    if gh_ref.starts_with("refs/pull/") || head_ref.is_some() {
+        // As with other CI services, for a pull request we report the name of
+        // the branch that it targets.
+        ci_info.pr = Some(true);
+        ci_info.branch_name = maybe_var("GITHUB_BASE_REF")?.filter(|s| !s.is_empty());
+    } else {
+        ci_info.pr = Some(false);
+        ci_info.branch_name = gh_ref.strip_prefix("refs/heads/").map(|s| s.to_owned());
+    }
+
+    Ok(())
+}
+
+/// Settings from the global command-line options that apply to every
+/// [`AppBuilder`] created by this process.
+///
+/// These are kept in-process rather than being put into the environment, so
+/// that they aren't inherited by any child processes that Cranko launches.
+#[derive(Clone, Debug, Default)]
+pub struct GlobalOptions {
+    /// Don't use cached data from previous invocations.
+    pub no_cache: bool,
+
+    /// Remove any existing lock on the repository.
+    pub break_lock: bool,
+
+    /// Only load projects located within this repository subdirectory.
+    pub prefix: Option<RepoPathBuf>,
+}
+
+lazy_static! {
+    static ref GLOBAL_OPTIONS: RwLock<GlobalOptions> = RwLock::new(GlobalOptions::default());
+}
+
+impl GlobalOptions {
+    /// Set the options that will be applied to every subsequently created
+    /// [`AppBuilder`].
+    pub fn set(opts: GlobalOptions) {
+        if let Ok(mut global) = GLOBAL_OPTIONS.write() {
+            *global = opts;
+        }
+    }
+
+    fn get() -> GlobalOptions {
+        GLOBAL_OPTIONS.read().map(|g| g.clone()).unwrap_or_default()
+    }
+}
+
 /// Setting up a Cranko application session.
 pub struct AppBuilder {
     pub repo: Repository,
@@ -28,6 +151,9 @@ pub struct AppBuilder {
 
     ci_info: ci_info::types::CiInfo,
     populate_graph: bool,
+    prefix: Option<RepoPathBuf>,
+    no_cache: bool,
+    break_lock: bool,
 }
 
 impl AppBuilder {
@@ -38,14 +164,47 @@ impl AppBuilder {
     pub fn new() -> Result<AppBuilder> {
         let repo = Repository::open_from_env()?;
         let graph = ProjectGraphBuilder::new();
-        let ci_info = ci_info::get();
+        let mut ci_info = ci_info::get();
+        augment_bitbucket_ci_info(&mut ci_info)?;
+        augment_github_ci_info(&mut ci_info, &repo)?;
+
+        // The global command-line options take precedence over their
+        // environment-variable equivalents.
+        let global = GlobalOptions::get();
+        let prefix = match global.prefix {
+            Some(p) => Some(p),
+            None => maybe_var("CRANKO_PREFIX")?.map(|p| RepoPathBuf::new(p.as_bytes())),
+        };
+        let no_cache = global.no_cache || maybe_var("CRANKO_NO_CACHE")?.is_some();
+        let break_lock = global.break_lock || maybe_var("CRANKO_BREAK_LOCK")?.is_some();
 
         Ok(AppBuilder {
             graph,
             repo,
             ci_info,
             populate_graph: true,
-        })
+            prefix: None,
+            no_cache: false,
+            break_lock: false,
+        }
+        .prefix(prefix)
+        .no_cache(no_cache)
+        .break_lock(break_lock))
+    }
+
+    /// Start initializing a session around an already-opened repository,
+    /// without consulting the environment, for testing.
+    #[cfg(test)]
+    pub(crate) fn new_for_testing(repo: Repository) -> AppBuilder {
+        AppBuilder {
+            graph: ProjectGraphBuilder::new(),
+            repo,
+            ci_info: ci_info::get(),
+            populate_graph: true,
+            prefix: None,
+            no_cache: false,
+            break_lock: false,
+        }
     }
 
     pub fn populate_graph(mut self, do_populate: bool) -> Self {
@@ -53,13 +212,66 @@ impl AppBuilder {
         self
     }
 
+    /// Only auto-load projects from files located within the specified
+    /// repository subdirectory.
+    ///
+    /// This can save a lot of time in large repositories, but projects outside
+    /// of the prefix will not be known to the graph. Packaging systems that
+    /// load whole workspaces at once, like Cargo, may still register projects
+    /// that lie outside of it.
+    pub fn prefix(mut self, prefix: Option<RepoPathBuf>) -> Self {
+        // Normalize to have a trailing separator, so that `foo` doesn't match
+        // `foobar/`.
+        self.prefix = prefix
+            .map(|p| {
+                let mut p = p.pop_sep().to_owned();
+
+                if AsRef::<[u8]>::as_ref(&p) == b"." {
+                    p.truncate(0);
+                }
+
+                if !p.is_empty() {
+                    p.push("");
+                }
+
+                p
+            })
+            .filter(|p| !p.is_empty());
+        self
+    }
+
+    /// Don't use data cached by previous invocations, such as the output of
+    /// `cargo metadata`.
+    pub fn no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self
+    }
+
+    /// Test whether cached data may be used.
+    pub fn use_cache(&self) -> bool {
+        !self.no_cache
+    }
+
+    /// Remove any existing lock on the repository when the session needs to
+    /// take out its own, rather than waiting for it to be released.
+    pub fn break_lock(mut self, break_lock: bool) -> Self {
+        self.break_lock = break_lock;
+        self
+    }
+
+    /// Get the path to the repository configuration file: either
+    /// `.config/cranko/config.toml` or, as a fallback, `cranko.toml` at the root
+    /// of the repository. The file might not exist.
+    pub fn config_path(&self) -> PathBuf {
+        self.repo.resolve_config_file("config.toml", "cranko.toml")
+    }
+
     /// Finish app initialization, yielding a full AppSession object.
-    pub fn initialize(mut self) -> Result<AppSession> {
+    pub fn initialize(self) -> Result<AppSession> {
         // Start by loading the configuration file, if it exists. If it doesn't
         // we'll get a sensible default.
 
-        let mut cfg_path = self.repo.resolve_config_dir();
-        cfg_path.push("config.toml");
+        let cfg_path = self.config_path();
         let config = ConfigurationFile::get(&cfg_path).with_context(|| {
             format!(
                 "failed to load repository config file `{}`",
@@ -67,6 +279,11 @@ impl AppBuilder {
             )
         })?;
 
+        self.initialize_with_config(config)
+    }
+
+    /// Finish app initialization using an already-loaded configuration.
+    pub fn initialize_with_config(mut self, config: ConfigurationFile) -> Result<AppSession> {
         self.repo
             .apply_config(config.repo)
             .with_context(|| "failed to finalize repository setup")?;
@@ -78,6 +295,7 @@ impl AppBuilder {
         if self.populate_graph {
             let mut cargo = crate::cargo::CargoLoader::default();
             let mut csproj = crate::csproj::CsProjLoader::default();
+            let mut maven = crate::maven::MavenLoader::default();
             let mut npm = crate::npm::NpmLoader::default();
             let mut pypa = crate::pypa::PypaLoader::default();
 
@@ -85,11 +303,19 @@ impl AppBuilder {
             // the graph while iterating over the repo:
             let repo = self.repo;
             let mut graph = self.graph;
+            let prefix = self.prefix.clone();
 
             repo.scan_paths(|p| {
+                if let Some(ref prefix) = prefix {
+                    if !p.starts_with(prefix) {
+                        return Ok(());
+                    }
+                }
+
                 let (dirname, basename) = p.split_basename();
                 cargo.process_index_item(dirname, basename);
                 csproj.process_index_item(&repo, p, dirname, basename)?;
+                maven.process_index_item(p, basename);
                 npm.process_index_item(&repo, &mut graph, p, dirname, basename, &proj_config)?;
                 pypa.process_index_item(dirname, basename);
                 Ok(())
@@ -99,18 +325,31 @@ impl AppBuilder {
             self.graph = graph;
             // End dumb hack.
 
-            cargo.finalize(&mut self, &proj_config)?;
+            if config.cargo.enabled.unwrap_or(true) {
+                cargo.finalize(&mut self, &proj_config)?;
+            }
             csproj.finalize(&mut self, &proj_config)?;
+            maven.finalize(&mut self, &proj_config)?;
             npm.finalize(&mut self)?;
             pypa.finalize(&mut self, &proj_config)?;
+            crate::meta::load_meta_projects(&mut self, &proj_config)?;
         }
 
         // Apply project config and compile the graph.
 
-        let graph = atry!(
-            self.graph.complete_loading();
-            ["the project graph is invalid"]
-        );
+        let graph = match self.prefix {
+            None => atry!(
+                self.graph.complete_loading();
+                ["the project graph is invalid"]
+            ),
+
+            Some(ref prefix) => atry!(
+                self.graph.complete_loading();
+                ["the project graph is invalid"]
+                (note "only projects within the prefix `{}` were loaded", prefix.escaped())
+                (note "if a project depends on one outside of the prefix, choose a broader prefix")
+            ),
+        };
 
         // All done.
         Ok(AppSession {
@@ -118,10 +357,165 @@ impl AppBuilder {
             graph,
             npm_config: config.npm,
             ci_info: self.ci_info,
+            lock: OnceCell::new(),
+            break_lock: self.break_lock,
+            prefix: self.prefix,
         })
     }
 }
 
+/// Get the version from which a project's new version is computed by
+/// [`AppSession::apply_versions`]: its most recent release, or in development
+/// mode, its configured `dev_version` if there is one.
+///
+/// The `post-release-dev` bump marks the most recent release as being in
+/// development, so it always starts from that release.
+fn baseline_version(
+    proj: &Project,
+    latest_release: Option<&ReleasedProjectInfo>,
+    rc: Option<&RcProjectInfo>,
+    dev_mode: bool,
+) -> Result<Version> {
+    let post_release = rc.is_some_and(|rc| rc.bump_spec == "post-release-dev");
+
+    Ok(
+        if let (true, false, Some(v)) = (dev_mode, post_release, &proj.dev_version) {
+            v.clone()
+        } else if let Some(info) = latest_release {
+            proj.version.parse_like(&info.version)?
+        } else {
+            proj.version.zero_like()
+        },
+    )
+}
+
+lazy_static! {
+    /// Versions obtained from `version_command`s, keyed by the project's
+    /// configuration name, so that each command is run at most once per
+    /// process.
+    static ref COMMAND_VERSIONS: Mutex<HashMap<String, Version>> = Mutex::new(HashMap::new());
+}
+
+/// Determine a project's version using its `version_command`, if one is
+/// configured.
+///
+/// This is an escape hatch for projects whose version can't be found in any
+/// file in the repository, to be used by the loaders when they come up empty.
+/// *qnames* are the project's qualified names, *prefix* is its directory,
+/// where the command is run, and *template* determines how the command's
+/// output is parsed. Returns None if no command is configured.
+///
+/// If a version for the project has already been recorded, by `cranko
+/// bootstrap` or a release, that is used instead, so the command generally
+/// only needs to be run when bootstrapping.
+pub fn version_from_command(
+    repo: &Repository,
+    pconfig: &HashMap<String, ProjectConfiguration>,
+    qnames: &[String],
+    prefix: &RepoPath,
+    template: &Version,
+) -> Result<Option<Version>> {
+    let config_name = qnames
+        .iter()
+        .rev()
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>()
+        .join(":");
+
+    let command = match pconfig
+        .get(&config_name)
+        .and_then(|c| c.version_command.as_ref())
+    {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    if let Some(v) = COMMAND_VERSIONS.lock().unwrap().get(&config_name) {
+        return Ok(Some(v.clone()));
+    }
+
+    let rel_info = repo.get_latest_release_info()?;
+
+    if let Some(rel) = rel_info.projects.iter().find(|r| r.qnames == qnames) {
+        let version = atry!(
+            template.parse_like(&rel.version);
+            ["cannot parse the recorded version `{}` of project `{}`", rel.version, config_name]
+        );
+        debug!(
+            "{}: using recorded version {} rather than running its `version_command`",
+            config_name, version
+        );
+        return Ok(Some(version));
+    }
+
+    let version = run_version_command(repo, &config_name, command, prefix, template)?;
+    COMMAND_VERSIONS
+        .lock()
+        .unwrap()
+        .insert(config_name, version.clone());
+    Ok(Some(version))
+}
+
+/// Run a project's `version_command` and parse its output.
+fn run_version_command(
+    repo: &Repository,
+    config_name: &str,
+    command: &str,
+    prefix: &RepoPath,
+    template: &Version,
+) -> Result<Version> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = process::Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+
+    cmd.arg(command);
+    cmd.current_dir(repo.resolve_workdir(prefix));
+    cmd.stdin(process::Stdio::null());
+
+    let output = atry!(
+        cmd.output();
+        ["failed to launch the `version_command` `{}` of project `{}`", command, config_name]
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = if stderr.trim().is_empty() {
+        "(nothing)".into()
+    } else {
+        stderr
+    };
+
+    let status = if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("the command failed ({})", output.status))
+    };
+
+    atry!(
+        status;
+        ["failed to determine the version of project `{}` with its `version_command` `{}`",
+         config_name, command]
+        (note "the command's error output was: {}", stderr.trim_end())
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = stdout.trim();
+
+    let version = atry!(
+        template.parse_like(text);
+        ["cannot parse the output `{}` of the `version_command` of project `{}` as a version",
+         text, config_name]
+        (note "the command's error output was: {}", stderr.trim_end())
+    );
+
+    Ok(version)
+}
+
 /// An error returned when one project in the repository needs a newer release
 /// of another project. The inner values are the user-facing names of the two
 /// projects: the first named project depends on the second one.
@@ -129,6 +523,38 @@ impl AppBuilder {
 #[error("unsatisfied internal requirement: `{0}` needs newer `{1}`")]
 pub struct UnsatisfiedInternalRequirementError(pub String, pub String);
 
+/// Build up notes explaining why the internal requirements of the last project
+/// in *chain* can't be satisfied, recursing into dependees that have
+/// unsatisfiable requirements of their own.
+fn describe_unsatisfied(
+    graph: &ProjectGraph,
+    unsatisfied: &HashMap<ProjectId, Vec<(ProjectId, String)>>,
+    chain: &mut Vec<ProjectId>,
+    notes: &mut Vec<String>,
+) {
+    let ident = *chain.last().unwrap();
+    let path = chain
+        .iter()
+        .map(|i| format!("`{}`", graph.lookup(*i).user_facing_name))
+        .collect::<Vec<_>>()
+        .join(" -> ");
+
+    for (dep_ident, reason) in unsatisfied.get(&ident).map(|v| &v[..]).unwrap_or(&[]) {
+        notes.push(format!(
+            "{} -> `{}`: {}",
+            path,
+            graph.lookup(*dep_ident).user_facing_name,
+            reason
+        ));
+
+        if !chain.contains(dep_ident) {
+            chain.push(*dep_ident);
+            describe_unsatisfied(graph, unsatisfied, chain, notes);
+            chain.pop();
+        }
+    }
+}
+
 /// The main Cranko CLI application state structure.
 pub struct AppSession {
     /// The backing repository.
@@ -142,6 +568,16 @@ pub struct AppSession {
 
     /// Information about the CI environment that we may be running in.
     ci_info: ci_info::types::CiInfo,
+
+    /// The advisory lock on the repository, taken out before we modify it.
+    lock: OnceCell<RepoLock>,
+
+    /// Whether to remove any existing lock when taking out our own.
+    break_lock: bool,
+
+    /// The repository subdirectory to which project loading was restricted,
+    /// if any.
+    prefix: Option<RepoPathBuf>,
 }
 
 impl AppSession {
@@ -150,6 +586,21 @@ impl AppSession {
         AppBuilder::new()?.initialize()
     }
 
+    /// Create a session wrapping an already-opened repository and completed
+    /// project graph, for testing.
+    #[cfg(test)]
+    pub(crate) fn new_for_testing(repo: Repository, graph: ProjectGraph) -> Self {
+        AppSession {
+            repo,
+            graph,
+            npm_config: NpmConfiguration::default(),
+            ci_info: ci_info::get(),
+            lock: OnceCell::new(),
+            break_lock: false,
+            prefix: None,
+        }
+    }
+
     /// Characterize the repository environment in which this process is
     /// running.
     pub fn execution_environment(&self) -> Result<ExecutionEnvironment> {
@@ -191,7 +642,8 @@ impl AppSession {
             // `rc` branch we can't determine `current_branch`. It would be kind
             // of tedious to force all Azure users to manually check out the RC
             // branch, so if we can parse out the RC info, let's assume that's
-            // what's going on.
+            // what's going on. GitHub Actions pull request builds are also
+            // detached, but `augment_github_ci_info` has taken care of them.
 
             let is_rc_update = maybe_ci_branch == Some(rc_name);
             let current_is_release = self
@@ -227,6 +679,27 @@ impl AppSession {
         }
     }
 
+    /// Determine which CI service the process is running in, if any.
+    ///
+    /// Whether we're in CI at all is determined by `ci_info`; we then identify
+    /// the services that we care about through their characteristic
+    /// environment variables.
+    pub fn ci_provider(&self) -> Result<Option<CiProvider>> {
+        if !self.ci_info.ci {
+            return Ok(None);
+        }
+
+        Ok(Some(if maybe_var("GITHUB_ACTIONS")?.is_some() {
+            CiProvider::GithubActions
+        } else if maybe_var("TF_BUILD")?.is_some() {
+            CiProvider::AzurePipelines
+        } else if maybe_var("GITLAB_CI")?.is_some() {
+            CiProvider::Gitlab
+        } else {
+            CiProvider::Other
+        }))
+    }
+
     /// Check that the current process is running *outside* of a CI environment.
     pub fn ensure_not_ci(&self, force: bool) -> Result<()> {
         match self.execution_environment()? {
@@ -369,26 +842,44 @@ impl AppSession {
     /// there are unsatisfiable internal dependencies. This can happen either
     /// because no sufficiently new release of the dependee exists (and it's not
     /// being released now), or the internal version requirement information
-    /// hasn't been annotated.
+    /// hasn't been annotated. The error is annotated with notes describing
+    /// each requirement that couldn't be met, following the chain through any
+    /// dependees that are themselves unreleasable.
+    ///
+    /// Progress is logged at the debug level as each project is processed.
     pub fn solve_internal_deps<F>(&mut self, mut process: F) -> Result<()>
     where
         F: FnMut(&mut Repository, &mut ProjectGraph, ProjectId) -> Result<bool>,
     {
         let mut new_versions: HashMap<ProjectId, Version> = HashMap::new();
         let toposorted_idents: Vec<_> = self.graph.toposorted().collect();
-        let mut unsatisfied_deps = Vec::new();
+        let n_projects = toposorted_idents.len();
 
-        for ident in (toposorted_idents[..]).iter().copied() {
+        // For each project with unsatisfiable requirements, the dependees and
+        // descriptions of the problems. We keep these around for projects that
+        // aren't being released so that we can explain the full chain of
+        // problems if something downstream of them is.
+        let mut unsatisfied: HashMap<ProjectId, Vec<(ProjectId, String)>> = HashMap::new();
+
+        for (n_done, ident) in toposorted_idents.iter().copied().enumerate() {
             // We can't conveniently navigate the deps while holding a mutable
             // ref to depending project, so do some lifetime futzing and buffer
             // up modifications to its dep info.
 
-            unsatisfied_deps.clear();
+            let mut unsatisfied_deps = Vec::new();
 
             let mut resolved_versions = {
                 let proj = self.graph.lookup(ident);
                 let mut resolved_versions = Vec::new();
 
+                debug!(
+                    "solving internal dependencies of `{}` ({} of {}; {} remaining)",
+                    proj.user_facing_name,
+                    n_done + 1,
+                    n_projects,
+                    n_projects - n_done - 1
+                );
+
                 for (idx, dep) in proj.internal_deps.iter().enumerate() {
                     match dep.cranko_requirement {
                         // If the requirement is of a specific commit, we need
@@ -403,8 +894,14 @@ impl AppSession {
 
                             let resolved = match avail {
                                 ReleaseAvailability::NotAvailable => {
-                                    unsatisfied_deps
-                                        .push(dependee_proj.user_facing_name.to_string());
+                                    unsatisfied_deps.push((
+                                        dep.ident,
+                                        format!(
+                                            "it requires commit {}, which is not contained in any release of `{}` \
+                                             or in the current history",
+                                            cid, dependee_proj.user_facing_name
+                                        ),
+                                    ));
                                     dependee_proj.version.clone()
                                 }
 
@@ -414,8 +911,14 @@ impl AppSession {
                                     if let Some(v) = new_versions.get(&dep.ident) {
                                         v.clone()
                                     } else {
-                                        unsatisfied_deps
-                                            .push(dependee_proj.user_facing_name.to_string());
+                                        unsatisfied_deps.push((
+                                            dep.ident,
+                                            format!(
+                                                "it requires commit {}, which will only be available in \
+                                                 a new release of `{}`, but that project isn't being released",
+                                                cid, dependee_proj.user_facing_name
+                                            ),
+                                        ));
                                         dependee_proj.version.clone()
                                     }
                                 }
@@ -428,7 +931,13 @@ impl AppSession {
 
                         DepRequirement::Unavailable => {
                             let dependee_proj = self.graph.lookup(dep.ident);
-                            unsatisfied_deps.push(dependee_proj.user_facing_name.to_string());
+                            unsatisfied_deps.push((
+                                dep.ident,
+                                format!(
+                                    "its required version of `{}` hasn't been annotated",
+                                    dependee_proj.user_facing_name
+                                ),
+                            ));
                             resolved_versions.push((idx, dependee_proj.version.clone()));
                         }
                     }
@@ -457,11 +966,37 @@ impl AppSession {
 
             if updated_version {
                 if !unsatisfied_deps.is_empty() {
-                    return Err(UnsatisfiedInternalRequirementError(
+                    let mut names = Vec::new();
+
+                    for (dep_ident, _) in &unsatisfied_deps {
+                        let name = &self.graph.lookup(*dep_ident).user_facing_name;
+
+                        if !names.contains(name) {
+                            names.push(name.clone());
+                        }
+                    }
+
+                    unsatisfied.insert(ident, unsatisfied_deps);
+                    let mut notes = Vec::new();
+                    describe_unsatisfied(&self.graph, &unsatisfied, &mut vec![ident], &mut notes);
+
+                    let err: Error = UnsatisfiedInternalRequirementError(
                         proj.user_facing_name.to_string(),
-                        unsatisfied_deps.join(", "),
+                        names.join(", "),
                     )
-                    .into());
+                    .into();
+
+                    let mut ar = AnnotatedReport::default();
+                    ar.set_message(format!(
+                        "cannot release project `{}`",
+                        proj.user_facing_name
+                    ));
+
+                    for note in notes {
+                        ar.add_note(note);
+                    }
+
+                    return Err(err.context(ar));
                 }
 
                 new_versions.insert(ident, proj.version.clone());
@@ -471,6 +1006,7 @@ impl AppSession {
                      but that's OK since it's not going to be released",
                     proj.user_facing_name
                 );
+                unsatisfied.insert(ident, unsatisfied_deps);
             }
         }
 
@@ -516,29 +1052,45 @@ impl AppSession {
     /// This also involves solving the version requirements for internal
     /// dependencies. If an internal dependency is unsatisfiable, the returned
     /// error will be downcastable to an UnsatisfiedInternalRequirementError.
-    pub fn apply_versions(&mut self, rc_info: &RcCommitInfo) -> Result<()> {
+    pub fn apply_versions(&mut self, rc_info: &RcCommitInfo, dev_mode: bool) -> Result<()> {
         let latest_info = self.repo.get_latest_release_info()?;
 
         self.solve_internal_deps(|_repo, graph, ident| {
+            // Meta-projects follow their members, which have already been
+            // processed since they come earlier in the toposort.
+            let aggregate_version = graph.aggregate_version(ident)?;
             let proj = graph.lookup_mut(ident);
 
-            // Set the baseline version to the last release.
+            // Set the baseline version to the last release, or the configured
+            // development-mode version if appropriate.
 
-            let latest_release = latest_info.lookup_project(proj);
-
-            proj.version = if let Some(info) = latest_release {
-                proj.version.parse_like(&info.version)?
-            } else {
-                proj.version.zero_like()
-            };
+            proj.version = baseline_version(
+                proj,
+                latest_info.lookup_project(proj),
+                rc_info.lookup_project(proj),
+                dev_mode,
+            )?;
 
             let baseline_version = proj.version.clone();
 
             // If there's a bump, apply it.
 
             Ok(if let Some(rc) = rc_info.lookup_project(proj) {
-                let scheme = proj.version.parse_bump_scheme(&rc.bump_spec)?;
-                scheme.apply(&mut proj.version)?;
+                if let Some(v) = aggregate_version {
+                    // The requested bump is irrelevant here.
+                    proj.version = v;
+
+                    if proj.version <= baseline_version {
+                        warn!(
+                            "{}: meta-project version is not increasing ({} => {})",
+                            proj.user_facing_name, baseline_version, proj.version
+                        );
+                    }
+                } else {
+                    let scheme = proj.version.parse_bump_scheme(&rc.bump_spec)?;
+                    scheme.apply(&mut proj.version)?;
+                }
+
                 info!(
                     "{}: {} => {}",
                     proj.user_facing_name, baseline_version, proj.version
@@ -557,16 +1109,85 @@ impl AppSession {
         Ok(())
     }
 
+    /// Take out the advisory lock on the repository, if we haven't already.
+    ///
+    /// This should be called before any operation that modifies the
+    /// repository, so that concurrent Cranko processes don't step on each
+    /// other. The lock is held until the session is dropped.
+    fn acquire_lock(&self) -> Result<()> {
+        if self.lock.get().is_none() {
+            let lock = RepoLock::acquire(self.repo.resolve_lock_path(), self.break_lock)?;
+            let _ignored = self.lock.set(lock);
+        }
+
+        Ok(())
+    }
+
     /// Rewrite everyone's metadata to match our internal state.
     pub fn rewrite(&self) -> Result<ChangeList> {
-        let mut changes = ChangeList::default();
+        let idents: Vec<_> = self.graph.toposorted().collect();
+        self.rewrite_selected(&idents)
+    }
 
-        for ident in self.graph.toposorted() {
-            let proj = self.graph.lookup(ident);
+    /// Rewrite the metadata of the projects in *idents* to match our internal
+    /// state, leaving the files of other projects alone.
+    pub fn rewrite_selected(&self, idents: &[ProjectId]) -> Result<ChangeList> {
+        self.acquire_lock()?;
 
-            for rw in &proj.rewriters {
-                rw.rewrite(self, &mut changes)?;
+        // The projects are rewritten in parallel. Worker threads pull projects
+        // off of a shared counter, and each project gets its own change list
+        // so that we can merge them in a deterministic order at the end.
+        // Within a project, the rewriters are run in sequence.
+
+        let todo: Vec<_> = self
+            .graph
+            .toposorted()
+            .filter(|ident| idents.contains(ident))
+            .collect();
+        let ctx = RewriteContext::new(
+            self.repo.resolve_workdir(&RepoPathBuf::new(b"")),
+            &self.graph,
+            &self.npm_config,
+        );
+        let n_workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(todo.len());
+        let next_index = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(todo.len()));
+
+        let graph = &self.graph;
+
+        thread::scope(|s| {
+            for _ in 0..n_workers {
+                s.spawn(|| loop {
+                    let i = next_index.fetch_add(1, Ordering::SeqCst);
+
+                    if i >= todo.len() {
+                        break;
+                    }
+
+                    let proj = graph.lookup(todo[i]);
+                    let mut proj_changes = ChangeList::default();
+                    let r = proj
+                        .rewriters
+                        .iter()
+                        .try_for_each(|rw| rw.rewrite(&ctx, &mut proj_changes));
+                    results.lock().unwrap().push((i, r.map(|_| proj_changes)));
+                });
             }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _)| *i);
+        let mut changes = ChangeList::default();
+
+        for (i, r) in results {
+            let proj_changes = atry!(
+                r;
+                ["failed to rewrite metadata for `{}`", self.graph.lookup(todo[i]).user_facing_name]
+            );
+            changes.merge(proj_changes);
         }
 
         Ok(changes)
@@ -588,21 +1209,44 @@ impl AppSession {
         Ok(changes)
     }
 
-    pub fn make_release_commit(&mut self, rci: &RcCommitInfo) -> Result<()> {
-        self.repo.make_release_commit(&self.graph, rci)
+    pub fn make_release_commit(&mut self, rci: &RcCommitInfo) -> Result<ReleaseCommitInfo> {
+        self.acquire_lock()?;
+        self.repo
+            .make_release_commit(&self.graph, rci, self.prefix.is_some())
     }
 
     pub fn make_rc_commit(
         &mut self,
         rcinfo: Vec<RcProjectInfo>,
         changes: &ChangeList,
+        amend: Option<CommitId>,
     ) -> Result<()> {
-        self.repo.make_rc_commit(rcinfo, changes)?;
+        self.acquire_lock()?;
+        self.repo.make_rc_commit(rcinfo, changes, amend)?;
         Ok(())
     }
 
+    /// Update the specified files in the working tree to reset them to what
+    /// HEAD says they should be.
+    pub fn hard_reset_changes(&self, changes: &ChangeList) -> Result<()> {
+        self.acquire_lock()?;
+        self.repo.hard_reset_changes(changes)
+    }
+
     pub fn analyze_histories(&self) -> Result<RepoHistories> {
-        self.graph.analyze_histories(&self.repo)
+        self.graph.analyze_histories(&self.repo, None, false)
+    }
+
+    /// Analyze project histories back to the specified commit, rather than
+    /// to each project's most recent release.
+    pub fn analyze_histories_since(&self, since: CommitId) -> Result<RepoHistories> {
+        self.graph.analyze_histories(&self.repo, Some(since), false)
+    }
+
+    /// Analyze project histories, not counting changes to files that only
+    /// affect whitespace.
+    pub fn analyze_histories_ignoring_whitespace(&self) -> Result<RepoHistories> {
+        self.graph.analyze_histories(&self.repo, None, true)
     }
 
     pub fn default_dev_rc_info(&self) -> RcCommitInfo {
@@ -618,13 +1262,15 @@ impl AppSession {
         rcinfo
     }
 
-    /// Rewrite all packages' changelogs to include their full release-branch
-    /// content. Packages staged for release will have new entries created
-    /// giving their final version numbers and other release information.
+    /// Rewrite the changelogs of the packages in *idents* to include their
+    /// full release-branch content. Packages staged for release will have new
+    /// entries created giving their final version numbers and other release
+    /// information.
     pub fn apply_changelogs(
         &self,
         latest_release_commit: Option<CommitId>,
         rcinfo: &RcCommitInfo,
+        idents: &[ProjectId],
         changes: &mut ChangeList,
     ) -> Result<()> {
         // This step could plausibly be implemented in the "rewriter" framework,
@@ -632,6 +1278,10 @@ impl AppSession {
         // than that it seemed easier at the time.
 
         for ident in self.graph.toposorted() {
+            if !idents.contains(&ident) {
+                continue;
+            }
+
             let proj = self.graph.lookup(ident);
 
             if rcinfo.lookup_project(proj).is_some() {
@@ -648,11 +1298,78 @@ impl AppSession {
         Ok(())
     }
 
-    /// Create version control tags for new releases.
-    pub fn create_tags(&mut self, rel_info: &ReleaseCommitInfo) -> Result<()> {
+    /// Create version control tags for the new releases of the projects in
+    /// *idents*.
+    ///
+    /// Unless *force* is true, this first checks that none of the tags to be
+    /// created already exist, so that a failure doesn't leave the repository
+    /// only partially tagged. In all cases, it checks that no two projects
+    /// would be given the same tag name.
+    ///
+    /// If *print_only* is true, no tags are created. Instead, each tag name is
+    /// printed along with the commit that it would point to. The check for
+    /// existing tags is still performed, after everything has been printed.
+    pub fn create_tags(
+        &mut self,
+        rel_info: &ReleaseCommitInfo,
+        idents: &[ProjectId],
+        force: bool,
+        print_only: bool,
+    ) -> Result<()> {
+        let mut collisions = Vec::new();
+        let mut tag_owners = HashMap::new();
+
+        for ident in self.graph.toposorted() {
+            if !idents.contains(&ident) {
+                continue;
+            }
+
+            let proj = self.graph.lookup(ident);
+
+            if let Some(rel) = rel_info.lookup_if_released(proj) {
+                let tagname = self.repo.get_tag_name(proj, rel, rel_info.commit)?;
+
+                if let Some(other) = tag_owners.insert(tagname.clone(), &proj.user_facing_name) {
+                    bail!(
+                        "projects `{}` and `{}` would both be given the release tag `{}`",
+                        other,
+                        proj.user_facing_name,
+                        tagname
+                    );
+                }
+
+                if print_only {
+                    match rel_info.commit {
+                        Some(cid) => println!("{} {}", tagname, cid),
+                        None => println!("{} HEAD", tagname),
+                    }
+                }
+
+                if !force && self.repo.tag_exists(&tagname)? {
+                    collisions.push(tagname);
+                }
+            }
+        }
+
+        if !collisions.is_empty() {
+            atry!(
+                Err::<(), _>(TagCollisionError(collisions));
+                ["refusing to create any release tags"]
+                (note "use `cranko release-workflow tag --force` to overwrite the existing tags")
+            );
+        }
+
+        if print_only {
+            return Ok(());
+        }
+
         for proj in self.graph.toposorted_mut() {
+            if !idents.contains(&proj.ident()) {
+                continue;
+            }
+
             if let Some(rel) = rel_info.lookup_if_released(proj) {
-                self.repo.tag_project_at_head(proj, rel)?;
+                self.repo.tag_project_at_head(proj, rel, force)?;
             }
         }
 
@@ -660,6 +1377,22 @@ impl AppSession {
     }
 }
 
+/// CI services that Cranko knows how to interact with specially.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CiProvider {
+    /// Azure Pipelines.
+    AzurePipelines,
+
+    /// GitHub Actions.
+    GithubActions,
+
+    /// GitLab CI/CD.
+    Gitlab,
+
+    /// Some other CI service.
+    Other,
+}
+
 /// Different categorizations of the environment in which the program is
 /// running.
 pub enum ExecutionEnvironment {
@@ -681,3 +1414,97 @@ pub enum ExecutionEnvironment {
     /// that we're running in an individual development environment.
     NotCi,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::VersionBumpScheme;
+
+    #[test]
+    fn post_release_dev_baseline() {
+        let mut graph = ProjectGraphBuilder::new();
+        let ident = graph
+            .try_add_project(vec!["proj".to_owned()], &HashMap::new())
+            .unwrap();
+        let b = graph.lookup_mut(ident);
+        b.version = Some(Version::Semver(semver::Version::new(0, 0, 0)));
+        b.prefix = Some(RepoPathBuf::new(b""));
+        b.dev_version = Some("0.0.0-dev.0".to_owned());
+        let graph = graph.complete_loading().unwrap();
+        let proj = graph.lookup(ident);
+
+        let released = ReleasedProjectInfo {
+            qnames: proj.qualified_names().clone(),
+            version: "1.2.3".to_owned(),
+            age: 0,
+        };
+        let rc = |bump_spec: &str| RcProjectInfo {
+            qnames: proj.qualified_names().clone(),
+            bump_spec: bump_spec.to_owned(),
+        };
+        let baseline = |rc: Option<&RcProjectInfo>, dev_mode| {
+            baseline_version(proj, Some(&released), rc, dev_mode)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(baseline(None, false), "1.2.3");
+        assert_eq!(baseline(None, true), "0.0.0-dev.0");
+        assert_eq!(baseline(Some(&rc("dev-datecode")), true), "0.0.0-dev.0");
+
+        // The configured `dev_version` doesn't get a `-dev` marker; the last
+        // release does.
+        let post = rc("post-release-dev");
+        assert_eq!(baseline(Some(&post), true), "1.2.3");
+
+        let mut v = baseline_version(proj, Some(&released), Some(&post), true).unwrap();
+        VersionBumpScheme::PostReleaseDev.apply(&mut v).unwrap();
+        assert_eq!(v.to_string(), "1.2.3-dev");
+
+        assert_eq!(
+            baseline_version(proj, None, Some(&post), true)
+                .unwrap()
+                .to_string(),
+            "0.0.0"
+        );
+    }
+
+    #[test]
+    fn version_command() {
+        use crate::{errors::AnnotatedReport, repository::test_util::ScratchRepo};
+
+        let scratch = ScratchRepo::new("version-command");
+        let prefix = RepoPathBuf::new(b"");
+        let mut pconfig = HashMap::new();
+        let template = Version::Semver(semver::Version::new(0, 0, 0));
+        let mut run = |name: &str, command: &str| {
+            pconfig.insert(
+                format!("npm:{}", name),
+                ProjectConfiguration {
+                    version_command: Some(command.to_owned()),
+                    ..Default::default()
+                },
+            );
+
+            version_from_command(
+                scratch.repo(),
+                &pconfig,
+                &[name.to_owned(), "npm".to_owned()],
+                &prefix,
+                &template,
+            )
+        };
+
+        assert_eq!(
+            run("good", "echo 1.2.3").unwrap(),
+            Some(Version::Semver(semver::Version::new(1, 2, 3)))
+        );
+
+        let err = run("failing", "echo oops 1>&2 && exit 3").unwrap_err();
+        assert!(err.to_string().contains("`npm:failing`"), "{}", err);
+        let notes = err.downcast_ref::<AnnotatedReport>().unwrap().notes();
+        assert!(notes.iter().any(|n| n.contains("oops")), "{:?}", notes);
+
+        assert!(run("garbage", "echo not-a-version").is_err());
+    }
+}
@@ -14,6 +14,7 @@ use crate::{
     atry,
     errors::{Error, Result},
     project::DepRequirement,
+    version::Version,
 };
 
 /// The toplevel bootstrap state structure.
@@ -91,13 +92,13 @@ impl Command for BootstrapCommand {
             cfg.repo.upstream_urls = vec![upstream_url];
             let cfg_text = cfg.into_toml()?;
 
-            let mut cfg_path = repo.resolve_config_dir();
+            let cfg_dir = repo.resolve_config_dir();
             atry!(
-                fs::create_dir_all(&cfg_path);
-                ["could not create Cranko configuration directory `{}`", cfg_path.display()]
+                fs::create_dir_all(&cfg_dir);
+                ["could not create Cranko configuration directory `{}`", cfg_dir.display()]
             );
 
-            cfg_path.push("config.toml");
+            let cfg_path = repo.resolve_config_file("config.toml", "cranko.toml");
             info!(
                 "stubbing Cranko configuration file `{}`",
                 cfg_path.display(),
@@ -154,7 +155,7 @@ impl Command for BootstrapCommand {
             let loc_desc = {
                 let p = proj.prefix();
 
-                if p.len() == 0 {
+                if p.is_empty() {
                     "the root directory".to_owned()
                 } else {
                     format!("`{}`", p.escaped())
@@ -182,6 +183,7 @@ impl Command for BootstrapCommand {
 
         let mut bs_cfg = BootstrapConfiguration::default();
         let mut versions = HashMap::new();
+        let mut old_versions = HashMap::new();
 
         for proj in sess.graph_mut().toposorted_mut() {
             bs_cfg.project.push(BootstrapProjectInfo {
@@ -189,8 +191,9 @@ impl Command for BootstrapCommand {
                 version: proj.version.to_string(),
                 release_commit: None,
             });
+            old_versions.insert(proj.ident(), proj.version.clone());
 
-            proj.version.set_to_dev_value();
+            proj.set_to_dev_version();
             versions.insert(proj.ident(), proj.version.clone());
 
             for dep in &mut proj.internal_deps[..] {
@@ -207,8 +210,7 @@ impl Command for BootstrapCommand {
         );
 
         {
-            let mut bs_path = repo.resolve_config_dir();
-            bs_path.push("bootstrap.toml");
+            let bs_path = repo.resolve_config_file("bootstrap.toml", "cranko-bootstrap.toml");
             info!("writing versioning bootstrap file `{}`", bs_path.display());
 
             let mut f = atry!(
@@ -254,8 +256,23 @@ impl Command for BootstrapCommand {
         // have altered `dep.literal`.
 
         for proj in sess.graph_mut().toposorted_mut() {
+            let is_cargo = proj.qualified_names().last().map(|s| s.as_str()) == Some("cargo");
+
             for dep in &mut proj.internal_deps[..] {
-                dep.cranko_requirement = DepRequirement::Manual(dep.literal.clone());
+                let req = if is_cargo {
+                    cargo_bootstrap_requirement(&dep.literal, &old_versions[&dep.ident])
+                } else {
+                    dep.literal.clone()
+                };
+
+                if req != dep.literal {
+                    info!(
+                        "recording requirement `{}` for an internal dependency of `{}` (was `{}`)",
+                        req, proj.user_facing_name, dep.literal
+                    );
+                }
+
+                dep.cranko_requirement = DepRequirement::Manual(req);
             }
         }
 
@@ -274,3 +291,78 @@ impl Command for BootstrapCommand {
         Ok(0)
     }
 }
+
+/// Compute the Cranko requirement to record for a Cargo internal dependency
+/// during bootstrap.
+///
+/// For pre-1.0 crates, semver treats minor versions as incompatible, so that a
+/// requirement like `^0.3` would break as soon as the dependee is released as
+/// 0.4. We broaden such requirements to `>=0.3,<1`, matching what the Cargo
+/// rewriter generates for commit-based requirements. Wildcard requirements,
+/// which are common for path dependencies but can't be published, are
+/// replaced with a requirement based on the dependee's current version.
+fn cargo_bootstrap_requirement(literal: &str, dep_version: &Version) -> String {
+    let literal = literal.trim();
+
+    if literal == "*" || literal == "UNDEFINED" {
+        let v = dep_version.to_string();
+
+        return if v.starts_with("0.") {
+            format!(">={},<1", v)
+        } else {
+            format!("^{}", v)
+        };
+    }
+
+    let bare = literal.strip_prefix('^').unwrap_or(literal);
+
+    if bare.starts_with("0.") && bare.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        format!(">={},<1", bare)
+    } else {
+        literal.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn semver(major: u64, minor: u64, patch: u64) -> Version {
+        Version::Semver(semver::Version::new(major, minor, patch))
+    }
+
+    #[test]
+    fn plain_requirements() {
+        let v = semver(0, 3, 2);
+
+        // Pre-1.0 literals, with or without a caret, are broadened.
+        assert_eq!(cargo_bootstrap_requirement("0.3", &v), ">=0.3,<1");
+        assert_eq!(cargo_bootstrap_requirement("0.3.1", &v), ">=0.3.1,<1");
+        assert_eq!(cargo_bootstrap_requirement("^0.3.1", &v), ">=0.3.1,<1");
+        assert_eq!(cargo_bootstrap_requirement(" 0.3 ", &v), ">=0.3,<1");
+
+        // Post-1.0 literals are already broad enough.
+        assert_eq!(cargo_bootstrap_requirement("1.2", &v), "1.2");
+        assert_eq!(cargo_bootstrap_requirement("^1.2.0", &v), "^1.2.0");
+
+        // Wildcards are based on the dependee's current version.
+        assert_eq!(cargo_bootstrap_requirement("*", &v), ">=0.3.2,<1");
+        assert_eq!(cargo_bootstrap_requirement("UNDEFINED", &v), ">=0.3.2,<1");
+        assert_eq!(cargo_bootstrap_requirement("*", &semver(1, 4, 0)), "^1.4.0");
+    }
+
+    #[test]
+    fn operator_requirements() {
+        let v = semver(0, 3, 2);
+
+        // Anything with other operators is left as the user wrote it.
+        assert_eq!(cargo_bootstrap_requirement("=0.3.1", &v), "=0.3.1");
+        assert_eq!(cargo_bootstrap_requirement("~0.3", &v), "~0.3");
+        assert_eq!(cargo_bootstrap_requirement(">=0.2", &v), ">=0.2");
+        assert_eq!(
+            cargo_bootstrap_requirement(">=0.2, <0.5", &v),
+            ">=0.2, <0.5"
+        );
+        assert_eq!(cargo_bootstrap_requirement("0.3.*", &v), "0.3.*");
+    }
+}
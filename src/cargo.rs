@@ -6,19 +6,21 @@
 //! If we detect a Cargo.toml in the repo root, we use `cargo metadata` to slurp
 //! information about all of the crates and their interdependencies.
 
-use anyhow::{anyhow, Context};
-use cargo_metadata::MetadataCommand;
-use log::{info, warn};
+use anyhow::{anyhow, bail, Context};
+use cargo_metadata::{Metadata, MetadataCommand};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
     ffi::OsString,
-    fs::File,
+    fs::{self, File},
+    hash::{Hash, Hasher},
     io::{BufReader, Read, Write},
     path::{Path, PathBuf},
     process, thread, time,
 };
 use structopt::StructOpt;
-use toml_edit::{Document, Item, Table};
+use toml_edit::{DocumentMut, Item, Table};
 
 use super::Command;
 
@@ -28,9 +30,10 @@ use crate::{
     config::ProjectConfiguration,
     errors::Result,
     graph::GraphQueryBuilder,
-    project::{DepRequirement, DependencyTarget, Project, ProjectId},
+    licenses,
+    project::{DepRequirement, DependencyTarget, ProjectId},
     repository::{ChangeList, RepoPath, RepoPathBuf},
-    rewriters::Rewriter,
+    rewriters::{RewriteContext, Rewriter},
     version::Version,
 };
 
@@ -38,6 +41,23 @@ use crate::{
 #[derive(Debug, Default)]
 pub struct CargoLoader {
     shortest_toml_dirname: Option<RepoPathBuf>,
+
+    /// All of the `Cargo.toml` and `Cargo.lock` files in the repository, used
+    /// to decide whether cached `cargo metadata` output is still valid.
+    cache_inputs: Vec<RepoPathBuf>,
+}
+
+/// The name of the file in which `cargo metadata` output is cached.
+const METADATA_CACHE_FILENAME: &str = "cargo-metadata.json";
+
+/// Cached `cargo metadata` output.
+#[derive(Deserialize, Serialize)]
+struct CachedMetadata {
+    /// A hash of the inputs that might affect the metadata.
+    key: String,
+
+    /// The metadata.
+    metadata: Metadata,
 }
 
 impl CargoLoader {
@@ -48,10 +68,21 @@ impl CargoLoader {
     /// structure that doesn't have a single toplevel Cargo.toml (either a
     /// workspace, or a single project), we'll have trouble with that.
     pub fn process_index_item(&mut self, dirname: &RepoPath, basename: &RepoPath) {
+        if basename.as_ref() == b"Cargo.lock" {
+            let mut p = dirname.to_owned();
+            p.push(basename);
+            self.cache_inputs.push(p);
+            return;
+        }
+
         if basename.as_ref() != b"Cargo.toml" {
             return;
         }
 
+        let mut p = dirname.to_owned();
+        p.push(basename);
+        self.cache_inputs.push(p);
+
         if let Some(ref mut prev) = self.shortest_toml_dirname {
             // Find the longest common prefix of the two dirnames.
             let bytes0: &[u8] = prev.as_ref();
@@ -83,13 +114,10 @@ impl CargoLoader {
 
         let mut toml_path = app.repo.resolve_workdir(&shortest_toml_dirname);
         toml_path.push("Cargo.toml");
-        let mut cmd = MetadataCommand::new();
-        cmd.manifest_path(&toml_path);
-        cmd.features(cargo_metadata::CargoOpt::AllFeatures);
-        let cargo_meta = atry!(
-            cmd.exec();
-            ["failed to fetch Cargo metadata using the `cargo metadata` command"]
-        );
+        let cargo_meta = load_metadata(app, &toml_path, &self.cache_inputs)?;
+        let workspace_toml_path = app
+            .repo
+            .convert_path(cargo_meta.workspace_root.join("Cargo.toml"))?;
 
         // Fill in the packages
 
@@ -111,11 +139,13 @@ impl CargoLoader {
 
                 // Q: should we include a registry name as a qualifier?
                 proj.version = Some(Version::Semver(pkg.version.clone()));
+                proj.license = pkg.license.clone();
                 proj.prefix = Some(prefix.to_owned());
                 cargo_to_graph.insert(pkg.id.clone(), ident);
 
                 // Auto-register a rewriter to update this package's Cargo.toml.
-                let cargo_rewrite = CargoRewriter::new(ident, manifest_repopath);
+                let cargo_rewrite =
+                    CargoRewriter::new(ident, manifest_repopath, workspace_toml_path.clone());
                 proj.rewriters.push(Box::new(cargo_rewrite));
             }
         }
@@ -201,37 +231,149 @@ impl CargoLoader {
     }
 }
 
+/// Get the `cargo metadata` output for the workspace rooted at *toml_path*.
+///
+/// Running `cargo metadata` can be slow in large workspaces, so we cache its
+/// output inside the Git directory, keyed by a hash of the contents of all of
+/// the Cargo manifests and lockfiles in the repository. The cache can be
+/// disabled with [`AppBuilder::no_cache`].
+fn load_metadata(
+    app: &AppBuilder,
+    toml_path: &Path,
+    cache_inputs: &[RepoPathBuf],
+) -> Result<Metadata> {
+    let use_cache = app.use_cache();
+    let mut cache_path = app.repo.resolve_cache_dir();
+    cache_path.push(METADATA_CACHE_FILENAME);
+
+    // If any of the inputs can't be read, don't try to use the cache at all.
+    // `cargo metadata` will presumably give a better error message.
+
+    let key = if use_cache {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        toml_path.hash(&mut hasher);
+
+        let mut inputs: Vec<&[u8]> = cache_inputs.iter().map(|p| p.as_ref()).collect();
+        inputs.sort();
+
+        inputs
+            .iter()
+            .map(|p| {
+                p.hash(&mut hasher);
+                fs::read(app.repo.resolve_workdir(p.as_ref())).map(|d| d.hash(&mut hasher))
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+            .ok()
+            .map(|_| format!("{:016x}", hasher.finish()))
+    } else {
+        None
+    };
+
+    if let Some(ref key) = key {
+        let cached = fs::read(&cache_path)
+            .ok()
+            .and_then(|d| serde_json::from_slice::<CachedMetadata>(&d).ok());
+
+        if let Some(cached) = cached {
+            if &cached.key == key {
+                return Ok(cached.metadata);
+            }
+        }
+    }
+
+    let mut cmd = MetadataCommand::new();
+    cmd.manifest_path(toml_path);
+    cmd.features(cargo_metadata::CargoOpt::AllFeatures);
+    let r = cmd.exec();
+    let cargo_missing = matches!(
+        r,
+        Err(cargo_metadata::Error::Io(ref e)) if e.kind() == std::io::ErrorKind::NotFound
+    );
+
+    let metadata = if cargo_missing {
+        atry!(
+            r;
+            ["could not run the `cargo` program, which is needed to load the Cargo project(s) in this repository"]
+            (note "install Cargo, or make sure that it is on your $PATH")
+            (note "to ignore Cargo projects instead, set `enabled = false` in the `[cargo]` section of the Cranko configuration file")
+        )
+    } else {
+        atry!(
+            r;
+            ["failed to fetch Cargo metadata using the `cargo metadata` command"]
+        )
+    };
+
+    if let Some(key) = key {
+        let cached = CachedMetadata { key, metadata };
+
+        // Failure to save the cache shouldn't be fatal.
+        let r = fs::create_dir_all(app.repo.resolve_cache_dir())
+            .map_err(|e| e.into())
+            .and_then(|_| serde_json::to_vec(&cached).map_err(|e| e.into()))
+            .and_then(|d| fs::write(&cache_path, d).map_err(anyhow::Error::from));
+
+        if let Err(e) = r {
+            warn!(
+                "failed to save cached Cargo metadata to `{}`: {}",
+                cache_path.display(),
+                e
+            );
+        }
+
+        return Ok(cached.metadata);
+    }
+
+    Ok(metadata)
+}
+
 /// Rewrite Cargo.toml to include real version numbers.
 #[derive(Debug)]
 pub struct CargoRewriter {
     proj_id: ProjectId,
     toml_path: RepoPathBuf,
+    workspace_toml_path: RepoPathBuf,
 }
 
 impl CargoRewriter {
     /// Create a new Cargo.toml rewriter.
-    pub fn new(proj_id: ProjectId, toml_path: RepoPathBuf) -> Self {
-        CargoRewriter { proj_id, toml_path }
+    ///
+    /// The *workspace_toml_path* is the path to the root manifest of the
+    /// workspace containing the project. Internal dependencies that inherit
+    /// their specifications from the workspace (`workspace = true`) have
+    /// their versions rewritten in its `[workspace.dependencies]` table.
+    pub fn new(
+        proj_id: ProjectId,
+        toml_path: RepoPathBuf,
+        workspace_toml_path: RepoPathBuf,
+    ) -> Self {
+        CargoRewriter {
+            proj_id,
+            toml_path,
+            workspace_toml_path,
+        }
     }
 }
 
 impl Rewriter for CargoRewriter {
-    fn rewrite(&self, app: &AppSession, changes: &mut ChangeList) -> Result<()> {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
         // Parse the current Cargo.toml using toml_edit so we can rewrite it
         // with minimal deltas.
-        let toml_path = app.repo.resolve_workdir(&self.toml_path);
+        let toml_path = ctx.resolve_workdir(&self.toml_path);
+        let toml_lock = ctx.lock_path(&self.toml_path);
         let mut s = String::new();
         {
             let mut f = File::open(&toml_path)?;
             f.read_to_string(&mut s)?;
         }
-        let mut doc: Document = s.parse()?;
+        let mut doc: DocumentMut = s.parse()?;
 
         // Helper table for applying internal deps. Note that we use the 0'th
         // qname, not the user-facing name, since that is what is used in
         // Cargo-land.
 
-        let proj = app.graph().lookup(self.proj_id);
+        let proj = ctx.graph().lookup(self.proj_id);
         let mut internal_reqs = HashMap::new();
 
         for dep in &proj.internal_deps[..] {
@@ -259,13 +401,15 @@ impl Rewriter for CargoRewriter {
             };
 
             internal_reqs.insert(
-                app.graph().lookup(dep.ident).qualified_names()[0].clone(),
+                ctx.graph().lookup(dep.ident).qualified_names()[0].clone(),
                 req_text,
             );
         }
 
         // Update the project version
 
+        let mut inherited = HashSet::new();
+
         {
             let ct_root = doc.as_table_mut();
             let ct_package = ct_root
@@ -281,7 +425,7 @@ impl Rewriter for CargoRewriter {
 
             for tblname in &["dependencies", "dev-dependencies", "build-dependencies"] {
                 if let Some(tbl) = ct_root.get_mut(tblname).and_then(|i| i.as_table_mut()) {
-                    rewrite_deptable(&internal_reqs, tbl)?;
+                    rewrite_deptable(&internal_reqs, tbl, &mut inherited)?;
                 }
             }
 
@@ -298,15 +442,30 @@ impl Rewriter for CargoRewriter {
                         .get_mut(target_spec)
                         .and_then(|i| i.as_table_mut())
                     {
-                        rewrite_deptable(&internal_reqs, tbl)?;
+                        rewrite_deptable(&internal_reqs, tbl, &mut inherited)?;
                     }
                 }
             }
         }
 
+        // Internal dependencies that inherit from the workspace need to have
+        // their versions updated in the root manifest. If that's the same
+        // file as ours, we can update our document directly.
+
+        let inherited_reqs = internal_reqs
+            .iter()
+            .filter(|(k, _v)| inherited.contains(*k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<HashMap<_, _>>();
+
+        if !inherited_reqs.is_empty() && self.workspace_toml_path == self.toml_path {
+            rewrite_workspace_deps(&inherited_reqs, &mut doc, &self.toml_path)?;
+        }
+
         fn rewrite_deptable(
             internal_reqs: &HashMap<String, String>,
             tbl: &mut toml_edit::Table,
+            inherited: &mut HashSet<String>,
         ) -> Result<()> {
             let deps = tbl.iter().map(|(k, _v)| k.to_owned()).collect::<Vec<_>>();
 
@@ -315,7 +474,17 @@ impl Rewriter for CargoRewriter {
                 // from cargo-metadata when we load everything.
 
                 if let Some(req_text) = internal_reqs.get(dep) {
-                    if let Some(dep_tbl) = tbl.get_mut(dep).and_then(|i| i.as_table_mut()) {
+                    let is_inherited = tbl
+                        .get(dep)
+                        .and_then(|i| i.get("workspace"))
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false);
+
+                    if is_inherited {
+                        // The version lives in the workspace root manifest;
+                        // we must not add one here, since Cargo rejects that.
+                        inherited.insert(dep.clone());
+                    } else if let Some(dep_tbl) = tbl.get_mut(dep).and_then(|i| i.as_table_mut()) {
                         dep_tbl["version"] = toml_edit::value(req_text.clone());
                     } else if let Some(dep_tbl) =
                         tbl.get_mut(dep).and_then(|i| i.as_inline_table_mut())
@@ -338,6 +507,53 @@ impl Rewriter for CargoRewriter {
             Ok(())
         }
 
+        fn rewrite_workspace_deps(
+            inherited_reqs: &HashMap<String, String>,
+            doc: &mut DocumentMut,
+            toml_path: &RepoPath,
+        ) -> Result<()> {
+            let tbl = doc
+                .get_mut("workspace")
+                .and_then(|i| i.get_mut("dependencies"))
+                .and_then(|i| i.as_table_like_mut())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no [workspace.dependencies] section in {}, but members inherit from it",
+                        toml_path.escaped()
+                    )
+                })?;
+
+            for (dep, req_text) in inherited_reqs {
+                match tbl.get_mut(dep) {
+                    Some(Item::Value(toml_edit::Value::String(_))) => {
+                        tbl.insert(dep, toml_edit::value(req_text.clone()));
+                    }
+
+                    Some(item) => {
+                        if let Some(dep_tbl) = item.as_table_like_mut() {
+                            dep_tbl.insert("version", toml_edit::value(req_text.clone()));
+                        } else {
+                            return Err(anyhow!(
+                                "unexpected workspace dependency item in {}: {:?}",
+                                toml_path.escaped(),
+                                item
+                            ));
+                        }
+                    }
+
+                    None => {
+                        return Err(anyhow!(
+                            "internal dependency `{}` inherits from the workspace, but is not listed in [workspace.dependencies] of {}",
+                            dep,
+                            toml_path.escaped()
+                        ));
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
         // Rewrite.
 
         {
@@ -346,6 +562,28 @@ impl Rewriter for CargoRewriter {
             changes.add_path(&self.toml_path);
         }
 
+        drop(toml_lock);
+
+        // If needed, update the workspace root manifest too. Multiple
+        // projects may do this; they should all agree on the requirements,
+        // since these are derived from the dependees' new versions.
+
+        if !inherited_reqs.is_empty() && self.workspace_toml_path != self.toml_path {
+            let ws_path = ctx.resolve_workdir(&self.workspace_toml_path);
+            let _ws_lock = ctx.lock_path(&self.workspace_toml_path);
+            let mut s = String::new();
+            {
+                let mut f = File::open(&ws_path)?;
+                f.read_to_string(&mut s)?;
+            }
+            let mut ws_doc: DocumentMut = s.parse()?;
+            rewrite_workspace_deps(&inherited_reqs, &mut ws_doc, &self.workspace_toml_path)?;
+
+            let mut f = File::create(&ws_path)?;
+            write!(f, "{}", ws_doc)?;
+            changes.add_path(&self.workspace_toml_path);
+        }
+
         Ok(())
     }
 
@@ -371,7 +609,7 @@ impl Rewriter for CargoRewriter {
             let mut f = File::open(&toml_path)?;
             f.read_to_string(&mut s)?;
         }
-        let mut doc: Document = s.parse()?;
+        let mut doc: DocumentMut = s.parse()?;
 
         // Modify.
 
@@ -435,6 +673,10 @@ impl Rewriter for CargoRewriter {
 /// Cargo-specific CLI utilities.
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub enum CargoCommands {
+    #[structopt(name = "check-licenses")]
+    /// Check Cargo projects' licenses against those of their internal dependencies.
+    CheckLicenses(CheckLicensesCommand),
+
     #[structopt(name = "foreach-released")]
     /// Run a "cargo" command for each released Cargo project.
     ForeachReleased(ForeachReleasedCommand),
@@ -442,6 +684,10 @@ pub enum CargoCommands {
     #[structopt(name = "package-released-binaries")]
     /// Archive the executables associated with released Cargo projects.
     PackageReleasedBinaries(PackageReleasedBinariesCommand),
+
+    #[structopt(name = "wait-for-crates-io")]
+    /// Wait until released Cargo projects are available on Crates.io.
+    WaitForCratesIo(WaitForCratesIoCommand),
 }
 
 #[derive(Debug, Eq, PartialEq, StructOpt)]
@@ -453,8 +699,40 @@ pub struct CargoCommand {
 impl Command for CargoCommand {
     fn execute(self) -> Result<i32> {
         match self.command {
+            CargoCommands::CheckLicenses(o) => o.execute(),
             CargoCommands::ForeachReleased(o) => o.execute(),
             CargoCommands::PackageReleasedBinaries(o) => o.execute(),
+            CargoCommands::WaitForCratesIo(o) => o.execute(),
+        }
+    }
+}
+
+/// `cranko cargo check-licenses`
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+pub struct CheckLicensesCommand {
+    #[structopt(help = "Names of the projects to check (default: all Cargo projects)")]
+    proj_names: Vec<String>,
+}
+
+impl Command for CheckLicensesCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = AppSession::initialize_default()?;
+
+        let mut q = GraphQueryBuilder::default();
+        q.names(self.proj_names);
+        q.only_project_type("cargo");
+        let idents = sess
+            .graph()
+            .query(q)
+            .context("could not select projects for cargo check-licenses")?;
+
+        let n = licenses::check_licenses(&sess, &idents);
+
+        if n == 0 {
+            Ok(0)
+        } else {
+            error!("found {} license conflict(s)", n);
+            Ok(1)
         }
     }
 }
@@ -476,6 +754,13 @@ pub struct ForeachReleasedCommand {
     )]
     pause: u64,
 
+    #[structopt(
+        long = "exclude",
+        help = "Skip the named project(s), even if released",
+        number_of_values = 1
+    )]
+    exclude: Vec<String>,
+
     #[structopt(help = "Arguments to the `cargo` command", required = true)]
     cargo_args: Vec<OsString>,
 }
@@ -492,6 +777,7 @@ impl Command for ForeachReleasedCommand {
         let mut q = GraphQueryBuilder::default();
         q.only_new_releases(rel_info);
         q.only_project_type("cargo");
+        q.exclude_names(self.exclude);
         let idents = sess
             .graph()
             .query(q)
@@ -561,6 +847,18 @@ pub struct PackageReleasedBinariesCommand {
     #[structopt(short = "t", long = "target", help = "The binaries' target platform")]
     target: String,
 
+    #[structopt(
+        long = "per-binary",
+        help = "Create a separate archive for each executable, rather than one per crate"
+    )]
+    per_binary: bool,
+
+    #[structopt(
+        long = "checksums",
+        help = "Write SHA256 checksums of the archives into the destination directory"
+    )]
+    checksums: bool,
+
     #[structopt(
         help = "The directory into which the archive files should be placed",
         required = true
@@ -594,6 +892,7 @@ impl Command for PackageReleasedBinariesCommand {
         q.only_new_releases(rel_info);
         q.only_project_type("cargo");
         let idents = sess.graph().query(q).context("could not select projects")?;
+        let mut archives = Vec::new();
 
         for ident in &idents {
             let proj = sess.graph().lookup(*ident);
@@ -625,13 +924,14 @@ impl Command for PackageReleasedBinariesCommand {
 
                     Message::CompilerArtifact(artifact) => {
                         if let Some(p) = artifact.executable {
-                            binaries.push(if let Some(ref root) = self.reroot {
+                            let path = if let Some(ref root) = self.reroot {
                                 let mut prefixed = root.clone();
                                 prefixed.push(p);
                                 PathBuf::from(prefixed)
                             } else {
                                 p.into_std_path_buf()
-                            });
+                            };
+                            binaries.push((artifact.target.name, path));
                         }
                     }
 
@@ -653,54 +953,123 @@ impl Command for PackageReleasedBinariesCommand {
                 continue;
             }
 
-            let archive_path = mode
-                .archive_binaries(proj, &self.dest_dir, &binaries, &target)
-                .context("couldn't create archive")?;
-            info!(
-                "`{}` => {} ({} files)",
-                proj.user_facing_name,
-                archive_path.display(),
-                binaries.len(),
-            );
+            let crate_name = &proj.qualified_names()[0];
+
+            if self.per_binary {
+                for (bin_name, bin) in &binaries {
+                    let stem = format!("{}-{}-{}-{}", crate_name, bin_name, proj.version, target);
+                    let archive_path = mode
+                        .archive_binaries(&self.dest_dir, &stem, std::slice::from_ref(bin))
+                        .context("couldn't create archive")?;
+                    info!(
+                        "`{}` ({}) => {}",
+                        proj.user_facing_name,
+                        bin_name,
+                        archive_path.display(),
+                    );
+                    archives.push(archive_path);
+                }
+            } else {
+                let stem = format!("{}-{}-{}", crate_name, proj.version, target);
+                let paths: Vec<_> = binaries.into_iter().map(|(_, p)| p).collect();
+                let archive_path = mode
+                    .archive_binaries(&self.dest_dir, &stem, &paths)
+                    .context("couldn't create archive")?;
+                info!(
+                    "`{}` => {} ({} files)",
+                    proj.user_facing_name,
+                    archive_path.display(),
+                    paths.len(),
+                );
+                archives.push(archive_path);
+            }
+        }
+
+        if self.checksums && !archives.is_empty() {
+            write_checksums(&self.dest_dir, &archives)?;
         }
 
         Ok(0)
     }
 }
 
+/// Write SHA256 checksums of the *archives*, in the format used by
+/// `sha256sum`. Each archive gets a `.sha256` file alongside it, and all of
+/// them are appended to the file `SHA256SUMS` in *dest_dir*, so that multiple
+/// runs with the same destination (e.g., for different targets) accumulate.
+fn write_checksums(dest_dir: &Path, archives: &[PathBuf]) -> Result<()> {
+    use sha2::{Digest, Sha256};
+    use std::{fmt::Write as _, fs::OpenOptions, io::Write as _};
+
+    let mut sums_path = dest_dir.to_path_buf();
+    sums_path.push("SHA256SUMS");
+
+    let mut sums_file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&sums_path)
+        .with_context(|| format!("failed to open checksum file `{}`", sums_path.display()))?;
+
+    for archive in archives {
+        // Stream the data through the hasher so that big archives don't need
+        // to be loaded into memory.
+        let mut hasher = Sha256::new();
+        let mut file = File::open(archive)
+            .with_context(|| format!("failed to open archive `{}`", archive.display()))?;
+        std::io::copy(&mut file, &mut hasher)
+            .with_context(|| format!("failed to read archive `{}`", archive.display()))?;
+
+        let mut hex = String::new();
+        for byte in hasher.finalize() {
+            write!(hex, "{:02x}", byte).unwrap();
+        }
+
+        let name = archive.file_name().unwrap().to_string_lossy();
+        let line = format!("{}  {}\n", hex, name);
+
+        let mut sum_path = archive.clone().into_os_string();
+        sum_path.push(".sha256");
+        let sum_path = PathBuf::from(sum_path);
+
+        atry!(
+            std::fs::write(&sum_path, &line);
+            ["failed to write checksum file `{}`", sum_path.display()]
+        );
+
+        atry!(
+            sums_file.write_all(line.as_bytes());
+            ["failed to write checksum file `{}`", sums_path.display()]
+        );
+
+        info!("{}: sha256 {}", name, hex);
+    }
+
+    Ok(())
+}
+
 enum BinaryArchiveMode {
     Tarball,
     Zipball,
 }
 
 impl BinaryArchiveMode {
+    /// Archive the *binaries* into a file in *dest_dir* whose name is *stem*
+    /// plus the appropriate extension.
     fn archive_binaries(
         &self,
-        proj: &Project,
         dest_dir: &Path,
+        stem: &str,
         binaries: &[PathBuf],
-        target: &target_lexicon::Triple,
     ) -> Result<PathBuf> {
         match self {
-            BinaryArchiveMode::Tarball => self.tarball(proj, dest_dir, binaries, target),
-            BinaryArchiveMode::Zipball => self.zipball(proj, dest_dir, binaries, target),
+            BinaryArchiveMode::Tarball => self.tarball(dest_dir, stem, binaries),
+            BinaryArchiveMode::Zipball => self.zipball(dest_dir, stem, binaries),
         }
     }
 
-    fn zipball(
-        &self,
-        proj: &Project,
-        dest_dir: &Path,
-        binaries: &[PathBuf],
-        target: &target_lexicon::Triple,
-    ) -> Result<PathBuf> {
+    fn zipball(&self, dest_dir: &Path, stem: &str, binaries: &[PathBuf]) -> Result<PathBuf> {
         let mut path = dest_dir.to_path_buf();
-        path.push(format!(
-            "{}-{}-{}.zip",
-            proj.qualified_names()[0],
-            proj.version,
-            target
-        ));
+        path.push(format!("{}.zip", stem));
 
         let out_file = File::create(&path)
             .with_context(|| format!("failed to create Zip file `{}`", path.display()))?;
@@ -744,23 +1113,12 @@ impl BinaryArchiveMode {
         Ok(path)
     }
 
-    fn tarball(
-        &self,
-        proj: &Project,
-        dest_dir: &Path,
-        binaries: &[PathBuf],
-        target: &target_lexicon::Triple,
-    ) -> Result<PathBuf> {
+    fn tarball(&self, dest_dir: &Path, stem: &str, binaries: &[PathBuf]) -> Result<PathBuf> {
         use flate2::write::GzEncoder;
         use flate2::Compression;
 
         let mut path = dest_dir.to_path_buf();
-        path.push(format!(
-            "{}-{}-{}.tar.gz",
-            proj.qualified_names()[0],
-            proj.version,
-            target
-        ));
+        path.push(format!("{}.tar.gz", stem));
 
         let file = File::create(&path)
             .with_context(|| format!("failed to create tar file `{}`", path.display()))?;
@@ -801,3 +1159,134 @@ impl From<&target_lexicon::Triple> for BinaryArchiveMode {
         }
     }
 }
+
+/// `cranko cargo wait-for-crates-io`
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+pub struct WaitForCratesIoCommand {
+    #[structopt(
+        long = "timeout",
+        help = "Give up after this many seconds",
+        default_value = "600"
+    )]
+    timeout: u64,
+
+    #[structopt(
+        long = "interval",
+        help = "Wait this many seconds between checks",
+        default_value = "10"
+    )]
+    interval: u64,
+
+    #[structopt(
+        long = "exclude",
+        help = "Don't wait for the named project(s), even if released",
+        number_of_values = 1
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(help = "Name(s) of the project(s) to wait for (default: all released)")]
+    proj_names: Vec<String>,
+}
+
+impl WaitForCratesIoCommand {
+    /// Check whether the Crates.io index lists the specified version of a
+    /// crate. We consult the "sparse" index, since that is what Cargo uses to
+    /// resolve dependencies.
+    fn is_available(client: &reqwest::blocking::Client, name: &str, version: &str) -> Result<bool> {
+        let lname = name.to_lowercase();
+
+        let index_path = match lname.len() {
+            1 => format!("1/{}", lname),
+            2 => format!("2/{}", lname),
+            3 => format!("3/{}/{}", &lname[..1], lname),
+            _ => format!("{}/{}/{}", &lname[..2], &lname[2..4], lname),
+        };
+
+        let resp = client
+            .get(format!("https://index.crates.io/{}", index_path))
+            .send()?;
+
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        let text = resp.error_for_status()?.text()?;
+
+        for line in text.lines() {
+            let entry: serde_json::Value = serde_json::from_str(line)?;
+
+            if entry.get("vers").and_then(|v| v.as_str()) == Some(version) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl Command for WaitForCratesIoCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = AppSession::initialize_default()?;
+
+        let (dev_mode, rel_info) = sess.ensure_ci_release_mode()?;
+        if dev_mode {
+            warn!("nothing should have been published in dev mode; not waiting");
+            return Ok(0);
+        }
+
+        let mut q = GraphQueryBuilder::default();
+        q.names(self.proj_names);
+        q.only_new_releases(rel_info.clone());
+        q.only_project_type("cargo");
+        q.exclude_names(self.exclude);
+        let idents = sess
+            .graph()
+            .query(q)
+            .context("could not select projects for cargo wait-for-crates-io")?;
+
+        let client = reqwest::blocking::Client::builder()
+            .user_agent("cranko")
+            .timeout(time::Duration::from_secs(30))
+            .build()?;
+        let interval = time::Duration::from_secs(self.interval);
+        let deadline = time::Instant::now() + time::Duration::from_secs(self.timeout);
+
+        for ident in &idents {
+            let proj = sess.graph().lookup(*ident);
+            let name = &proj.qualified_names()[0];
+            let version = &rel_info.lookup_if_released(proj).unwrap().version;
+
+            loop {
+                match Self::is_available(&client, name, version) {
+                    Ok(true) => {
+                        info!("{} {} is available on Crates.io", name, version);
+                        break;
+                    }
+
+                    Ok(false) => {}
+
+                    Err(e) => warn!(
+                        "error checking Crates.io for {} {} (will retry): {}",
+                        name, version, e
+                    ),
+                }
+
+                if time::Instant::now() + interval > deadline {
+                    bail!(
+                        "timed out waiting for {} {} to become available on Crates.io",
+                        name,
+                        version
+                    );
+                }
+
+                info!(
+                    "{} {} is not yet available on Crates.io; waiting {} seconds",
+                    name, version, self.interval
+                );
+                thread::sleep(interval);
+            }
+        }
+
+        Ok(0)
+    }
+}
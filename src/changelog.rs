@@ -8,35 +8,51 @@
 //! has to do with looking at the repository history since the most recent
 //! release(s). That's exactly the information contained in a release changelog.
 
+use anyhow::bail;
 use chrono::{offset::Local, Datelike};
 use dynfmt::{Format, SimpleCurlyFormat};
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
 use std::{
     collections::HashMap,
-    fs::File,
+    fs::{self, File},
     io::{prelude::*, BufReader, Cursor},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use thiserror::Error as ThisError;
 
 use crate::{
+    a_ok_or,
     app::AppSession,
+    atry,
     errors::{Error, Result},
+    line_endings::{LineEnding, LineEndingWriter},
     project::Project,
-    repository::{ChangeList, CommitId, PathMatcher, RcProjectInfo, RepoPathBuf, Repository},
-    write_crlf,
+    repository::{
+        ChangeList, CommitId, PathMatcher, RcProjectInfo, RepoPath, RepoPathBuf, Repository,
+    },
 };
 
 /// A type that defines how the changelog for a given project is managed.
-pub trait Changelog: std::fmt::Debug {
+pub trait Changelog: std::fmt::Debug + Send + Sync {
     /// Rewrite the changelog file(s) with stub contents derived from the
     /// repository history, prepended to whatever contents existed at the
     /// previous release commit.
+    ///
+    /// If *bump_spec* is provided, the draft should request that version bump
+    /// rather than one chosen by the changelog format. The caller is
+    /// responsible for validating it.
+    ///
+    /// If the changelog file doesn't exist yet, it is created, along with any
+    /// missing directories.
     fn draft_release_update(
         &self,
         proj: &Project,
         sess: &AppSession,
         changes: &[CommitId],
         prev_release_commit: Option<CommitId>,
+        bump_spec: Option<&str>,
     ) -> Result<()>;
 
     /// Replace the changelog file(s) in the project's working directory with
@@ -79,13 +95,205 @@ pub trait Changelog: std::fmt::Debug {
     /// Note that this operation ignores the working tree in an effort to provide
     /// more reliability.
     fn scan_changelog(&self, proj: &Project, repo: &Repository, cid: &CommitId) -> Result<String>;
+
+    /// Render the stub release notes that [`Changelog::draft_release_update`]
+    /// would add to the changelog for the specified commits, without modifying
+    /// any files.
+    fn render_draft(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        changes: &[CommitId],
+    ) -> Result<String>;
+
+    /// Read the pending release notes from the changelog(s) in the project's
+    /// working directory, which should be in the "rc" format produced by
+    /// `cranko stage`.
+    fn scan_staged_notes(&self, proj: &Project, repo: &Repository) -> Result<String>;
+}
+
+/// Create a Changelog implementation from the project configuration.
+///
+/// The *format* is the name of the changelog format, as specified in the
+/// `changelog_format` project configuration key, and the *path* is the
+/// location of the changelog file relative to the project directory, as
+/// specified in the `changelog_path` key. The *fragments_dir* is the
+/// `changelog_fragments_dir` key, which is only meaningful for the
+/// `fragments` format, and *infer_bump* is the `infer_bump` key, which is only
+/// meaningful for the `cranko` format.
+pub fn from_config(
+    format: Option<&str>,
+    path: Option<&str>,
+    fragments_dir: Option<&str>,
+    infer_bump: Option<&str>,
+) -> Result<Box<dyn Changelog>> {
+    let path = match path {
+        Some(p) => {
+            check_changelog_path("changelog path", p)?;
+            p.to_owned()
+        }
+        None => DEFAULT_CHANGELOG_PATH.to_owned(),
+    };
+
+    let format = format.unwrap_or("cranko");
+
+    if fragments_dir.is_some() && format != "fragments" {
+        bail!(
+            "the `changelog_fragments_dir` setting requires the `fragments` changelog format, not `{}`",
+            format
+        );
+    }
+
+    let infer_conventional = match infer_bump {
+        None => false,
+        Some("conventional") => true,
+        Some(other) => bail!(
+            "unrecognized `infer_bump` mode `{}` (expected `conventional`)",
+            other
+        ),
+    };
+
+    if infer_conventional && format != "cranko" {
+        bail!(
+            "the `infer_bump` setting requires the `cranko` changelog format, not `{}`",
+            format
+        );
+    }
+
+    match format {
+        "cranko" => Ok(Box::new(MarkdownChangelog {
+            path,
+            infer_conventional,
+            ..MarkdownChangelog::default()
+        })),
+        "keepachangelog" => Ok(Box::new(KeepAChangelog { path })),
+        "fragments" => {
+            let fragments_dir = match fragments_dir {
+                Some(d) => {
+                    check_changelog_path("fragments directory", d)?;
+                    d.to_owned()
+                }
+                None => DEFAULT_FRAGMENTS_DIR.to_owned(),
+            };
+
+            Ok(Box::new(FragmentsChangelog {
+                markdown: MarkdownChangelog {
+                    path,
+                    ..MarkdownChangelog::default()
+                },
+                fragments_dir,
+            }))
+        }
+        other => bail!(
+            "unrecognized changelog format `{}` (expected `cranko`, `keepachangelog`, or `fragments`)",
+            other
+        ),
+    }
+}
+
+/// The default location of a project's changelog, relative to its directory.
+const DEFAULT_CHANGELOG_PATH: &str = "CHANGELOG.md";
+
+/// The default location of a project's news fragments, relative to its
+/// directory.
+const DEFAULT_FRAGMENTS_DIR: &str = "newsfragments";
+
+/// Check that a configured changelog path is a relative path that stays
+/// within the project directory. The *what* describes the path in error
+/// messages.
+fn check_changelog_path(what: &str, path: &str) -> Result<()> {
+    if path.is_empty() {
+        bail!("the {} may not be empty", what);
+    }
+
+    if path.starts_with('/') || path.contains('\\') || path.contains(':') {
+        bail!(
+            "the {} `{}` must be a relative path using `/` as a separator",
+            what,
+            path
+        );
+    }
+
+    if path
+        .split('/')
+        .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        bail!(
+            "the {} `{}` must be a simple path inside the project directory",
+            what,
+            path
+        );
+    }
+
+    Ok(())
+}
+
+/// If a changelog file that's about to be drafted doesn't exist yet, make sure
+/// that its directory does, so that projects without changelogs can be staged.
+fn prepare_new_changelog(repopath: &RepoPath, path: &Path) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        atry!(
+            fs::create_dir_all(parent);
+            ["failed to create the directory `{}`", parent.display()]
+        );
+    }
+
+    info!("creating new changelog file `{}`", repopath.escaped());
+    Ok(())
 }
 
-/// Create a new default Changelog implementation.
+/// Infer a bump spec from the Conventional Commits messages of a set of
+/// commits.
 ///
-/// This uses the Markdown format.
-pub fn default() -> Box<dyn Changelog> {
-    Box::<MarkdownChangelog>::default()
+/// See <https://www.conventionalcommits.org/>. A breaking change, indicated by
+/// a `!` after the commit type/scope or a `BREAKING CHANGE:` footer, implies a
+/// major bump; a `feat` commit implies a minor bump; anything else implies a
+/// micro bump.
+fn infer_conventional_bump(repo: &Repository, commits: &[CommitId]) -> Result<&'static str> {
+    let mut level = 0;
+
+    for cid in commits {
+        level = level.max(conventional_bump_level(&repo.get_commit_message(*cid)?));
+
+        if level == 2 {
+            break;
+        }
+    }
+
+    Ok(["micro bump", "minor bump", "major bump"][level])
+}
+
+/// Get the bump level implied by a single commit message: 0 for micro, 1 for
+/// minor, and 2 for major.
+///
+/// The summary only counts as a Conventional Commit if it starts with a
+/// `type`, an optional `(scope)`, an optional `!`, a colon, and a space, so
+/// that a summary like `Fix the foo: really` isn't mistaken for one.
+fn conventional_bump_level(message: &str) -> usize {
+    lazy_static! {
+        static ref PREFIX_RE: Regex = Regex::new(r"^([A-Za-z]+)(\([^()\r\n]*\))?(!)?: ").unwrap();
+    }
+
+    let summary = message.lines().next().unwrap_or("");
+    let caps = PREFIX_RE.captures(summary);
+
+    let breaking = caps.as_ref().is_some_and(|c| c.get(3).is_some())
+        || message
+            .lines()
+            .skip(1)
+            .any(|l| l.starts_with("BREAKING CHANGE:") || l.starts_with("BREAKING-CHANGE:"));
+
+    if breaking {
+        2
+    } else if caps.is_some_and(|c| c[1].eq_ignore_ascii_case("feat")) {
+        1
+    } else {
+        0
+    }
 }
 
 /// An error returned when a changelog file does not obey the special structure
@@ -108,7 +316,13 @@ impl std::fmt::Display for InvalidChangelogFormatError {
 /// Settings for Markdown-formatted changelogs.
 #[derive(Debug)]
 pub struct MarkdownChangelog {
-    basename: String,
+    /// The path of the changelog file, relative to the project directory.
+    path: String,
+
+    /// Whether to infer the drafted bump spec from Conventional Commits
+    /// messages, rather than always drafting a micro bump.
+    infer_conventional: bool,
+
     release_header_format: String,
     stage_header_format: String,
     footer_format: String,
@@ -117,7 +331,8 @@ pub struct MarkdownChangelog {
 impl Default for MarkdownChangelog {
     fn default() -> Self {
         MarkdownChangelog {
-            basename: "CHANGELOG.md".to_owned(),
+            path: DEFAULT_CHANGELOG_PATH.to_owned(),
+            infer_conventional: false,
             release_header_format: "# {project_slug} {version} ({yyyy_mm_dd})\n".to_owned(),
             stage_header_format: "# rc: {bump_spec}\n".to_owned(),
             footer_format: "".to_owned(),
@@ -128,7 +343,7 @@ impl Default for MarkdownChangelog {
 impl MarkdownChangelog {
     fn changelog_repopath(&self, proj: &Project) -> RepoPathBuf {
         let mut pfx = proj.prefix().to_owned();
-        pfx.push(&self.basename);
+        pfx.push(&self.path);
         pfx
     }
 
@@ -136,13 +351,61 @@ impl MarkdownChangelog {
         repo.resolve_workdir(&self.changelog_repopath(proj))
     }
 
+    /// Render the new changelog section for a release update.
+    fn render_draft_section(
+        &self,
+        sess: &AppSession,
+        commits: &[CommitId],
+        bump_spec: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let mut dest = Vec::new();
+        let mut headfoot_args = HashMap::new();
+        let bump_spec = match bump_spec {
+            Some(spec) => spec,
+            None if self.infer_conventional => infer_conventional_bump(&sess.repo, commits)?,
+            None => "micro bump",
+        };
+        headfoot_args.insert("bump_spec", bump_spec);
+        let header = SimpleCurlyFormat
+            .format(&self.stage_header_format, &headfoot_args)
+            .map_err(|e| Error::msg(e.to_string()))?;
+        writeln!(&mut dest, "{}", header)?;
+
+        // Commit summaries! Note: if we're staging muliple projects and the
+        // same commit affects many of them, we'll reload the same commit many
+        // times when generating changelogs.
+
+        const WRAP_WIDTH: usize = 78;
+
+        for cid in commits {
+            let message = sess.repo.get_commit_summary(*cid)?;
+            let mut prefix = "- ";
+
+            for line in textwrap::wrap(&message, WRAP_WIDTH) {
+                writeln!(&mut dest, "{}{}", prefix, line)?;
+                prefix = "  ";
+            }
+        }
+
+        // Footer
+
+        let footer = SimpleCurlyFormat
+            .format(&self.footer_format, &headfoot_args)
+            .map_err(|e| Error::msg(e.to_string()))?;
+        writeln!(&mut dest, "{}", footer)?;
+        Ok(dest)
+    }
+
     /// Generic implementation for draft_release_update and replace_changelog.
+    ///
+    /// If *new_section* is provided, we're drafting a release update, and it
+    /// is the rendered text of the new changelog section.
     fn replace_changelog_impl(
         &self,
         proj: &Project,
         sess: &AppSession,
         prev_release_commit: Option<CommitId>,
-        in_changes: Option<&[CommitId]>,
+        new_section: Option<&[u8]>,
         out_changes: Option<&mut ChangeList>,
     ) -> Result<()> {
         // Get the previous changelog from the most recent `release`
@@ -160,44 +423,24 @@ impl MarkdownChangelog {
 
         let changelog_path = self.changelog_path(proj, &sess.repo);
 
+        if new_section.is_some() {
+            prepare_new_changelog(&changelog_repopath, &changelog_path)?;
+        }
+
+        let ending = match LineEnding::detect(&prev_log) {
+            Some(e) => e,
+            None => LineEnding::for_file(&changelog_path)?,
+        };
+
         let new_af = atomicwrites::AtomicFile::new(
             changelog_path,
             atomicwrites::OverwriteBehavior::AllowOverwrite,
         );
 
         let r = new_af.write(|new_f| {
-            if let Some(commits) = in_changes {
+            if let Some(section) = new_section {
                 // We're drafting a release update -- add a new section.
-
-                let mut headfoot_args = HashMap::new();
-                headfoot_args.insert("bump_spec", "micro bump");
-                let header = SimpleCurlyFormat
-                    .format(&self.stage_header_format, &headfoot_args)
-                    .map_err(|e| Error::msg(e.to_string()))?;
-                write_crlf!(new_f, "{}", header)?;
-
-                // Commit summaries! Note: if we're staging muliple projects and the
-                // same commit affects many of them, we'll reload the same commit many
-                // times when generating changelogs.
-
-                const WRAP_WIDTH: usize = 78;
-
-                for cid in commits {
-                    let message = sess.repo.get_commit_summary(*cid)?;
-                    let mut prefix = "- ";
-
-                    for line in textwrap::wrap(&message, WRAP_WIDTH) {
-                        write_crlf!(new_f, "{}{}", prefix, line)?;
-                        prefix = "  ";
-                    }
-                }
-
-                // Footer
-
-                let footer = SimpleCurlyFormat
-                    .format(&self.footer_format, &headfoot_args)
-                    .map_err(|e| Error::msg(e.to_string()))?;
-                write_crlf!(new_f, "{}", footer)?;
+                LineEndingWriter::new(&mut *new_f, ending).write_all(section)?;
             }
 
             // Write back all of the previous contents, and we're done.
@@ -207,7 +450,7 @@ impl MarkdownChangelog {
         });
 
         if let Some(chlist) = out_changes {
-            chlist.add_path(&self.changelog_repopath(proj));
+            chlist.add_path(&changelog_repopath);
         }
 
         match r {
@@ -225,8 +468,10 @@ impl Changelog for MarkdownChangelog {
         sess: &AppSession,
         changes: &[CommitId],
         prev_release_commit: Option<CommitId>,
+        bump_spec: Option<&str>,
     ) -> Result<()> {
-        self.replace_changelog_impl(proj, sess, prev_release_commit, Some(changes), None)
+        let section = self.render_draft_section(sess, changes, bump_spec)?;
+        self.replace_changelog_impl(proj, sess, prev_release_commit, Some(&section), None)
     }
 
     fn replace_changelog(
@@ -291,6 +536,7 @@ impl Changelog for MarkdownChangelog {
         );
 
         let changelog_path = self.changelog_path(proj, repo);
+        let ending = LineEnding::for_file(&changelog_path)?;
         let cur_f = File::open(&changelog_path)?;
         let cur_reader = BufReader::new(cur_f);
 
@@ -299,6 +545,7 @@ impl Changelog for MarkdownChangelog {
             atomicwrites::OverwriteBehavior::AllowOverwrite,
         );
         let r = new_af.write(|new_f| {
+            let mut new_f = LineEndingWriter::new(new_f, ending);
             // Pipe the current changelog into the new one, replacing the `rc`
             // header with the final one.
 
@@ -327,18 +574,18 @@ impl Changelog for MarkdownChangelog {
                         let header = SimpleCurlyFormat
                             .format(&self.release_header_format, &header_args)
                             .map_err(|e| Error::msg(e.to_string()))?;
-                        write_crlf!(new_f, "{}", header)?;
+                        writeln!(new_f, "{}", header)?;
                     }
 
                     State::BlanksAfterHeader => {
                         if !line.trim().is_empty() {
                             state = State::AfterHeader;
-                            write_crlf!(new_f, "{}", line)?;
+                            writeln!(new_f, "{}", line)?;
                         }
                     }
 
                     State::AfterHeader => {
-                        write_crlf!(new_f, "{}", line)?;
+                        writeln!(new_f, "{}", line)?;
                     }
                 }
             }
@@ -397,4 +644,877 @@ impl Changelog for MarkdownChangelog {
 
         Ok(changelog)
     }
+
+    fn render_draft(
+        &self,
+        _proj: &Project,
+        sess: &AppSession,
+        changes: &[CommitId],
+    ) -> Result<String> {
+        Ok(String::from_utf8(
+            self.render_draft_section(sess, changes, None)?,
+        )?)
+    }
+
+    fn scan_staged_notes(&self, proj: &Project, repo: &Repository) -> Result<String> {
+        let changelog_path = self.changelog_path(proj, repo);
+        let f = File::open(&changelog_path)?;
+        let reader = BufReader::new(f);
+        let mut in_notes = false;
+        let mut notes = String::new();
+
+        // As in scan_rc_info, we allow all-whitespace lines before the rc:
+        // header. The notes continue until the next top-level header.
+        for maybe_line in reader.lines() {
+            let line = maybe_line?;
+
+            if !in_notes {
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                if !line.starts_with("# rc:") {
+                    return Err(InvalidChangelogFormatError(changelog_path).into());
+                }
+
+                in_notes = true;
+            } else if line.starts_with("# ") {
+                break;
+            }
+
+            notes.push_str(&line);
+            notes.push('\n');
+        }
+
+        if !in_notes {
+            return Err(InvalidChangelogFormatError(changelog_path).into());
+        }
+
+        Ok(notes)
+    }
+}
+
+/// Settings for changelogs following the "Keep a Changelog" conventions.
+///
+/// See <https://keepachangelog.com/>. Releases are introduced with headers of
+/// the form `## [x.y.z] - yyyy-mm-dd`, and release candidates are described in
+/// an `## [Unreleased]` section, whose subheadings (`### Added`, `### Fixed`,
+/// etc.) determine how the version should be bumped. Bumps that can't be
+/// expressed that way are recorded in a `<!-- cranko-bump: ... -->` comment.
+#[derive(Debug)]
+pub struct KeepAChangelog {
+    /// The path of the changelog file, relative to the project directory.
+    path: String,
+}
+
+impl Default for KeepAChangelog {
+    fn default() -> Self {
+        KeepAChangelog {
+            path: DEFAULT_CHANGELOG_PATH.to_owned(),
+        }
+    }
+}
+
+const KAC_UNRELEASED_HEADER: &str = "## [Unreleased]";
+const KAC_BUMP_MARKER_PREFIX: &str = "<!-- cranko-bump:";
+const KAC_BUMP_MARKER_SUFFIX: &str = "-->";
+
+impl KeepAChangelog {
+    fn changelog_repopath(&self, proj: &Project) -> RepoPathBuf {
+        let mut pfx = proj.prefix().to_owned();
+        pfx.push(&self.path);
+        pfx
+    }
+
+    fn changelog_path(&self, proj: &Project, repo: &Repository) -> PathBuf {
+        repo.resolve_workdir(&self.changelog_repopath(proj))
+    }
+
+    /// Get the bump spec implied by a subheading of the `Unreleased` section,
+    /// along with a rank so that the most significant one can be chosen.
+    ///
+    /// Subheadings never imply major bumps, since whether a change is breaking
+    /// isn't something that we can guess. Those must be requested explicitly
+    /// with a bump marker.
+    fn bump_for_subheading(heading: &str) -> Option<(usize, &'static str)> {
+        match heading.trim().to_lowercase().as_ref() {
+            "fixed" | "security" => Some((0, "micro bump")),
+            "added" | "changed" | "deprecated" | "removed" => Some((1, "minor bump")),
+            _ => None,
+        }
+    }
+
+    /// Render the new `Unreleased` section for a release update.
+    fn render_draft_section(
+        &self,
+        sess: &AppSession,
+        commits: &[CommitId],
+        bump_spec: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let summaries = commits
+            .iter()
+            .map(|cid| sess.repo.get_commit_summary(*cid))
+            .collect::<Result<Vec<_>>>()?;
+        Self::format_draft_section(&summaries, bump_spec)
+    }
+
+    /// Format the new `Unreleased` section listing the given commit summaries.
+    ///
+    /// Micro and minor bumps are expressed through the subheading that the
+    /// commits are listed under. Any other requested bump is recorded in a
+    /// marker comment, which takes precedence over the subheadings.
+    fn format_draft_section(summaries: &[String], bump_spec: Option<&str>) -> Result<Vec<u8>> {
+        let (subheading, marker) = match bump_spec {
+            None | Some("micro bump") => ("Fixed", None),
+            Some("minor bump") => ("Changed", None),
+            Some(other) => ("Changed", Some(other)),
+        };
+
+        let mut dest = Vec::new();
+        writeln!(&mut dest, "{}", KAC_UNRELEASED_HEADER)?;
+        writeln!(&mut dest)?;
+
+        if let Some(spec) = marker {
+            writeln!(
+                &mut dest,
+                "{} {} {}",
+                KAC_BUMP_MARKER_PREFIX, spec, KAC_BUMP_MARKER_SUFFIX
+            )?;
+            writeln!(&mut dest)?;
+        }
+
+        writeln!(&mut dest, "### {}", subheading)?;
+        writeln!(&mut dest)?;
+
+        const WRAP_WIDTH: usize = 78;
+
+        for message in summaries {
+            let mut prefix = "- ";
+
+            for line in textwrap::wrap(message, WRAP_WIDTH) {
+                writeln!(&mut dest, "{}{}", prefix, line)?;
+                prefix = "  ";
+            }
+        }
+
+        writeln!(&mut dest)?;
+        Ok(dest)
+    }
+
+    /// Infer the bump requested by the `Unreleased` section of a changelog. An
+    /// explicit bump marker wins; otherwise the most significant subheading
+    /// determines the bump, defaulting to a micro bump. Returns None if there
+    /// is no `Unreleased` section.
+    fn infer_bump_spec<R: BufRead>(reader: R) -> Result<Option<String>> {
+        let mut in_unreleased = false;
+        let mut saw_unreleased = false;
+        let mut marker = None;
+        let mut bump = None;
+
+        for maybe_line in reader.lines() {
+            let line = maybe_line?;
+
+            if line.starts_with("## ") {
+                if in_unreleased {
+                    break;
+                }
+
+                in_unreleased = line.starts_with(KAC_UNRELEASED_HEADER);
+                saw_unreleased |= in_unreleased;
+            } else if in_unreleased {
+                if let Some(heading) = line.strip_prefix("### ") {
+                    if let Some(this_bump) = Self::bump_for_subheading(heading) {
+                        if bump
+                            .map(|b: (usize, &str)| this_bump.0 > b.0)
+                            .unwrap_or(true)
+                        {
+                            bump = Some(this_bump);
+                        }
+                    }
+                } else if let Some(spec) = line
+                    .trim()
+                    .strip_prefix(KAC_BUMP_MARKER_PREFIX)
+                    .and_then(|s| s.strip_suffix(KAC_BUMP_MARKER_SUFFIX))
+                {
+                    marker = Some(spec.trim().to_owned());
+                }
+            }
+        }
+
+        if !saw_unreleased {
+            return Ok(None);
+        }
+
+        Ok(Some(marker.unwrap_or_else(|| {
+            bump.map(|b| b.1).unwrap_or("micro bump").to_owned()
+        })))
+    }
+
+    /// Copy a changelog, replacing the `Unreleased` header with *header* and
+    /// dropping any bump marker in that section, along with the blank line
+    /// following it. Returns false if there is no such header.
+    fn finalize_lines<R: BufRead, W: Write>(reader: R, mut dest: W, header: &str) -> Result<bool> {
+        let mut replaced = false;
+        let mut in_unreleased = false;
+        let mut skip_blank = false;
+
+        for maybe_line in reader.lines() {
+            let line = maybe_line?;
+
+            if std::mem::take(&mut skip_blank) && line.trim().is_empty() {
+                continue;
+            }
+
+            if line.starts_with("## ") {
+                in_unreleased = false;
+            }
+
+            if !replaced && line.starts_with(KAC_UNRELEASED_HEADER) {
+                writeln!(dest, "{}", header)?;
+                replaced = true;
+                in_unreleased = true;
+            } else if in_unreleased && line.trim().starts_with(KAC_BUMP_MARKER_PREFIX) {
+                skip_blank = true;
+            } else {
+                writeln!(dest, "{}", line)?;
+            }
+        }
+
+        Ok(replaced)
+    }
+
+    /// Generic implementation for draft_release_update and replace_changelog.
+    fn replace_changelog_impl(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        prev_release_commit: Option<CommitId>,
+        new_section: Option<&[u8]>,
+        out_changes: Option<&mut ChangeList>,
+    ) -> Result<()> {
+        // Get the previous changelog from the most recent `release`
+        // commit.
+
+        let changelog_repopath = self.changelog_repopath(proj);
+
+        let prev_log: Vec<u8> = prev_release_commit
+            .map(|prc| sess.repo.get_file_at_commit(&prc, &changelog_repopath))
+            .transpose()?
+            .flatten()
+            .unwrap_or_default();
+
+        let changelog_path = self.changelog_path(proj, &sess.repo);
+
+        if new_section.is_some() {
+            prepare_new_changelog(&changelog_repopath, &changelog_path)?;
+        }
+
+        let ending = match LineEnding::detect(&prev_log) {
+            Some(e) => e,
+            None => LineEnding::for_file(&changelog_path)?,
+        };
+
+        let new_af = atomicwrites::AtomicFile::new(
+            changelog_path,
+            atomicwrites::OverwriteBehavior::AllowOverwrite,
+        );
+
+        let r = new_af.write(|new_f| {
+            let section = match new_section {
+                Some(s) => s,
+                None => {
+                    new_f.write_all(&prev_log[..])?;
+                    return Ok(());
+                }
+            };
+
+            let mut new_f = LineEndingWriter::new(new_f, ending);
+
+            // We're drafting a release update. The new `Unreleased` section
+            // goes before the first release section, after any preamble.
+
+            let mut lines = Cursor::new(&prev_log[..]).lines().peekable();
+
+            if prev_log.is_empty() {
+                writeln!(new_f, "# Changelog")?;
+                writeln!(new_f)?;
+            }
+
+            while let Some(line) = lines.next_if(|l| match l {
+                Ok(l) => !l.starts_with("## "),
+                Err(_) => false,
+            }) {
+                writeln!(new_f, "{}", line?)?;
+            }
+
+            new_f.write_all(section)?;
+
+            // Write back the rest of the previous contents, skipping any stale
+            // `Unreleased` section.
+
+            let mut in_unreleased = false;
+
+            for line in lines {
+                let line = line?;
+
+                if line.starts_with("## ") {
+                    in_unreleased = line.starts_with(KAC_UNRELEASED_HEADER);
+                }
+
+                if !in_unreleased {
+                    writeln!(new_f, "{}", line)?;
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Some(chlist) = out_changes {
+            chlist.add_path(&changelog_repopath);
+        }
+
+        match r {
+            Err(atomicwrites::Error::Internal(e)) => Err(e.into()),
+            Err(atomicwrites::Error::User(e)) => Err(e),
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+impl Changelog for KeepAChangelog {
+    fn draft_release_update(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        changes: &[CommitId],
+        prev_release_commit: Option<CommitId>,
+        bump_spec: Option<&str>,
+    ) -> Result<()> {
+        let section = self.render_draft_section(sess, changes, bump_spec)?;
+        self.replace_changelog_impl(proj, sess, prev_release_commit, Some(&section), None)
+    }
+
+    fn replace_changelog(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        changes: &mut ChangeList,
+        prev_release_commit: CommitId,
+    ) -> Result<()> {
+        self.replace_changelog_impl(proj, sess, Some(prev_release_commit), None, Some(changes))
+    }
+
+    fn create_path_matcher(&self, proj: &Project) -> Result<PathMatcher> {
+        Ok(PathMatcher::new_include(self.changelog_repopath(proj)))
+    }
+
+    fn scan_rc_info(&self, proj: &Project, repo: &Repository) -> Result<RcProjectInfo> {
+        let changelog_path = self.changelog_path(proj, repo);
+        let f = File::open(&changelog_path)?;
+        let bump_spec = match Self::infer_bump_spec(BufReader::new(f))? {
+            Some(s) => s,
+            None => return Err(InvalidChangelogFormatError(changelog_path).into()),
+        };
+        let _check_scheme = proj.version.parse_bump_scheme(&bump_spec)?;
+
+        Ok(RcProjectInfo {
+            qnames: proj.qualified_names().clone(),
+            bump_spec,
+        })
+    }
+
+    fn finalize_changelog(
+        &self,
+        proj: &Project,
+        repo: &Repository,
+        changes: &mut ChangeList,
+    ) -> Result<()> {
+        let now = Local::now();
+        let header = format!(
+            "## [{}] - {:04}-{:02}-{:02}",
+            proj.version,
+            now.year(),
+            now.month(),
+            now.day()
+        );
+
+        let changelog_path = self.changelog_path(proj, repo);
+        let ending = LineEnding::for_file(&changelog_path)?;
+        let cur_f = File::open(&changelog_path)?;
+        let cur_reader = BufReader::new(cur_f);
+
+        let new_af = atomicwrites::AtomicFile::new(
+            &changelog_path,
+            atomicwrites::OverwriteBehavior::AllowOverwrite,
+        );
+        let r = new_af.write(|new_f| {
+            let new_f = LineEndingWriter::new(new_f, ending);
+
+            if !Self::finalize_lines(cur_reader, new_f, &header)? {
+                return Err(InvalidChangelogFormatError(changelog_path).into());
+            }
+
+            Ok(())
+        });
+
+        changes.add_path(&self.changelog_repopath(proj));
+
+        match r {
+            Err(atomicwrites::Error::Internal(e)) => Err(e.into()),
+            Err(atomicwrites::Error::User(e)) => Err(e),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn scan_changelog(&self, proj: &Project, repo: &Repository, cid: &CommitId) -> Result<String> {
+        let changelog_path = self.changelog_repopath(proj);
+        let data = match repo.get_file_at_commit(cid, &changelog_path)? {
+            Some(d) => d,
+            None => return Ok(String::new()),
+        };
+        let reader = Cursor::new(data);
+        let mut in_release = false;
+        let mut changelog = String::new();
+
+        // The most recent release is the first level-2 section that isn't
+        // the `Unreleased` one.
+        for maybe_line in reader.lines() {
+            let line = maybe_line?;
+
+            if line.starts_with("## ") {
+                if in_release {
+                    break;
+                }
+
+                in_release = !line.starts_with(KAC_UNRELEASED_HEADER);
+            }
+
+            if in_release {
+                changelog.push_str(&line);
+                changelog.push('\n');
+            }
+        }
+
+        Ok(changelog)
+    }
+
+    fn render_draft(
+        &self,
+        _proj: &Project,
+        sess: &AppSession,
+        changes: &[CommitId],
+    ) -> Result<String> {
+        Ok(String::from_utf8(
+            self.render_draft_section(sess, changes, None)?,
+        )?)
+    }
+
+    fn scan_staged_notes(&self, proj: &Project, repo: &Repository) -> Result<String> {
+        let changelog_path = self.changelog_path(proj, repo);
+        let f = File::open(&changelog_path)?;
+        let reader = BufReader::new(f);
+        let mut in_unreleased = false;
+        let mut saw_unreleased = false;
+        let mut notes = String::new();
+
+        for maybe_line in reader.lines() {
+            let line = maybe_line?;
+
+            if line.starts_with("## ") {
+                if in_unreleased {
+                    break;
+                }
+
+                in_unreleased = line.starts_with(KAC_UNRELEASED_HEADER);
+                saw_unreleased |= in_unreleased;
+            }
+
+            if in_unreleased {
+                notes.push_str(&line);
+                notes.push('\n');
+            }
+        }
+
+        if !saw_unreleased {
+            return Err(InvalidChangelogFormatError(changelog_path).into());
+        }
+
+        Ok(notes)
+    }
+}
+
+/// The recognized news fragment types, in order of decreasing significance:
+/// the type name used in fragment filenames, the subheading used in the
+/// changelog, and the version bump that the type implies.
+const FRAGMENT_TYPES: &[(&str, &str, &str)] = &[
+    ("removal", "Removals and Breaking Changes", "major bump"),
+    ("feature", "Features", "minor bump"),
+    ("bugfix", "Bug Fixes", "micro bump"),
+    ("doc", "Documentation", "micro bump"),
+    ("misc", "Miscellaneous", "micro bump"),
+];
+
+/// A news fragment found in the working tree.
+#[derive(Debug)]
+struct NewsFragment {
+    repopath: RepoPathBuf,
+    name: String,
+    type_index: usize,
+    text: String,
+}
+
+/// Settings for changelogs assembled from "news fragment" files.
+///
+/// This is modeled on [towncrier](https://towncrier.readthedocs.io/). Instead
+/// of writing release notes while staging, contributors add Markdown files
+/// named `<name>.<type>.md` to a fragments directory along with their changes.
+/// `cranko stage` assembles the fragments added since the project's last
+/// release into a new changelog section, grouped by type, and chooses the
+/// bump implied by the most significant type. From there, the changelog file
+/// is handled just like a [`MarkdownChangelog`]. The fragments are deleted
+/// from the release branch when the release is finalized.
+#[derive(Debug)]
+pub struct FragmentsChangelog {
+    markdown: MarkdownChangelog,
+
+    /// The path of the fragments directory, relative to the project
+    /// directory.
+    fragments_dir: String,
+}
+
+impl FragmentsChangelog {
+    fn fragments_repopath(&self, proj: &Project) -> RepoPathBuf {
+        let mut pfx = proj.prefix().to_owned();
+        pfx.push(&self.fragments_dir);
+        pfx
+    }
+
+    /// Find the news fragments in the working tree, sorted by type and name.
+    ///
+    /// If *prev_release_commit* is specified, fragments that already existed
+    /// on the main branch when that release was made are skipped, since their
+    /// contents are already in the changelog. (The main branch never sees the
+    /// deletions made on the release branch.)
+    fn find_fragments(
+        &self,
+        proj: &Project,
+        repo: &Repository,
+        prev_release_commit: Option<CommitId>,
+    ) -> Result<Vec<NewsFragment>> {
+        let dir_repopath = self.fragments_repopath(proj);
+        let dir = repo.resolve_workdir(&dir_repopath);
+
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(Error::new(e).context(format!(
+                    "failed to read the news fragment directory `{}`",
+                    dir.display()
+                )))
+            }
+        };
+
+        let baseline = prev_release_commit
+            .map(|cid| repo.release_main_branch_commit(cid))
+            .transpose()?;
+
+        let mut fragments = Vec::new();
+
+        for maybe_entry in entries {
+            let entry = maybe_entry?;
+
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            // Files that don't look like `<name>.<type>.md` (e.g. `.gitkeep`
+            // or `README.md`) are not fragments.
+            let name = match entry.file_name().to_str() {
+                Some(n) => n.to_owned(),
+                None => continue,
+            };
+
+            let type_name = match name.strip_suffix(".md").and_then(|s| s.rsplit_once('.')) {
+                Some((stem, t)) if !stem.is_empty() => t,
+                _ => continue,
+            };
+
+            let type_index = a_ok_or!(
+                FRAGMENT_TYPES.iter().position(|ft| ft.0 == type_name);
+                ["unrecognized type `{}` for news fragment `{}`", type_name, entry.path().display()]
+                (note "the recognized types are: removal, feature, bugfix, doc, misc")
+            );
+
+            let mut repopath = dir_repopath.clone();
+            repopath.push(&name);
+
+            if let Some(ref b) = baseline {
+                if repo.get_file_at_commit(b, &repopath)?.is_some() {
+                    continue;
+                }
+            }
+
+            let text = atry!(
+                std::fs::read_to_string(entry.path());
+                ["failed to read news fragment `{}`", entry.path().display()]
+            );
+
+            fragments.push(NewsFragment {
+                repopath,
+                name,
+                type_index,
+                text,
+            });
+        }
+
+        fragments.sort_by(|a, b| (a.type_index, &a.name).cmp(&(b.type_index, &b.name)));
+        Ok(fragments)
+    }
+
+    /// Render the new changelog section from a set of fragments.
+    fn render_draft_section(
+        &self,
+        fragments: &[NewsFragment],
+        bump_spec: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let mut dest = Vec::new();
+        let mut headfoot_args = HashMap::new();
+        let bump_spec = bump_spec.unwrap_or_else(|| {
+            fragments
+                .first()
+                .map(|f| FRAGMENT_TYPES[f.type_index].2)
+                .unwrap_or("micro bump")
+        });
+        headfoot_args.insert("bump_spec", bump_spec);
+        let header = SimpleCurlyFormat
+            .format(&self.markdown.stage_header_format, &headfoot_args)
+            .map_err(|e| Error::msg(e.to_string()))?;
+        writeln!(&mut dest, "{}", header)?;
+
+        if fragments.is_empty() {
+            writeln!(&mut dest, "- No significant changes.")?;
+        }
+
+        let mut cur_type = None;
+
+        for frag in fragments {
+            if cur_type != Some(frag.type_index) {
+                if cur_type.is_some() {
+                    writeln!(&mut dest)?;
+                }
+
+                writeln!(&mut dest, "### {}", FRAGMENT_TYPES[frag.type_index].1)?;
+                writeln!(&mut dest)?;
+                cur_type = Some(frag.type_index);
+            }
+
+            let mut prefix = "- ";
+
+            for line in frag.text.trim().lines() {
+                if line.trim().is_empty() {
+                    writeln!(&mut dest)?;
+                } else {
+                    writeln!(&mut dest, "{}{}", prefix, line.trim_end())?;
+                }
+
+                prefix = "  ";
+            }
+        }
+
+        writeln!(&mut dest)?;
+        Ok(dest)
+    }
+}
+
+impl Changelog for FragmentsChangelog {
+    fn draft_release_update(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        _changes: &[CommitId],
+        prev_release_commit: Option<CommitId>,
+        bump_spec: Option<&str>,
+    ) -> Result<()> {
+        let fragments = self.find_fragments(proj, &sess.repo, prev_release_commit)?;
+        let section = self.render_draft_section(&fragments, bump_spec)?;
+        self.markdown
+            .replace_changelog_impl(proj, sess, prev_release_commit, Some(&section), None)
+    }
+
+    fn replace_changelog(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        changes: &mut ChangeList,
+        prev_release_commit: CommitId,
+    ) -> Result<()> {
+        self.markdown
+            .replace_changelog(proj, sess, changes, prev_release_commit)
+    }
+
+    fn create_path_matcher(&self, proj: &Project) -> Result<PathMatcher> {
+        // Only the changelog file itself: merely adding a fragment shouldn't
+        // make the project look like it's been staged.
+        self.markdown.create_path_matcher(proj)
+    }
+
+    fn scan_rc_info(&self, proj: &Project, repo: &Repository) -> Result<RcProjectInfo> {
+        self.markdown.scan_rc_info(proj, repo)
+    }
+
+    fn finalize_changelog(
+        &self,
+        proj: &Project,
+        repo: &Repository,
+        changes: &mut ChangeList,
+    ) -> Result<()> {
+        self.markdown.finalize_changelog(proj, repo, changes)?;
+
+        // The fragments have now been incorporated into the changelog, so
+        // remove all of them from the release branch.
+
+        for frag in self.find_fragments(proj, repo, None)? {
+            let path = repo.resolve_workdir(&frag.repopath);
+            atry!(
+                std::fs::remove_file(&path);
+                ["failed to delete news fragment `{}`", path.display()]
+            );
+            changes.add_path(&frag.repopath);
+        }
+
+        Ok(())
+    }
+
+    fn scan_changelog(&self, proj: &Project, repo: &Repository, cid: &CommitId) -> Result<String> {
+        self.markdown.scan_changelog(proj, repo, cid)
+    }
+
+    fn render_draft(
+        &self,
+        proj: &Project,
+        sess: &AppSession,
+        _changes: &[CommitId],
+    ) -> Result<String> {
+        let histories = sess.analyze_histories()?;
+        let prev_release_commit = histories.lookup(proj.ident()).release_commit();
+        let fragments = self.find_fragments(proj, &sess.repo, prev_release_commit)?;
+        Ok(String::from_utf8(
+            self.render_draft_section(&fragments, None)?,
+        )?)
+    }
+
+    fn scan_staged_notes(&self, proj: &Project, repo: &Repository) -> Result<String> {
+        self.markdown.scan_staged_notes(proj, repo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREVIOUS: &str = "# Changelog\n\n## [1.0.0] - 2021-01-01\n\n### Added\n\n- First.\n";
+
+    /// Draft a KAC `Unreleased` section ahead of the previous release, as
+    /// `cranko stage` would.
+    fn draft(bump_spec: Option<&str>) -> String {
+        let section =
+            KeepAChangelog::format_draft_section(&["Do a thing".to_owned()], bump_spec).unwrap();
+        let (preamble, rest) = PREVIOUS.split_at(PREVIOUS.find("## ").unwrap());
+        format!(
+            "{}{}{}",
+            preamble,
+            String::from_utf8(section).unwrap(),
+            rest
+        )
+    }
+
+    fn infer(text: &str) -> Option<String> {
+        KeepAChangelog::infer_bump_spec(Cursor::new(text)).unwrap()
+    }
+
+    #[test]
+    fn conventional_bump_levels() {
+        for (message, level) in [
+            ("Fix a typo", 0),
+            ("Fix the foo: really", 0),
+            ("Release notes: feat(foo)!: nope", 0),
+            ("fix: a bug", 0),
+            ("docs(readme): tweak", 0),
+            ("feat: a feature", 1),
+            ("Feat(cli): a feature", 1),
+            ("feat:no space", 0),
+            ("feat (cli): a space", 0),
+            ("feat(a(b)): nested", 0),
+            ("fix!: a breaking fix", 2),
+            ("refactor(core)!: breaking", 2),
+            ("chore!: drop old platforms", 2),
+            ("Not!conventional: either", 0),
+            ("fix: a bug\n\nBREAKING CHANGE: it broke", 2),
+            ("Plain summary\n\nBREAKING-CHANGE: it broke", 2),
+            ("feat: x\n\nsee BREAKING CHANGE: not a footer", 1),
+        ] {
+            assert_eq!(conventional_bump_level(message), level, "{}", message);
+        }
+    }
+
+    #[test]
+    fn kac_draft_round_trips() {
+        assert_eq!(infer(&draft(None)).as_deref(), Some("micro bump"));
+        assert!(draft(None).contains("### Fixed\n\n- Do a thing\n"));
+        assert!(draft(Some("minor bump")).contains("### Changed\n\n- Do a thing\n"));
+
+        for spec in ["micro bump", "minor bump", "major bump", "dev-datecode"] {
+            assert_eq!(infer(&draft(Some(spec))).as_deref(), Some(spec));
+        }
+    }
+
+    #[test]
+    fn kac_subheading_bumps() {
+        let with = |headings: &[&str]| {
+            let mut text = "## [Unreleased]\n\n".to_owned();
+
+            for h in headings {
+                text.push_str(&format!("### {}\n\n- Item.\n\n", h));
+            }
+
+            text.push_str("## [1.0.0] - 2021-01-01\n\n### Removed\n");
+            infer(&text).unwrap()
+        };
+
+        assert_eq!(with(&[]), "micro bump");
+        assert_eq!(with(&["Fixed", "Security"]), "micro bump");
+        assert_eq!(with(&["Changed"]), "minor bump");
+        assert_eq!(with(&["Added"]), "minor bump");
+        assert_eq!(with(&["Fixed", "Changed"]), "minor bump");
+        assert_eq!(with(&["Removed"]), "minor bump");
+        assert_eq!(with(&["Deprecated", "Fixed"]), "minor bump");
+
+        assert_eq!(infer(PREVIOUS), None);
+    }
+
+    #[test]
+    fn kac_finalize() {
+        let mut out = Vec::new();
+        let ok = KeepAChangelog::finalize_lines(
+            Cursor::new(draft(Some("major bump"))),
+            &mut out,
+            "## [2.0.0] - 2021-06-01",
+        )
+        .unwrap();
+        assert!(ok);
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "# Changelog\n\n## [2.0.0] - 2021-06-01\n\n### Changed\n\n- Do a thing\n\n\
+             ## [1.0.0] - 2021-01-01\n\n### Added\n\n- First.\n"
+        );
+        assert_eq!(infer(&out), None);
+
+        let mut out = Vec::new();
+        assert!(
+            !KeepAChangelog::finalize_lines(Cursor::new(PREVIOUS), &mut out, "## [x]").unwrap()
+        );
+    }
 }
@@ -27,6 +27,10 @@ mod syntax {
         /// General per-repository configuration.
         pub repo: RepoConfiguration,
 
+        /// Cargo integration configuration.
+        #[serde(default)]
+        pub cargo: CargoConfiguration,
+
         /// NPM integration configuration.
         #[serde(default)]
         pub npm: NpmConfiguration,
@@ -51,6 +55,25 @@ mod syntax {
 
         /// The format for release tag names.
         pub release_tag_name_format: Option<String>,
+
+        /// The author name to use for commits created by Cranko.
+        pub commit_author_name: Option<String>,
+
+        /// The author email to use for commits created by Cranko.
+        pub commit_author_email: Option<String>,
+
+        /// Whether internal dependencies on commits that predate a project's
+        /// first Cranko release should be satisfied by its bootstrap version.
+        /// The default is false.
+        pub bootstrap_dep_floor: Option<bool>,
+    }
+
+    /// Configuration related to the Cargo integration.
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct CargoConfiguration {
+        /// Whether to load Cargo projects at all. The default is true. Loading
+        /// them requires the `cargo` program to be available.
+        pub enabled: Option<bool>,
     }
 
     /// Configuration related to the NPM integration.
@@ -69,18 +92,104 @@ mod syntax {
     #[derive(Clone, Debug, Default, Deserialize, Serialize)]
     pub struct ProjectConfiguration {
         /// Ignore this project if/when it is automatically detected.
+        #[serde(default)]
         pub ignore: bool,
+
+        /// The type of a project defined in the configuration file rather
+        /// than detected from the repository contents. The only type is
+        /// `"meta"`, for a virtual project whose version is derived from
+        /// those of the projects that it aggregates.
+        #[serde(rename = "type")]
+        pub project_type: Option<String>,
+
+        /// The names of the projects aggregated by a `"meta"` project.
+        #[serde(default)]
+        pub aggregates: Vec<String>,
+
+        /// Override the versioning scheme of this project: one of `"semver"`,
+        /// `"pep440"`, or `"dotnet"`. By default, the scheme is determined by
+        /// the project type.
+        pub version_scheme: Option<String>,
+
+        /// The format of this project's changelog: `"cranko"` (the
+        /// default), `"keepachangelog"`, or `"fragments"`.
+        pub changelog_format: Option<String>,
+
+        /// The path of this project's changelog file, relative to the
+        /// project's directory. The default is `"CHANGELOG.md"`.
+        pub changelog_path: Option<String>,
+
+        /// The directory holding this project's news fragments, relative to
+        /// the project's directory, when using the `"fragments"` changelog
+        /// format. The default is `"newsfragments"`.
+        pub changelog_fragments_dir: Option<String>,
+
+        /// How to infer the bump spec drafted by `cranko stage`. The only
+        /// mode is `"conventional"`, which examines Conventional Commits
+        /// messages. By default, a micro bump is always drafted.
+        pub infer_bump: Option<String>,
+
+        /// The version to assign to this project in "development" mode,
+        /// overriding the scheme-specific default (e.g. `0.0.0-dev.0`).
+        pub dev_version: Option<String>,
+
+        /// A shell command that prints out the project's current version. It
+        /// is only used if Cranko can't find the version in any of the
+        /// project's files, and is run in the project's directory.
+        pub version_command: Option<String>,
+
+        /// `.gitignore`-style patterns of files, relative to the project's
+        /// directory, that should not be considered to affect the project when
+        /// analyzing its history.
+        #[serde(default)]
+        pub ignore_paths: Vec<String>,
+
+        /// Additional directories, relative to the repository root, whose
+        /// files should be considered to affect the project, for projects
+        /// whose files don't all live under a single directory.
+        #[serde(default)]
+        pub extra_paths: Vec<String>,
+
+        /// A tiebreaker for ordering this project relative to projects that it
+        /// has no dependency relationship with, e.g. when publishing releases.
+        /// Lower values come first. The default is zero.
+        pub release_priority: Option<i32>,
+
+        /// Textual replacements to make in arbitrary files when the project's
+        /// version is updated, such as installation instructions in a README.
+        #[serde(default)]
+        pub replacements: Vec<ReplacementConfiguration>,
+    }
+
+    /// A textual replacement to make when updating a project's version.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct ReplacementConfiguration {
+        /// The path of the file to modify, relative to the project's
+        /// directory.
+        pub file: String,
+
+        /// A regular expression matching the text to be replaced.
+        pub search: String,
+
+        /// The replacement text. The string `{{version}}` is replaced with the
+        /// project's new version, and capture groups of the search pattern
+        /// may be referenced as `$1`, `${name}`, etc.
+        pub replace: String,
     }
 }
 
 // The rest of this module normalizes the on-disk format into forms more useful
 // at runtime.
 
-pub use syntax::{NpmConfiguration, ProjectConfiguration, RepoConfiguration};
+pub use syntax::{
+    CargoConfiguration, NpmConfiguration, ProjectConfiguration, ReplacementConfiguration,
+    RepoConfiguration,
+};
 
 #[derive(Clone, Debug)]
 pub struct ConfigurationFile {
     pub repo: RepoConfiguration,
+    pub cargo: CargoConfiguration,
     pub npm: NpmConfiguration,
     pub projects: HashMap<String, ProjectConfiguration>,
 }
@@ -88,11 +197,13 @@ pub struct ConfigurationFile {
 impl Default for ConfigurationFile {
     fn default() -> Self {
         let repo = RepoConfiguration::default();
+        let cargo = Default::default();
         let npm = Default::default();
         let projects = Default::default();
 
         ConfigurationFile {
             repo,
+            cargo,
             npm,
             projects,
         }
@@ -129,6 +240,7 @@ impl ConfigurationFile {
 
         Ok(ConfigurationFile {
             repo: sercfg.repo,
+            cargo: sercfg.cargo,
             npm: sercfg.npm,
             projects: sercfg.projects,
         })
@@ -137,6 +249,7 @@ impl ConfigurationFile {
     pub fn into_toml(self) -> Result<String> {
         let syn_cfg = syntax::SerializedConfiguration {
             repo: self.repo,
+            cargo: self.cargo,
             npm: self.npm,
             projects: self.projects,
         };
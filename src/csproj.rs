@@ -3,28 +3,32 @@
 
 //! Visual Studio C# projects.
 //!
-//! We currently "manually" update `Properties/AssemblyInfo.cs`.
+//! We "manually" update `Properties/AssemblyInfo.cs`, or the MSBuild `<Version>`
+//! property in the `.csproj` file or an ancestor `Directory.Build.props` file.
 
 use anyhow::bail;
+use lazy_static::lazy_static;
 use log::{info, warn};
 use quick_xml::{events::Event, Reader};
+use regex::Regex;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{BufRead, BufReader},
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    path::Path,
 };
 
 use crate::{
     a_ok_or,
-    app::{AppBuilder, AppSession},
+    app::{self, AppBuilder},
     atry,
     config::ProjectConfiguration,
-    errors::Result,
+    errors::{AnnotatedReport, Result},
+    line_endings::{LineEnding, LineEndingWriter},
     project::{DepRequirement, DependencyTarget, ProjectId},
     repository::{ChangeList, RepoPath, RepoPathBuf, Repository},
-    rewriters::Rewriter,
-    version::Version,
-    write_crlf,
+    rewriters::{RewriteContext, Rewriter},
+    version::{DotNetVersion, Version, VersionScheme},
 };
 
 /// Framework for auto-loading Visual Studio C# projects from the repository
@@ -33,6 +37,9 @@ use crate::{
 pub struct CsProjLoader {
     dirs_of_interest: HashMap<RepoPathBuf, DirData>,
     vdproj_files: Vec<RepoPathBuf>,
+
+    /// `Directory.Build.props` files, keyed by their containing directory.
+    build_props: HashMap<RepoPathBuf, RepoPathBuf>,
 }
 
 #[derive(Debug, Default)]
@@ -61,11 +68,32 @@ impl CsProjLoader {
             e.assembly_info = Some(repopath.to_owned());
         } else if basename.ends_with(b".vdproj") {
             self.vdproj_files.push(repopath.to_owned());
+        } else if basename.as_ref() == b"Directory.Build.props" {
+            self.build_props
+                .insert(dirname.to_owned(), repopath.to_owned());
         }
 
         Ok(())
     }
 
+    /// Find the `Directory.Build.props` file that MSBuild would import for a
+    /// project in *dir*: the one in the closest enclosing directory.
+    fn find_build_props(&self, dir: &RepoPath) -> Option<&RepoPathBuf> {
+        let mut dir = dir;
+
+        loop {
+            if let Some(props) = self.build_props.get(&dir.to_owned()) {
+                return Some(props);
+            }
+
+            if dir.is_empty() {
+                return None;
+            }
+
+            dir = dir.pop_sep().split_basename().0;
+        }
+    }
+
     /// Finalize autoloading any CsProj projects. Consumes this object.
     pub fn finalize(
         mut self,
@@ -134,10 +162,13 @@ impl CsProjLoader {
         }
 
         let mut guid_to_info = HashMap::new();
+        let mut props_users: HashMap<RepoPathBuf, Vec<ProjectId>> = HashMap::new();
         let mut gave_dep_warning_help = false;
 
         for (repodir, data) in &self.dirs_of_interest {
-            // Basic checking that we got both a csproj and an assemblyinfo.
+            // Basic checking that we got a csproj. The AssemblyInfo.cs is
+            // optional, since modern projects set their versions with MSBuild
+            // properties.
 
             let csproj = match data.csproj {
                 Some(ref d) => d,
@@ -152,17 +183,6 @@ impl CsProjLoader {
                 }
             };
 
-            let assembly_info = match data.assembly_info {
-                Some(ref d) => d,
-                None => {
-                    warn!(
-                        "ignoring directory `{}` that has a .csproj file but no Properties/AssemblyInfo.cs",
-                        repodir.escaped()
-                    );
-                    continue;
-                }
-            };
-
             // Parse the .csproj XML
 
             let p = app.repo.resolve_workdir(csproj);
@@ -266,25 +286,16 @@ impl CsProjLoader {
                 }
             }
 
-            let guid = match guid {
-                Some(g) => g,
-                None => {
-                    warn!(
-                        "ignoring .csproj file `{}`: cannot find its ProjectGuid",
-                        p.display()
-                    );
-                    continue;
-                }
-            };
+            // SDK-style projects generally don't have GUIDs, which means that
+            // they can't participate in our dependency tracking, and their
+            // assembly name defaults to the name of the project file.
 
             let name = match name {
                 Some(n) => n,
                 None => {
-                    warn!(
-                        "ignoring .csproj file `{}`: cannot find its ProjectGuid",
-                        p.display()
-                    );
-                    continue;
+                    let (_dir, base) = csproj.split_basename();
+                    let base = base.escaped();
+                    base.strip_suffix(".csproj").unwrap_or(&base).to_owned()
                 }
             };
 
@@ -328,12 +339,19 @@ impl CsProjLoader {
                 resolved_reqs.push((guid, text, req));
             }
 
-            // Now parse the assembly info ...
+            // Now parse the assembly info, if there is one ... If the project
+            // is configured to use semver versions, the version text might
+            // carry prerelease or build metadata that can't be parsed as a
+            // .NET version.
 
+            let semver_hint = pconfig
+                .get(&format!("csproj:{}", name))
+                .and_then(|c| c.version_scheme.as_deref())
+                == Some("semver");
             let mut version = None;
-            let p = app.repo.resolve_workdir(assembly_info);
 
-            {
+            if let Some(assembly_info) = data.assembly_info.as_ref() {
+                let p = app.repo.resolve_workdir(assembly_info);
                 let f = atry!(
                     File::open(&p);
                     ["failed to open file `{}`", p.display()]
@@ -355,22 +373,87 @@ impl CsProjLoader {
                             line.rfind('"');
                             ["error parsing AssemblyVersion line in file `{}`", p.display()]
                         );
-                        let v = atry!(
-                            line[l1 + 1..l2].parse();
+                        let text = &line[l1 + 1..l2];
+                        version = Some(atry!(
+                            parse_version_text(text, semver_hint);
                             ["error parsing AssemblyVersion line in file `{}`", p.display()]
-                        );
-                        version = Some(Version::DotNet(v));
+                        ));
+                    }
+                }
+            }
+
+            // Otherwise, look for an MSBuild `<Version>` property in the
+            // project file itself, then in the closest `Directory.Build.props`
+            // file, which is where MSBuild would look for inherited
+            // properties. If a project gets its version from the props file,
+            // it will be rewritten there.
+
+            let mut version_file = None;
+
+            if version.is_none() {
+                let mut candidates = vec![csproj.to_owned()];
+
+                if let Some(props) = self.find_build_props(repodir) {
+                    candidates.push(props.to_owned());
+                }
+
+                for candidate in candidates {
+                    let cp = app.repo.resolve_workdir(&candidate);
+
+                    if let Some(text) = read_msbuild_version(&cp)? {
+                        // The property might be defined in terms of other
+                        // ones, like `$(VersionPrefix)`, in which case we can
+                        // neither read nor rewrite it. It still overrides any
+                        // inherited definition, so we stop looking.
+                        match parse_version_text(&text, semver_hint) {
+                            Ok(v) => {
+                                version = Some(v);
+                                version_file = Some(candidate);
+                            }
+
+                            Err(e) => {
+                                warn!(
+                                    "cannot use the <Version> property \"{}\" in file `{}`: {}",
+                                    text,
+                                    cp.display(),
+                                    e
+                                );
+                            }
+                        }
+
+                        break;
                     }
                 }
             }
 
+            // If that didn't work, the user might have configured a command
+            // to get the version.
+
+            let version = match version {
+                Some(v) => Some(v),
+                None => {
+                    let template = if semver_hint {
+                        Version::Semver(semver::Version::new(0, 0, 0))
+                    } else {
+                        Version::DotNet(DotNetVersion::default())
+                    };
+
+                    app::version_from_command(
+                        &app.repo,
+                        pconfig,
+                        &[name.to_owned(), "csproj".to_owned()],
+                        repodir,
+                        &template,
+                    )?
+                }
+            };
+
             let version = match version {
                 Some(v) => v,
                 None => {
                     warn!(
-                        "ignoring project in `{}`: cannot find its version in `{}`",
-                        repodir.escaped(),
-                        p.display()
+                        "ignoring project in `{}`: cannot find its version in an AssemblyInfo.cs file or MSBuild <Version> property",
+                        repodir.escaped()
                     );
                     continue;
                 }
@@ -385,28 +468,55 @@ impl CsProjLoader {
                 proj.prefix = Some(repodir.to_owned());
                 proj.version = Some(version);
 
-                // Auto-register a rewriter to update this package's `AssemblyInfo.cs`.
-                let rewrite = AssemblyInfoCsRewriter::new(ident, assembly_info.to_owned());
-                proj.rewriters.push(Box::new(rewrite));
+                // Our rewriters insert the version textually, so a semver-style
+                // version can be used if the user so chooses.
+                proj.alternate_version_schemes.push(VersionScheme::Semver);
+
+                // Auto-register a rewriter to update wherever the version came
+                // from. Rewriters for MSBuild files, which might be shared
+                // between projects, are set up below.
+                if let Some(vfile) = version_file {
+                    props_users.entry(vfile).or_default().push(ident);
+                } else if let Some(assembly_info) = data.assembly_info.as_ref() {
+                    let rewrite = AssemblyInfoCsRewriter::new(ident, assembly_info.to_owned());
+                    proj.rewriters.push(Box::new(rewrite));
+                }
 
-                // Any vdproj rewriters?
-                if let Some(mut vdprojs) = guid_to_vdproj.remove(&guid) {
-                    for vdproj in vdprojs.drain(..) {
-                        let rewrite = VdprojRewriter::new(ident, vdproj);
-                        proj.rewriters.push(Box::new(rewrite));
+                if let Some(guid) = guid {
+                    // Any vdproj rewriters?
+                    if let Some(mut vdprojs) = guid_to_vdproj.remove(&guid) {
+                        for vdproj in vdprojs.drain(..) {
+                            let rewrite = VdprojRewriter::new(ident, vdproj);
+                            proj.rewriters.push(Box::new(rewrite));
+                        }
                     }
-                }
 
-                // Save the info for dep-linking.
+                    // Save the info for dep-linking.
 
-                guid_to_info.insert(
-                    guid,
-                    Info {
-                        ident,
-                        name: name.to_owned(),
-                        deps: resolved_reqs,
-                    },
-                );
+                    guid_to_info.insert(
+                        guid,
+                        Info {
+                            ident,
+                            name: name.to_owned(),
+                            deps: resolved_reqs,
+                        },
+                    );
+                }
+            }
+        }
+
+        // If several projects inherit their version from the same
+        // `Directory.Build.props`, each of them gets a rewriter for it, so
+        // that the file is updated if any of them is selected. The rewriter
+        // leaves the file alone if another one has already updated it.
+
+        for (path, idents) in props_users.drain() {
+            for ident in &idents {
+                let rewrite = MsBuildVersionRewriter::new(idents.clone(), path.clone());
+                app.graph
+                    .lookup_mut(*ident)
+                    .rewriters
+                    .push(Box::new(rewrite));
             }
         }
 
@@ -436,6 +546,186 @@ impl CsProjLoader {
     }
 }
 
+/// Parse a C# project version string. If the project is configured to use
+/// semver versions, the text can be a semver version that isn't a valid .NET
+/// version.
+fn parse_version_text(text: &str, semver_hint: bool) -> Result<Version> {
+    Ok(if semver_hint && text.parse::<DotNetVersion>().is_err() {
+        Version::Semver(semver::Version::parse(text)?)
+    } else {
+        Version::DotNet(text.parse()?)
+    })
+}
+
+/// Read the value of the MSBuild `<Version>` property from an XML file, if it
+/// is defined. We only look at elements directly inside a `<PropertyGroup>`, so
+/// that the `<Version>` child elements of `<PackageReference>` items aren't
+/// picked up. As in MSBuild, the last definition wins.
+fn read_msbuild_version(path: &Path) -> Result<Option<String>> {
+    let mut xml = atry!(
+        Reader::from_file(path);
+        ["unable to open `{}` for reading", path.display()]
+    );
+    let mut buf = Vec::new();
+    let mut depth = 0;
+    let mut property_group_depth = None;
+    let mut in_version = false;
+    let mut version = None;
+
+    loop {
+        match xml.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                depth += 1;
+
+                if e.name().0 == b"PropertyGroup" {
+                    property_group_depth = Some(depth);
+                } else if e.name().0 == b"Version" && property_group_depth == Some(depth - 1) {
+                    in_version = true;
+                }
+            }
+
+            Ok(Event::Text(ref t)) if in_version => {
+                version = Some(
+                    atry!(
+                        t.unescape();
+                        ["unable to decode XML text in <Version> of `{}`", path.display()]
+                    )
+                    .trim()
+                    .to_owned(),
+                );
+            }
+
+            Ok(Event::End(_)) => {
+                if property_group_depth == Some(depth) {
+                    property_group_depth = None;
+                }
+
+                in_version = false;
+                depth -= 1;
+            }
+
+            Ok(Event::Eof) => break,
+
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("error parsing `{}` as XML", path.display())))
+            }
+
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    Ok(version)
+}
+
+/// Rewrite the MSBuild `<Version>` property in a `.csproj` or
+/// `Directory.Build.props` file.
+///
+/// A `Directory.Build.props` file can define the version of several projects,
+/// which must then all have the same version.
+#[derive(Debug)]
+pub struct MsBuildVersionRewriter {
+    proj_ids: Vec<ProjectId>,
+    path: RepoPathBuf,
+}
+
+impl MsBuildVersionRewriter {
+    /// Create a new MSBuild `<Version>` rewriter. *proj_ids* must not be empty.
+    pub fn new(proj_ids: Vec<ProjectId>, path: RepoPathBuf) -> Self {
+        MsBuildVersionRewriter { proj_ids, path }
+    }
+}
+
+/// Replace the values of any `<Version>` properties directly inside a
+/// `<PropertyGroup>` in the text of an MSBuild file. Returns None if there are
+/// no such properties.
+fn replace_msbuild_version(text: &str, version: &str) -> Option<String> {
+    lazy_static! {
+        static ref PROPERTY_GROUP_RE: Regex =
+            Regex::new(r"(?s)<PropertyGroup\b[^>]*>.*?</PropertyGroup>").unwrap();
+        static ref VERSION_RE: Regex = Regex::new(r"<Version>[^<]*</Version>").unwrap();
+    }
+
+    let mut did_anything = false;
+    let new_version = format!("<Version>{}</Version>", version);
+
+    let new_text = PROPERTY_GROUP_RE.replace_all(text, |caps: &regex::Captures| {
+        let group = &caps[0];
+
+        if VERSION_RE.is_match(group) {
+            did_anything = true;
+        }
+
+        VERSION_RE
+            .replace_all(group, new_version.as_str())
+            .into_owned()
+    });
+
+    if did_anything {
+        Some(new_text.into_owned())
+    } else {
+        None
+    }
+}
+
+impl Rewriter for MsBuildVersionRewriter {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let file_path = ctx.resolve_workdir(&self.path);
+        let _lock = ctx.lock_path(&self.path);
+        let proj = ctx.graph().lookup(self.proj_ids[0]);
+        let version = proj.version.to_string();
+
+        for other_id in &self.proj_ids[1..] {
+            let other = ctx.graph().lookup(*other_id);
+
+            if other.version != proj.version {
+                let mut ar = AnnotatedReport::default();
+                ar.set_message(format!(
+                    "projects `{}` and `{}` need different versions ({} and {}), but both get their version from `{}`",
+                    proj.user_facing_name,
+                    other.user_facing_name,
+                    proj.version,
+                    other.version,
+                    file_path.display()
+                ));
+                ar.add_note(
+                    "projects that share a version in a `Directory.Build.props` file must always be released together".to_owned()
+                );
+                return Err(ar.into());
+            }
+        }
+
+        let text = atry!(
+            fs::read_to_string(&file_path);
+            ["failed to read file `{}`", file_path.display()]
+        );
+
+        let new_text = match replace_msbuild_version(&text, &version) {
+            Some(t) => t,
+            None => {
+                warn!(
+                    "rewriter for MSBuild file `{}` didn't make any modifications",
+                    file_path.display()
+                );
+                return Ok(());
+            }
+        };
+
+        if new_text == text {
+            return Ok(());
+        }
+
+        atry!(
+            fs::write(&file_path, new_text.as_bytes());
+            ["failed to overwrite file `{}`", file_path.display()]
+        );
+        changes.add_path(&self.path);
+        Ok(())
+    }
+}
+
 /// Rewrite `AssemblyInfo.cs` to include real version numbers.
 #[derive(Debug)]
 pub struct AssemblyInfoCsRewriter {
@@ -451,9 +741,10 @@ impl AssemblyInfoCsRewriter {
 }
 
 impl Rewriter for AssemblyInfoCsRewriter {
-    fn rewrite(&self, app: &AppSession, changes: &mut ChangeList) -> Result<()> {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
         let mut did_anything = false;
-        let file_path = app.repo.resolve_workdir(&self.cs_path);
+        let file_path = ctx.resolve_workdir(&self.cs_path);
+        let _lock = ctx.lock_path(&self.cs_path);
 
         let cur_f = atry!(
             File::open(&file_path);
@@ -461,14 +752,18 @@ impl Rewriter for AssemblyInfoCsRewriter {
         );
         let cur_reader = BufReader::new(cur_f);
 
+        let ending = LineEnding::for_file(&file_path)?;
+
         let new_af = atomicwrites::AtomicFile::new(
             &file_path,
             atomicwrites::OverwriteBehavior::AllowOverwrite,
         );
 
-        let proj = app.graph().lookup(self.proj_id);
+        let proj = ctx.graph().lookup(self.proj_id);
 
         let r = new_af.write(|new_f| {
+            let mut new_f = LineEndingWriter::new(new_f, ending);
+
             for line in cur_reader.lines() {
                 let line = atry!(
                     line;
@@ -486,7 +781,7 @@ impl Rewriter for AssemblyInfoCsRewriter {
                 };
 
                 atry!(
-                    write_crlf!(new_f, "{}", line);
+                    writeln!(new_f, "{}", line);
                     ["error writing data to `{}`", new_af.path().display()]
                 );
             }
@@ -530,9 +825,10 @@ impl VdprojRewriter {
 }
 
 impl Rewriter for VdprojRewriter {
-    fn rewrite(&self, app: &AppSession, changes: &mut ChangeList) -> Result<()> {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
         let mut did_anything = false;
-        let file_path = app.repo.resolve_workdir(&self.vdproj_path);
+        let file_path = ctx.resolve_workdir(&self.vdproj_path);
+        let _lock = ctx.lock_path(&self.vdproj_path);
 
         let cur_f = atry!(
             File::open(&file_path);
@@ -540,12 +836,14 @@ impl Rewriter for VdprojRewriter {
         );
         let cur_reader = BufReader::new(cur_f);
 
+        let ending = LineEnding::for_file(&file_path)?;
+
         let new_af = atomicwrites::AtomicFile::new(
             &file_path,
             atomicwrites::OverwriteBehavior::AllowOverwrite,
         );
 
-        let proj = app.graph().lookup(self.proj_id);
+        let proj = ctx.graph().lookup(self.proj_id);
 
         let mut pcode = uuid::Uuid::new_v4().hyphenated().to_string();
         pcode.make_ascii_uppercase();
@@ -557,6 +855,8 @@ impl Rewriter for VdprojRewriter {
         );
 
         let r = new_af.write(|new_f| {
+            let mut new_f = LineEndingWriter::new(new_f, ending);
+
             let mut seen_product = false;
 
             for line in cur_reader.lines() {
@@ -600,7 +900,7 @@ impl Rewriter for VdprojRewriter {
                 };
 
                 atry!(
-                    write_crlf!(new_f, "{}", line);
+                    writeln!(new_f, "{}", line);
                     ["error writing data to `{}`", new_af.path().display()]
                 );
             }
@@ -652,7 +952,7 @@ pub fn extract_braced_text(line: &str) -> Result<&str> {
 
 /// This function works on vdproj ProductVersion lines that look like:
 ///
-/// ```
+/// ```text
 ///         "ProductVersion" = "8:6.0.13"
 /// ```
 ///
@@ -676,3 +976,179 @@ pub fn replace_vdproj_text(line: &str, new_val: &str) -> Result<String> {
     replaced.push_str(&line[right_idx..]);
     Ok(replaced)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write *text* to a scratch file and read its MSBuild `<Version>`.
+    fn msbuild_version(name: &str, text: &str) -> Option<String> {
+        let path = std::env::temp_dir().join(format!(
+            "cranko-msbuild-{}-test-{}.xml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, text).unwrap();
+        let version = read_msbuild_version(&path);
+        let _ignored = fs::remove_file(&path);
+        version.unwrap()
+    }
+
+    #[test]
+    fn msbuild_version_properties() {
+        let csproj = r#"<Project Sdk="Microsoft.NET.Sdk">
+  <PropertyGroup>
+    <Version>1.0.0</Version>
+  </PropertyGroup>
+  <ItemGroup>
+    <PackageReference Include="Foo">
+      <Version>9.9.9</Version>
+    </PackageReference>
+  </ItemGroup>
+  <PropertyGroup Condition="'$(Configuration)' == 'Release'">
+    <Version> 1.2.0 </Version>
+  </PropertyGroup>
+</Project>
+"#;
+
+        assert_eq!(msbuild_version("last", csproj).as_deref(), Some("1.2.0"));
+
+        let no_version = r#"<Project Sdk="Microsoft.NET.Sdk">
+  <ItemGroup>
+    <PackageReference Include="Foo" Version="2.0.0" />
+    <PackageReference Include="Bar">
+      <Version>9.9.9</Version>
+    </PackageReference>
+  </ItemGroup>
+</Project>
+"#;
+
+        assert_eq!(msbuild_version("none", no_version), None);
+
+        let expr =
+            "<Project><PropertyGroup><Version>$(VersionPrefix)</Version></PropertyGroup></Project>";
+        let text = msbuild_version("expr", expr).unwrap();
+        assert!(parse_version_text(&text, false).is_err());
+        assert!(parse_version_text(&text, true).is_err());
+    }
+
+    #[test]
+    fn build_props_fallback() {
+        let mut loader = CsProjLoader::default();
+        loader.build_props.insert(
+            RepoPathBuf::new(b""),
+            RepoPathBuf::new(b"Directory.Build.props"),
+        );
+        loader.build_props.insert(
+            RepoPathBuf::new(b"src/"),
+            RepoPathBuf::new(b"src/Directory.Build.props"),
+        );
+
+        fn find(loader: &CsProjLoader, dir: &[u8]) -> Option<String> {
+            loader
+                .find_build_props(&RepoPathBuf::new(dir))
+                .map(|p| p.escaped())
+        }
+
+        assert_eq!(
+            find(&loader, b"src/Lib/").as_deref(),
+            Some("src/Directory.Build.props")
+        );
+        assert_eq!(
+            find(&loader, b"src/").as_deref(),
+            Some("src/Directory.Build.props")
+        );
+        assert_eq!(
+            find(&loader, b"tools/App/").as_deref(),
+            Some("Directory.Build.props")
+        );
+        assert_eq!(find(&loader, b"").as_deref(), Some("Directory.Build.props"));
+
+        loader.build_props.remove(&RepoPathBuf::new(b""));
+        assert_eq!(find(&loader, b"tools/App/"), None);
+    }
+
+    #[test]
+    fn msbuild_version_rewriting() {
+        let text = r#"<Project>
+  <PropertyGroup>
+    <Version>1.0.0</Version>
+    <Authors>Me</Authors>
+  </PropertyGroup>
+  <ItemGroup>
+    <PackageReference Include="Foo">
+      <Version>9.9.9</Version>
+    </PackageReference>
+  </ItemGroup>
+  <PropertyGroup Label="Later">
+    <Version>1.0.1</Version>
+  </PropertyGroup>
+</Project>
+"#;
+
+        let new_text = replace_msbuild_version(text, "2.0.0").unwrap();
+        assert_eq!(new_text.matches("<Version>2.0.0</Version>").count(), 2);
+        assert!(new_text.contains("<Version>9.9.9</Version>"));
+        assert!(new_text.contains("<Authors>Me</Authors>"));
+
+        assert_eq!(
+            replace_msbuild_version("<Project><ItemGroup /></Project>", "2.0.0"),
+            None
+        );
+    }
+
+    #[test]
+    fn shared_build_props() {
+        use crate::{config::NpmConfiguration, repository::test_util::ScratchRepo};
+
+        let mut scratch = ScratchRepo::new("build-props");
+        let paths = ["Directory.Build.props", "src/A/A.csproj", "src/B/B.csproj"];
+
+        scratch.write(
+            paths[0],
+            "<Project>\n  <PropertyGroup>\n    <Version>1.0.0</Version>\n  </PropertyGroup>\n</Project>\n",
+        );
+        scratch.write(paths[1], "<Project Sdk=\"Microsoft.NET.Sdk\"></Project>\n");
+        scratch.write(paths[2], "<Project Sdk=\"Microsoft.NET.Sdk\"></Project>\n");
+
+        let mut app = AppBuilder::new_for_testing(scratch.repo.take().unwrap());
+        let mut loader = CsProjLoader::default();
+
+        for path in &paths {
+            let p = RepoPathBuf::new(path.as_bytes());
+            let (dirname, basename) = p.split_basename();
+            loader
+                .process_index_item(&app.repo, &p, dirname, basename)
+                .unwrap();
+        }
+
+        loader.finalize(&mut app, &HashMap::new()).unwrap();
+        let mut graph = app.graph.complete_loading().unwrap();
+        let a = graph.lookup_ident("A").unwrap();
+        let b = graph.lookup_ident("B").unwrap();
+
+        for ident in [a, b] {
+            graph.lookup_mut(ident).version = Version::DotNet("2.0.0".parse().unwrap());
+        }
+
+        // Rewriting just the second project updates the shared file, and
+        // rewriting the first one afterwards leaves it alone.
+
+        let npm_config = NpmConfiguration::default();
+        let ctx = RewriteContext::new(scratch.dir.clone(), &graph, &npm_config);
+        let rewrite = |ident| {
+            let mut changes = ChangeList::default();
+
+            for rw in &graph.lookup(ident).rewriters {
+                rw.rewrite(&ctx, &mut changes).unwrap();
+            }
+
+            changes.paths().count()
+        };
+
+        assert_eq!(rewrite(b), 1);
+        let text = std::fs::read_to_string(scratch.dir.join(paths[0])).unwrap();
+        assert!(text.contains("<Version>2.0.0</Version>"));
+        assert_eq!(rewrite(a), 0);
+    }
+}
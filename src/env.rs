@@ -3,10 +3,10 @@
 
 //! Helpers for environment variables.
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use std::env;
 
-use crate::errors::Result;
+use crate::{atry, errors::Result};
 
 /// Get an optional environment variable as a string.
 ///
@@ -38,3 +38,48 @@ pub fn maybe_var(key: &str) -> Result<Option<String>> {
 pub fn require_var(key: &str) -> Result<String> {
     maybe_var(key)?.ok_or_else(|| anyhow!("environment variable {} must be provided", key))
 }
+
+/// Require a secret, such as an API token, from the environment.
+///
+/// The secret is taken from the environment variable *key* if it is set.
+/// Otherwise, if the variable `{key}_FILE` is set, it is taken to be the path
+/// of a file containing the secret, as is common when secrets are mounted
+/// into containers. Leading and trailing whitespace in the file is ignored.
+pub fn require_secret(key: &str) -> Result<String> {
+    if let Some(s) = maybe_var(key)? {
+        return Ok(s);
+    }
+
+    let file_key = format!("{}_FILE", key);
+
+    let path = maybe_var(&file_key)?.ok_or_else(|| {
+        anyhow!(
+            "environment variable {} (or {}) must be provided",
+            key,
+            file_key
+        )
+    })?;
+
+    let text = atry!(
+        std::fs::read_to_string(&path);
+        ["failed to read the file `{}` named by environment variable {}", path, file_key]
+    );
+
+    let text = text.trim();
+
+    if text.is_empty() {
+        bail!(
+            "the file `{}` named by environment variable {} is empty",
+            path,
+            file_key
+        );
+    }
+
+    Ok(text.to_owned())
+}
+
+/// Determine whether a secret is available in the environment, either
+/// directly or through a `{key}_FILE` variable.
+pub fn secret_is_set(key: &str) -> bool {
+    env::var_os(key).is_some() || env::var_os(format!("{}_FILE", key)).is_some()
+}
@@ -3,18 +3,19 @@
 
 //! Release automation utilities related to the GitHub service.
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use json::{object, JsonValue};
 use log::{error, info, warn};
-use std::{fs::File, path::PathBuf};
+use std::{cell::Cell, fs::File, path::PathBuf};
 use structopt::StructOpt;
 
 use super::Command;
 use crate::{
     app::{AppBuilder, AppSession},
-    env::require_var,
+    atry,
+    env::{maybe_var, require_var},
     errors::Result,
-    graph,
+    graph, http,
     project::Project,
     repository::{CommitId, ReleasedProjectInfo},
 };
@@ -115,9 +116,10 @@ impl GitHubInformation {
         sess: &AppSession,
         proj: &Project,
         rel: &ReleasedProjectInfo,
+        release_commit: Option<CommitId>,
         client: &mut reqwest::blocking::Client,
     ) -> Result<JsonValue> {
-        let tag_name = sess.repo.get_tag_name(proj, rel)?;
+        let tag_name = sess.repo.get_tag_name(proj, rel, release_commit)?;
         self.get_custom_release_metadata(&tag_name, client)
     }
 
@@ -169,13 +171,52 @@ impl GitHubInformation {
         cid: &CommitId,
         client: &mut reqwest::blocking::Client,
     ) -> Result<JsonValue> {
-        let tag_name = sess.repo.get_tag_name(proj, rel)?;
+        let tag_name = sess.repo.get_tag_name(proj, rel, Some(*cid))?;
         let release_name = format!("{} {}", proj.user_facing_name, proj.version);
         let changelog = proj.changelog.scan_changelog(proj, &sess.repo, cid)?;
         self.create_custom_release(tag_name, release_name, changelog, false, false, client)
     }
 }
 
+/// Set a GitHub Actions step output by appending to the file named by the
+/// `$GITHUB_OUTPUT` environment variable.
+///
+/// Returns false, without doing anything, if that variable isn't set, which
+/// indicates that we're not running in a GitHub Actions step.
+pub fn append_actions_output(name: &str, value: &str) -> Result<bool> {
+    use std::{fs::OpenOptions, io::Write};
+
+    let path = match maybe_var("GITHUB_OUTPUT")? {
+        Some(p) => p,
+        None => return Ok(false),
+    };
+
+    // Multi-line values need the "heredoc" syntax. The delimiter just needs to
+    // not appear in the value.
+    let text = if value.contains('\n') {
+        let mut delim = "CRANKO_EOF".to_owned();
+
+        while value.contains(&delim) {
+            delim.push('_');
+        }
+
+        format!("{}<<{}\n{}\n{}\n", name, delim, value, delim)
+    } else {
+        format!("{}={}\n", name, value)
+    };
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .with_context(|| format!("cannot open GitHub Actions output file `{}`", path))?;
+
+    file.write_all(text.as_bytes())
+        .with_context(|| format!("cannot write to GitHub Actions output file `{}`", path))?;
+
+    Ok(true)
+}
+
 /// The `github` subcommands.
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub enum GithubCommands {
@@ -406,6 +447,13 @@ impl Command for InstallCredentialHelperCommand {
 /// Upload one or more artifact files to a GitHub release.
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct UploadArtifactsCommand {
+    #[structopt(
+        short = "f",
+        long = "force",
+        help = "Force operation even in unexpected conditions"
+    )]
+    force: bool,
+
     #[structopt(
         long = "overwrite",
         help = "Overwrite artifacts if they already exist in the release (default: error out)"
@@ -434,9 +482,18 @@ impl Command for UploadArtifactsCommand {
         let mut metadata = if self.by_tag {
             info.get_custom_release_metadata(&self.proj_name, &mut client)
         } else {
-            let rel_info = sess.repo.parse_release_info_from_head().context(
-                "expected Cranko release metadata in the HEAD commit but could not load it",
-            )?;
+            let (dev_mode, rel_info) = sess.ensure_ci_release_mode()?;
+
+            if dev_mode {
+                if self.force {
+                    warn!(
+                        "should not upload artifacts in development mode, but you're forcing me to"
+                    );
+                } else {
+                    error!("do not upload artifacts in development mode");
+                    bail!("refusing to proceed (use `--force` to override)",);
+                }
+            }
 
             let ident = sess
                 .graph()
@@ -455,7 +512,7 @@ impl Command for UploadArtifactsCommand {
             // Get information about the release
 
             let proj = sess.graph().lookup(ident);
-            info.get_release_metadata(&sess, proj, rel, &mut client)
+            info.get_release_metadata(&sess, proj, rel, rel_info.commit, &mut client)
         }?;
 
         let upload_url = metadata["upload_url"]
@@ -472,8 +529,11 @@ impl Command for UploadArtifactsCommand {
         // Upload artifacts
 
         for path in &self.paths {
-            // Make sure the file exists!
-            let file = File::open(path)?;
+            // Make sure the file exists! It is reopened for each upload attempt.
+            atry!(
+                File::open(path);
+                ["failed to open file `{}`", path.display()]
+            );
 
             let name = path
                 .file_name()
@@ -495,7 +555,13 @@ impl Command for UploadArtifactsCommand {
 
                         let del_url =
                             info.api_url(&format!("releases/assets/{}", asset_info["id"]));
-                        let resp = client.delete(&del_url).send()?;
+                        let resp = http::send_with_retry(
+                            "GitHub",
+                            "delete asset",
+                            http::DEFAULT_MAX_ATTEMPTS,
+                            true,
+                            || client.delete(&del_url),
+                        )?;
                         let status = resp.status();
 
                         if !status.is_success() {
@@ -508,20 +574,59 @@ impl Command for UploadArtifactsCommand {
 
             // Ready to upload now.
 
-            info!("uploading {} => {}", path.display(), name);
+            let content_type = http::guess_content_type(path);
+            info!(
+                "uploading {} => {} ({})",
+                path.display(),
+                name,
+                content_type
+            );
             let url = reqwest::Url::parse_with_params(&upload_url, &[("name", &name)])?;
-            let resp = client
-                .post(url)
-                .header(
-                    reqwest::header::ACCEPT,
-                    "application/vnd.github.manifold-preview",
-                )
-                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
-                .body(file)
-                .send()?;
+
+            // Asset names are unique within a release, so retrying an upload
+            // can't create a duplicate, even if an earlier attempt actually
+            // went through.
+            let attempts = Cell::new(0);
+            let resp = http::try_send_with_retry(
+                "GitHub",
+                "upload asset",
+                http::DEFAULT_MAX_ATTEMPTS,
+                true,
+                || {
+                    attempts.set(attempts.get() + 1);
+                    let file = atry!(
+                        File::open(path);
+                        ["failed to open file `{}`", path.display()]
+                    );
+
+                    Ok(client
+                        .post(url.clone())
+                        .header(
+                            reqwest::header::ACCEPT,
+                            "application/vnd.github.manifold-preview",
+                        )
+                        .header(reqwest::header::CONTENT_TYPE, content_type)
+                        .body(file))
+                },
+            )?;
             let status = resp.status();
             let mut parsed = json::parse(&resp.text()?)?;
 
+            // If an earlier attempt timed out but was actually processed, the
+            // retry will be rejected because the asset exists.
+            if attempts.get() > 1
+                && status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+                && parsed["errors"]
+                    .members()
+                    .any(|e| e["code"].as_str() == Some("already_exists"))
+            {
+                warn!(
+                    "asset {} already exists; assuming that an earlier upload attempt succeeded",
+                    name
+                );
+                continue;
+            }
+
             if !status.is_success() {
                 error!("API response: {}", parsed);
                 return Err(anyhow!("creation of asset {} failed", name));
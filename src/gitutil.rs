@@ -3,12 +3,116 @@
 
 //! Utilities for Git.
 
-use anyhow::Context;
-use std::path::PathBuf;
+use anyhow::{bail, Context};
+use log::{info, warn};
+use std::{path::PathBuf, process};
 use structopt::StructOpt;
 
 use super::Command;
-use crate::errors::Result;
+use crate::{
+    app::AppSession,
+    atry,
+    errors::{AnnotatedReport, Result},
+};
+
+/// Report or delete `rc` branch references superseded by releases.
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+pub struct PruneRcCommand {
+    #[structopt(
+        short = "f",
+        long = "force",
+        help = "Actually delete the references, rather than just reporting them"
+    )]
+    force: bool,
+
+    #[structopt(
+        long = "remote",
+        help = "Also delete the `rc` branch in the upstream repository"
+    )]
+    remote: bool,
+}
+
+impl Command for PruneRcCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = AppSession::initialize_default()?;
+
+        let superseded = atry!(
+            sess.repo.find_superseded_rc_refs();
+            ["failed to search for superseded `rc` references"]
+        );
+
+        if superseded.is_empty() {
+            info!("no superseded `rc` references found");
+            return Ok(0);
+        }
+
+        // Don't pull the rug out from under the working tree. Check this before
+        // deleting anything so that we don't half-finish the job.
+
+        if self.force {
+            if let Some(head_ref) = sess.repo.head_ref_name()? {
+                if superseded.iter().any(|(n, _)| n == &head_ref) {
+                    let mut ar = AnnotatedReport::default();
+                    ar.set_message(format!(
+                        "refusing to delete `{}`: it is currently checked out",
+                        head_ref
+                    ));
+                    ar.add_note("check out a different branch and try again".to_owned());
+                    return Err(ar.into());
+                }
+            }
+        }
+
+        for (ref_name, cid) in &superseded {
+            if self.force {
+                atry!(
+                    sess.repo.delete_rc_ref(ref_name);
+                    ["failed to delete reference `{}`", ref_name]
+                );
+                println!("deleted {} (was {})", ref_name, cid);
+            } else {
+                println!("would delete {} ({})", ref_name, cid);
+            }
+        }
+
+        // The remote-tracking reference tells us whether the upstream branch
+        // itself is superseded.
+
+        let remote_ref_name = format!(
+            "refs/remotes/{}/{}",
+            sess.repo.upstream_name(),
+            sess.repo.upstream_rc_name()
+        );
+
+        if self.remote && superseded.iter().any(|(n, _)| n == &remote_ref_name) {
+            if self.force {
+                let status = process::Command::new("git")
+                    .arg("push")
+                    .arg(sess.repo.upstream_name())
+                    .arg("--delete")
+                    .arg(sess.repo.upstream_rc_name())
+                    .status()
+                    .context("failed to run `git push`")?;
+
+                if !status.success() {
+                    bail!("failed to delete the `rc` branch in the upstream repository");
+                }
+            } else {
+                println!(
+                    "would delete branch `{}` in remote `{}`",
+                    sess.repo.upstream_rc_name(),
+                    sess.repo.upstream_name()
+                );
+            }
+        }
+
+        if !self.force {
+            warn!("dry run: no references were deleted (use `--force` to delete them)");
+        }
+
+        Ok(0)
+    }
+}
 
 /// Force-create an ancestor-less branch containing a directory tree.
 #[derive(Debug, Eq, PartialEq, StructOpt)]
@@ -71,6 +175,10 @@ impl Command for RebootBranchCommand {
 
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub enum GitUtilCommands {
+    #[structopt(name = "prune-rc")]
+    /// Report or delete `rc` branch references superseded by releases
+    PruneRc(PruneRcCommand),
+
     #[structopt(name = "reboot-branch")]
     /// Force-create an ancestor-less branch
     RebootBranch(RebootBranchCommand),
@@ -85,6 +193,7 @@ pub struct GitUtilCommand {
 impl Command for GitUtilCommand {
     fn execute(self) -> Result<i32> {
         match self.command {
+            GitUtilCommands::PruneRc(o) => o.execute(),
             GitUtilCommands::RebootBranch(o) => o.execute(),
         }
     }
@@ -9,11 +9,17 @@
 //! depend on each other. In the general case, these intra-repository
 //! dependencies have the structure of a directed acyclic graph (DAG).
 
+use anyhow::bail;
+use log::{debug, warn};
 use petgraph::{
     algo::toposort,
     graph::{DefaultIx, DiGraph, NodeIndex},
+    Direction,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
 };
-use std::collections::{HashMap, HashSet};
 use thiserror::Error as ThisError;
 
 use crate::{
@@ -24,7 +30,8 @@ use crate::{
         DepRequirement, Dependency, DependencyBuilder, DependencyTarget, Project, ProjectBuilder,
         ProjectId,
     },
-    repository::{ReleaseCommitInfo, RepoHistory, Repository},
+    repository::{CommitId, ReleaseCommitInfo, RepoHistory, Repository},
+    version::Version,
 };
 
 type OurNodeIndex = NodeIndex<DefaultIx>;
@@ -45,6 +52,16 @@ pub struct ProjectGraph {
 
     /// Project IDs in a topologically sorted order.
     toposorted_ids: Vec<ProjectId>,
+
+    /// For each project, the IDs of the projects that directly depend on it,
+    /// in topologically sorted order. This is the inverse of the projects'
+    /// `internal_deps` and is calculated in the complete_loading() method.
+    dependents: Vec<Vec<ProjectId>>,
+
+    /// The full qualified names, in the "type:name" form used by the
+    /// configuration file, of every project detected during loading,
+    /// including ones that were ignored.
+    detected_names: HashSet<String>,
 }
 
 /// An error returned when an input has requested a project with a certain name,
@@ -59,11 +76,24 @@ impl ProjectGraph {
         &self.projects[ident]
     }
 
+    /// Determine whether a name in the "type:name" form used by the
+    /// configuration file matches a project detected during loading. Ignored
+    /// projects count as detected.
+    pub fn was_detected(&self, full_name: &str) -> bool {
+        self.detected_names.contains(full_name)
+    }
+
     /// Get a mutable reference to a project in the graph from its ID.
     pub fn lookup_mut(&mut self, ident: ProjectId) -> &mut Project {
         &mut self.projects[ident]
     }
 
+    /// Get the IDs of the projects that directly depend on the specified
+    /// project, in topologically sorted order.
+    pub fn dependents_of(&self, ident: ProjectId) -> Vec<ProjectId> {
+        self.dependents[ident].clone()
+    }
+
     /// Get a project ID from its user-facing name.
     ///
     /// None indicates that the name is not found.
@@ -141,11 +171,26 @@ impl ProjectGraph {
             root_idents
         };
 
+        let mut excluded_ids = HashSet::new();
+
+        for name in &query.exclude_names {
+            if let Some(id) = self.name_to_id.get(name) {
+                excluded_ids.insert(*id);
+            } else {
+                warn!("cannot exclude project `{}`: no such project", name);
+            }
+        }
+
         // Apply filters and deduplicate if needed
 
         for id in root_idents {
             let proj = &self.projects[id];
 
+            // exclude_names() filter
+            if excluded_ids.contains(&id) {
+                continue;
+            }
+
             // only_new_releases() filter
             if let Some(ref rel_info) = query.release_info {
                 if rel_info.lookup_if_released(proj).is_none() {
@@ -176,13 +221,53 @@ impl ProjectGraph {
         Ok(matched_idents)
     }
 
-    pub fn analyze_histories(&self, repo: &Repository) -> Result<RepoHistories> {
+    /// Compute the version of a meta-project from the current versions of the
+    /// projects that it aggregates: the highest one of them.
+    ///
+    /// Returns None if the project is not a meta-project.
+    pub fn aggregate_version(&self, ident: ProjectId) -> Result<Option<Version>> {
+        aggregate_version(&self.projects, ident)
+    }
+
+    pub fn analyze_histories(
+        &self,
+        repo: &Repository,
+        since: Option<CommitId>,
+        ignore_whitespace: bool,
+    ) -> Result<RepoHistories> {
         Ok(RepoHistories {
-            histories: repo.analyze_histories(&self.projects[..])?,
+            histories: repo.analyze_histories(&self.projects[..], since, ignore_whitespace)?,
         })
     }
 }
 
+/// The implementation of [`ProjectGraph::aggregate_version`], usable while
+/// the graph is still being constructed.
+fn aggregate_version(projects: &[Project], ident: ProjectId) -> Result<Option<Version>> {
+    let proj = &projects[ident];
+    let mut max: Option<&Version> = None;
+
+    for member_ident in &proj.aggregates {
+        let member = &projects[*member_ident];
+
+        if member.version.scheme() != proj.version.scheme() {
+            bail!(
+                "meta-project `{}` uses the `{}` version scheme, but its member `{}` uses `{}`",
+                proj.user_facing_name,
+                proj.version.scheme(),
+                member.user_facing_name,
+                member.version.scheme()
+            );
+        }
+
+        if max.map(|v| member.version > *v).unwrap_or(true) {
+            max = Some(&member.version);
+        }
+    }
+
+    Ok(max.cloned())
+}
+
 /// This type is how we "launder" the knowledge that the vector that
 /// comes out of repo.analyze_histories can be mapped into ProjectId values.
 #[derive(Clone, Debug)]
@@ -205,6 +290,7 @@ impl RepoHistories {
 #[derive(Debug, Default)]
 pub struct GraphQueryBuilder {
     names: Vec<String>,
+    exclude_names: Vec<String>,
     release_info: Option<ReleaseCommitInfo>,
     project_type: Option<String>,
 }
@@ -218,6 +304,19 @@ impl GraphQueryBuilder {
         self
     }
 
+    /// Specify project names that should be excluded from the query results.
+    ///
+    /// Unlike the names passed to [`Self::names`], it is not an error if these
+    /// do not correspond to any known project, although a warning will be
+    /// issued.
+    pub fn exclude_names<T: std::fmt::Display>(
+        &mut self,
+        names: impl IntoIterator<Item = T>,
+    ) -> &mut Self {
+        self.exclude_names = names.into_iter().map(|s| s.to_string()).collect();
+        self
+    }
+
     /// Specify that only projects released in the associated info should be
     /// matched.
     pub fn only_new_releases(&mut self, rel_info: ReleaseCommitInfo) -> &mut Self {
@@ -252,6 +351,9 @@ pub struct ProjectGraphBuilder {
 
     /// The `petgraph` state expressing the project graph.
     graph: DiGraph<ProjectId, ()>,
+
+    /// The full qualified names of every project passed to try_add_project().
+    detected_names: HashSet<String>,
 }
 
 /// An error returned when the internal project graph has a dependency cycle.
@@ -273,6 +375,7 @@ impl ProjectGraphBuilder {
             projects: Vec::new(),
             node_ixs: Vec::new(),
             graph: DiGraph::default(),
+            detected_names: HashSet::new(),
         }
     }
 
@@ -298,16 +401,25 @@ impl ProjectGraphBuilder {
             full_name.push_str(term);
         }
 
-        let ignore = pconfig
-            .get(&full_name)
-            .map(|c| c.ignore)
-            .unwrap_or_default();
-        if ignore {
+        let cfg = pconfig.get(&full_name);
+        self.detected_names.insert(full_name);
+
+        if cfg.map(|c| c.ignore).unwrap_or_default() {
             return None;
         }
 
         let mut pbuilder = ProjectBuilder::new();
         pbuilder.qnames = qnames;
+        pbuilder.version_scheme = cfg.and_then(|c| c.version_scheme.clone());
+        pbuilder.changelog_format = cfg.and_then(|c| c.changelog_format.clone());
+        pbuilder.changelog_path = cfg.and_then(|c| c.changelog_path.clone());
+        pbuilder.changelog_fragments_dir = cfg.and_then(|c| c.changelog_fragments_dir.clone());
+        pbuilder.infer_bump = cfg.and_then(|c| c.infer_bump.clone());
+        pbuilder.dev_version = cfg.and_then(|c| c.dev_version.clone());
+        pbuilder.ignore_paths = cfg.map(|c| c.ignore_paths.clone()).unwrap_or_default();
+        pbuilder.extra_paths = cfg.map(|c| c.extra_paths.clone()).unwrap_or_default();
+        pbuilder.release_priority = cfg.and_then(|c| c.release_priority).unwrap_or_default();
+        pbuilder.replacements = cfg.map(|c| c.replacements.clone()).unwrap_or_default();
 
         let id = self.projects.len();
         self.projects.push(pbuilder);
@@ -513,10 +625,49 @@ impl ProjectGraphBuilder {
                 self.graph.add_edge(dependee_nix, depender_nix, ());
             }
 
-            let proj = proj_builder.finalize(ident, name, internal_deps)?;
+            // A meta-project is sorted after the projects that it aggregates,
+            // so that its version can be computed from theirs, but it
+            // doesn't formally depend on them.
+
+            let mut aggregates = Vec::new();
+
+            for member_name in proj_builder.aggregates.take().unwrap_or_default() {
+                let member_ident = *a_ok_or!(
+                    name_to_id.get(&member_name);
+                    ["meta-project `{}` aggregates an unrecognized project name: `{}`",
+                     name, member_name]
+                );
+
+                if member_ident == ident {
+                    bail!("meta-project `{}` cannot aggregate itself", name);
+                }
+
+                if !aggregates.contains(&member_ident) {
+                    aggregates.push(member_ident);
+                    let member_nix = self.node_ixs[member_ident];
+                    self.graph.add_edge(member_nix, depender_nix, ());
+                }
+            }
+
+            let proj = proj_builder.finalize(ident, name, internal_deps, aggregates)?;
             projects.push(proj);
         }
 
+        for proj in &projects {
+            if !proj.internal_deps.is_empty() {
+                let dep_names: Vec<_> = proj
+                    .internal_deps
+                    .iter()
+                    .map(|d| projects[d.ident].user_facing_name.as_str())
+                    .collect();
+                debug!(
+                    "project `{}` depends on: {}",
+                    proj.user_facing_name,
+                    dep_names.join(", ")
+                );
+            }
+        }
+
         // Now that we've done that and compiled all of the interdependencies,
         // we can verify that the graph has no cycles. We compute the
         // topological sorting once and just reuse it later.
@@ -529,11 +680,95 @@ impl ProjectGraphBuilder {
             ["the project graph contains a dependency cycle"]
         );
 
-        let toposorted_ids = sorted_nixs
+        // Layer the projects' release priorities on top of that sort. We redo
+        // the sort with Kahn's algorithm, always choosing the ready project
+        // with the lowest (priority, position in the basic sort). If no
+        // priorities are set, this reproduces the basic sort exactly.
+
+        let mut position = vec![0; projects.len()];
+
+        for (pos, node_ix) in sorted_nixs.iter().enumerate() {
+            position[self.graph[*node_ix]] = pos;
+        }
+
+        let mut n_pending_deps: Vec<usize> = self
+            .node_ixs
             .iter()
-            .map(|node_ix| self.graph[*node_ix])
+            .map(|nix| {
+                self.graph
+                    .neighbors_directed(*nix, Direction::Incoming)
+                    .count()
+            })
             .collect();
 
+        let mut ready = BinaryHeap::new();
+
+        for (ident, n) in n_pending_deps.iter().enumerate() {
+            if *n == 0 {
+                ready.push(Reverse((
+                    projects[ident].release_priority,
+                    position[ident],
+                    ident,
+                )));
+            }
+        }
+
+        let mut toposorted_ids = Vec::with_capacity(projects.len());
+
+        while let Some(Reverse((_, _, ident))) = ready.pop() {
+            toposorted_ids.push(ident);
+
+            for dependent_nix in self
+                .graph
+                .neighbors_directed(self.node_ixs[ident], Direction::Outgoing)
+            {
+                let dependent = self.graph[dependent_nix];
+                n_pending_deps[dependent] -= 1;
+
+                if n_pending_deps[dependent] == 0 {
+                    ready.push(Reverse((
+                        projects[dependent].release_priority,
+                        position[dependent],
+                        dependent,
+                    )));
+                }
+            }
+        }
+
+        // Now we can determine the versions of any meta-projects, which were
+        // just placeholders until now.
+
+        for ident in &toposorted_ids {
+            if let Some(version) = aggregate_version(&projects, *ident)? {
+                projects[*ident].version = version;
+            }
+        }
+
+        debug!(
+            "project processing order: {}",
+            toposorted_ids
+                .iter()
+                .map(|i| projects[*i].user_facing_name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        // Invert the dependency edges so that we can easily answer "who
+        // depends on this project?" Visiting the depender projects in sorted
+        // order keeps each list sorted too.
+
+        let mut dependents = vec![Vec::new(); projects.len()];
+
+        for ident in &toposorted_ids {
+            for dep in &projects[*ident].internal_deps {
+                let list: &mut Vec<ProjectId> = &mut dependents[dep.ident];
+
+                if !list.contains(ident) {
+                    list.push(*ident);
+                }
+            }
+        }
+
         // Another bit of housekeeping: by default we set things up so that
         // project's path matchers are partially disjoint. In particular, if
         // there is a project rooted in prefix "a/" and a project rooted in
@@ -562,6 +797,8 @@ impl ProjectGraphBuilder {
             name_to_id,
             graph: self.graph,
             toposorted_ids,
+            dependents,
+            detected_names: self.detected_names,
         })
     }
 }
@@ -725,4 +962,147 @@ mod tests {
         ])
         .unwrap();
     }
+
+    #[test]
+    fn query_exclude_names() {
+        let mut graph = ProjectGraphBuilder::new();
+        let empty_config = HashMap::new();
+
+        for name in &["A", "B", "C"] {
+            let projid = graph
+                .try_add_project(vec![(*name).to_owned()], &empty_config)
+                .unwrap();
+            let b = graph.lookup_mut(projid);
+            b.version = Some(Version::Semver(semver::Version::new(0, 0, 0)));
+            b.prefix = Some(RepoPathBuf::new(b""));
+        }
+
+        let graph = graph.complete_loading().unwrap();
+
+        let mut q = GraphQueryBuilder::default();
+        q.exclude_names(["B", "nonexistent"]);
+        let names: HashSet<_> = graph
+            .query(q)
+            .unwrap()
+            .into_iter()
+            .map(|id| graph.lookup(id).user_facing_name.clone())
+            .collect();
+        assert_eq!(names, ["A", "C"].iter().map(|s| s.to_string()).collect());
+    }
+
+    #[test]
+    fn dependents_of() {
+        let mut graph = ProjectGraphBuilder::new();
+        let empty_config = HashMap::new();
+        let mut ids = Vec::new();
+
+        for name in &["A", "B", "C"] {
+            let projid = graph
+                .try_add_project(vec![(*name).to_owned()], &empty_config)
+                .unwrap();
+            let b = graph.lookup_mut(projid);
+            b.version = Some(Version::Semver(semver::Version::new(0, 0, 0)));
+            b.prefix = Some(RepoPathBuf::new(b""));
+            ids.push(projid);
+        }
+
+        for (depender, dependee) in &[(1, 0), (2, 1), (2, 0)] {
+            graph.add_dependency(
+                ids[*depender],
+                DependencyTarget::Ident(ids[*dependee]),
+                "0.0.0".to_owned(),
+                DepRequirement::Manual("0.0.0".to_owned()),
+            );
+        }
+
+        let graph = graph.complete_loading().unwrap();
+        assert_eq!(graph.dependents_of(ids[0]), vec![ids[1], ids[2]]);
+        assert_eq!(graph.dependents_of(ids[1]), vec![ids[2]]);
+        assert!(graph.dependents_of(ids[2]).is_empty());
+    }
+
+    /// Build a graph from a list of `(name, version, aggregates)` tuples. A
+    /// project with nonempty `aggregates` is a meta-project.
+    fn build_meta_graph(spec: &[(&str, Version, &[&str])]) -> Result<ProjectGraph> {
+        let mut graph = ProjectGraphBuilder::new();
+        let empty_config = HashMap::new();
+
+        for (name, version, aggregates) in spec {
+            let projid = graph
+                .try_add_project(vec![(*name).to_owned()], &empty_config)
+                .unwrap();
+            let b = graph.lookup_mut(projid);
+            b.version = Some(version.clone());
+            b.prefix = Some(RepoPathBuf::new(b""));
+
+            if !aggregates.is_empty() {
+                b.aggregates = Some(aggregates.iter().map(|s| (*s).to_owned()).collect());
+            }
+        }
+
+        graph.complete_loading()
+    }
+
+    fn semver(text: &str) -> Version {
+        Version::Semver(semver::Version::parse(text).unwrap())
+    }
+
+    #[test]
+    fn meta_project_versions() {
+        let graph = build_meta_graph(&[
+            ("site", semver("0.0.0"), &["A", "B"]),
+            ("all", semver("0.0.0"), &["site", "C"]),
+            ("A", semver("1.10.0"), &[]),
+            ("B", semver("1.9.3"), &[]),
+            ("C", semver("1.2.0"), &[]),
+        ])
+        .unwrap();
+
+        let version_of = |name: &str| {
+            graph
+                .lookup(graph.lookup_ident(name).unwrap())
+                .version
+                .clone()
+        };
+        assert_eq!(version_of("site"), semver("1.10.0"));
+        assert_eq!(version_of("all"), semver("1.10.0"));
+
+        let a = graph.lookup_ident("A").unwrap();
+        let site = graph.lookup_ident("site").unwrap();
+        assert!(graph.lookup(site).is_meta());
+        assert!(!graph.lookup(a).is_meta());
+        assert_eq!(graph.aggregate_version(a).unwrap(), None);
+
+        // Meta-projects are processed after the projects they aggregate.
+        let order: Vec<_> = graph.toposorted().collect();
+        let pos = |name: &str| {
+            let ident = graph.lookup_ident(name).unwrap();
+            order.iter().position(|i| *i == ident).unwrap()
+        };
+        assert!(pos("A") < pos("site"));
+        assert!(pos("B") < pos("site"));
+        assert!(pos("site") < pos("all"));
+        assert!(pos("C") < pos("all"));
+    }
+
+    #[test]
+    fn meta_project_scheme_mismatch() {
+        let pep440 = Version::Pep440("1.0".parse().unwrap());
+        let err = build_meta_graph(&[
+            ("site", semver("0.0.0"), &["A", "B"]),
+            ("A", semver("1.0.0"), &[]),
+            ("B", pep440, &[]),
+        ])
+        .unwrap_err();
+
+        let msg = err.to_string();
+        assert!(msg.contains("`site`"), "{}", msg);
+        assert!(msg.contains("`B`"), "{}", msg);
+    }
+
+    #[test]
+    fn meta_project_bad_members() {
+        assert!(build_meta_graph(&[("site", semver("0.0.0"), &["nonexistent"])]).is_err());
+        assert!(build_meta_graph(&[("site", semver("0.0.0"), &["site"])]).is_err());
+    }
 }
@@ -0,0 +1,141 @@
+// Copyright 2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Utilities for talking to web service APIs.
+
+use log::warn;
+use std::{path::Path, thread, time};
+
+use crate::errors::Result;
+
+/// The default maximum number of attempts to make for an API request.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry of a failed API request, in seconds.
+/// Subsequent delays double.
+const INITIAL_RETRY_DELAY: u64 = 2;
+
+/// Send an API request, retrying with exponential backoff if it fails in a
+/// way that seems transient.
+///
+/// Since request bodies can't generally be cloned, *make_req* is called to
+/// build a fresh request for every attempt. If *idempotent* is false, the
+/// request is only retried if the connection to the server could not be
+/// established, since in any other case the previous attempt may have been
+/// processed. Otherwise, gateway errors (HTTP 502, 503, and 504), timeouts, and
+/// connection errors all lead to retries. *service* and *desc* are used in
+/// log messages.
+pub fn send_with_retry<F>(
+    service: &str,
+    desc: &str,
+    max_attempts: u32,
+    idempotent: bool,
+    make_req: F,
+) -> Result<reqwest::blocking::Response>
+where
+    F: Fn() -> reqwest::blocking::RequestBuilder,
+{
+    try_send_with_retry(service, desc, max_attempts, idempotent, || Ok(make_req()))
+}
+
+/// Like [`send_with_retry`], but building the request can fail, in which case
+/// the error is returned immediately. This is useful when each attempt needs
+/// to reopen a file for the request body.
+pub fn try_send_with_retry<F>(
+    service: &str,
+    desc: &str,
+    max_attempts: u32,
+    idempotent: bool,
+    make_req: F,
+) -> Result<reqwest::blocking::Response>
+where
+    F: Fn() -> Result<reqwest::blocking::RequestBuilder>,
+{
+    use reqwest::StatusCode;
+
+    let mut delay = time::Duration::from_secs(INITIAL_RETRY_DELAY);
+    let mut attempt = 1;
+
+    loop {
+        let retry_reason = match make_req()?.send() {
+            Ok(resp) => {
+                let status = resp.status();
+                let transient = status == StatusCode::BAD_GATEWAY
+                    || status == StatusCode::SERVICE_UNAVAILABLE
+                    || status == StatusCode::GATEWAY_TIMEOUT;
+
+                if !(idempotent && transient && attempt < max_attempts) {
+                    return Ok(resp);
+                }
+
+                format!("server responded with status {}", status)
+            }
+
+            Err(e) => {
+                let transient = e.is_connect() || (idempotent && e.is_timeout());
+
+                if !(transient && attempt < max_attempts) {
+                    return Err(e.into());
+                }
+
+                format!("{}", e)
+            }
+        };
+
+        warn!(
+            "{} request to {} failed (attempt {} of {}): {}",
+            service, desc, attempt, max_attempts, retry_reason
+        );
+        warn!("... retrying in {} seconds", delay.as_secs());
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Guess the MIME content type of a file to be uploaded, based on its name.
+///
+/// This only knows about the kinds of files that are commonly attached to
+/// software releases. Anything else is `application/octet-stream`.
+pub fn guess_content_type<P: AsRef<Path>>(path: P) -> &'static str {
+    let name = path
+        .as_ref()
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    if name == "sha256sums" {
+        return "text/plain";
+    }
+
+    let ext = match name.rsplit_once('.') {
+        Some((_, ext)) => ext,
+        None => return "application/octet-stream",
+    };
+
+    match ext {
+        "asc" | "sig" => "application/pgp-signature",
+        "bz2" => "application/x-bzip2",
+        "deb" => "application/vnd.debian.binary-package",
+        "dmg" => "application/x-apple-diskimage",
+        "exe" => "application/vnd.microsoft.portable-executable",
+        "gz" | "tgz" => "application/gzip",
+        "html" | "htm" => "text/html",
+        "jar" => "application/java-archive",
+        "json" => "application/json",
+        "md" => "text/markdown",
+        "msi" => "application/x-msi",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "rpm" => "application/x-rpm",
+        "sha256" | "sha512" | "txt" => "text/plain",
+        "svg" => "image/svg+xml",
+        "tar" => "application/x-tar",
+        "wasm" => "application/wasm",
+        "whl" | "zip" => "application/zip",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2020-2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Cranko as a library.
+//!
+//! The `cranko` command-line program is a thin layer on top of this crate. If
+//! you want to build your own tooling on top of Cranko's analysis of a
+//! repository — say, an editor integration that shows which projects have
+//! unreleased changes — you can use this crate directly rather than shelling
+//! out to the CLI.
+//!
+//! The entry point is [`AppSession`], which is usually obtained with
+//! [`AppSession::initialize_default`]. It gives access to the backing Git
+//! [`Repository`] and to the [`ProjectGraph`] of the projects found in it.
+//! Histories of the projects since their most recent releases can be obtained
+//! with [`AppSession::analyze_histories`], which returns a [`RepoHistories`]
+//! containing a [`RepoHistory`] for each project.
+//!
+//! # Stability
+//!
+//! Cranko is primarily a command-line tool, and its internals change as the
+//! tool evolves. The following types, re-exported at the crate root, and the
+//! modules that define them are considered the stable public API. Changes to
+//! them will be noted in the changelog and, once Cranko reaches 1.0, will follow
+//! semver.
+//!
+//! - [`AppBuilder`] and [`AppSession`] (module [`app`])
+//! - [`ProjectGraph`], [`GraphQueryBuilder`], and [`RepoHistories`] (module
+//!   [`graph`])
+//! - [`Project`] and [`ProjectId`] (module [`project`])
+//! - [`Repository`], [`RepoHistory`], [`CommitId`], [`ReleaseCommitInfo`],
+//!   [`RepoPath`], and [`RepoPathBuf`] (module [`repository`])
+//! - [`Version`] and [`VersionBumpScheme`] (module [`version`])
+//! - The error-handling helpers in the [`errors`] module
+//!
+//! Everything else — notably the modules implementing individual integrations
+//! and CLI subcommands, which are hidden from the documentation — is an
+//! implementation detail of the CLI and may change at any time.
+
+pub mod app;
+pub mod changelog;
+pub mod config;
+pub mod errors;
+pub mod graph;
+pub mod project;
+pub mod repository;
+pub mod version;
+
+#[doc(hidden)]
+pub mod bootstrap;
+#[doc(hidden)]
+pub mod cargo;
+#[doc(hidden)]
+pub mod csproj;
+#[doc(hidden)]
+pub mod env;
+#[doc(hidden)]
+pub mod github;
+#[doc(hidden)]
+pub mod gitutil;
+#[doc(hidden)]
+pub mod http;
+#[doc(hidden)]
+pub mod licenses;
+#[doc(hidden)]
+pub mod line_endings;
+#[doc(hidden)]
+pub mod lock;
+#[doc(hidden)]
+pub mod logger;
+#[doc(hidden)]
+pub mod maven;
+#[doc(hidden)]
+pub mod meta;
+#[doc(hidden)]
+pub mod npm;
+#[doc(hidden)]
+pub mod pypa;
+#[doc(hidden)]
+pub mod rewriters;
+#[doc(hidden)]
+pub mod zenodo;
+
+pub use app::{AppBuilder, AppSession};
+pub use graph::{GraphQueryBuilder, ProjectGraph, RepoHistories};
+pub use project::{Project, ProjectId};
+pub use repository::{CommitId, ReleaseCommitInfo, RepoHistory, RepoPath, RepoPathBuf, Repository};
+pub use version::{Version, VersionBumpScheme};
+
+/// A subcommand of the `cranko` CLI.
+///
+/// This is an implementation detail of the CLI and not part of the stable API.
+#[doc(hidden)]
+pub trait Command {
+    fn execute(self) -> errors::Result<i32>;
+}
@@ -0,0 +1,380 @@
+// Copyright 2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Checking the licenses of projects against those of their internal
+//! dependencies.
+//!
+//! In a monorepo it's easy to end up with, say, an MIT-licensed library that
+//! depends on a GPL-licensed one, which effectively makes the former GPL too.
+//! We classify each project's declared SPDX license expression into a broad
+//! category of restrictiveness and flag any dependency on a project that is
+//! more restrictive than its dependent. This is a heuristic sanity check, not
+//! legal advice.
+
+use log::{error, info, warn};
+use std::{collections::HashSet, fmt};
+
+use crate::{app::AppSession, project::ProjectId};
+
+/// A broad category of license restrictiveness, ordered from least to most
+/// restrictive.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum LicenseCategory {
+    /// Licenses like MIT, BSD, and Apache-2.0.
+    Permissive,
+
+    /// File- or library-level copyleft licenses like the LGPL and MPL.
+    WeakCopyleft,
+
+    /// Licenses like the GPL.
+    StrongCopyleft,
+
+    /// Licenses like the AGPL that also apply to network use.
+    NetworkCopyleft,
+}
+
+impl fmt::Display for LicenseCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LicenseCategory::Permissive => "permissive",
+            LicenseCategory::WeakCopyleft => "weak copyleft",
+            LicenseCategory::StrongCopyleft => "strong copyleft",
+            LicenseCategory::NetworkCopyleft => "network copyleft",
+        })
+    }
+}
+
+/// Known SPDX license identifiers, uppercased. A trailing `*` indicates a
+/// prefix match. Order matters: the first match wins.
+const KNOWN_LICENSES: &[(&str, LicenseCategory)] = &[
+    ("AGPL-*", LicenseCategory::NetworkCopyleft),
+    ("GPL-*", LicenseCategory::StrongCopyleft),
+    ("LGPL-*", LicenseCategory::WeakCopyleft),
+    ("MPL-*", LicenseCategory::WeakCopyleft),
+    ("EPL-*", LicenseCategory::WeakCopyleft),
+    ("CDDL-*", LicenseCategory::WeakCopyleft),
+    ("MS-RL", LicenseCategory::WeakCopyleft),
+    ("0BSD", LicenseCategory::Permissive),
+    ("APACHE-*", LicenseCategory::Permissive),
+    ("BSD-*", LicenseCategory::Permissive),
+    ("BSL-1.0", LicenseCategory::Permissive),
+    ("CC0-1.0", LicenseCategory::Permissive),
+    ("ISC", LicenseCategory::Permissive),
+    ("MIT", LicenseCategory::Permissive),
+    ("MIT-0", LicenseCategory::Permissive),
+    ("MS-PL", LicenseCategory::Permissive),
+    ("NCSA", LicenseCategory::Permissive),
+    ("PSF-2.0", LicenseCategory::Permissive),
+    ("PYTHON-2.0", LicenseCategory::Permissive),
+    ("UNICODE-*", LicenseCategory::Permissive),
+    ("UNLICENSE", LicenseCategory::Permissive),
+    ("UPL-1.0", LicenseCategory::Permissive),
+    ("WTFPL", LicenseCategory::Permissive),
+    ("X11", LicenseCategory::Permissive),
+    ("ZLIB", LicenseCategory::Permissive),
+];
+
+/// Classify a single SPDX license identifier.
+fn classify_identifier(id: &str) -> Option<LicenseCategory> {
+    // `GPL-2.0+` is the legacy spelling of `GPL-2.0-or-later`.
+    let id = id.trim_end_matches('+').to_ascii_uppercase();
+
+    for (pattern, category) in KNOWN_LICENSES {
+        let matched = match pattern.strip_suffix('*') {
+            Some(prefix) => id.starts_with(prefix),
+            None => id == *pattern,
+        };
+
+        if matched {
+            return Some(*category);
+        }
+    }
+
+    None
+}
+
+/// Classify an SPDX license expression.
+///
+/// A disjunction (`OR`, or the legacy `/` separator) is as restrictive as its
+/// most permissive alternative, since the recipient can choose among them. A
+/// conjunction (`AND`) is as restrictive as its most restrictive term. A `WITH`
+/// exception is ignored. Returns None if the expression can't be parsed or
+/// involves licenses that we don't know about.
+pub fn classify_expression(expr: &str) -> Option<LicenseCategory> {
+    let mut parser = ExprParser {
+        tokens: tokenize(expr),
+        pos: 0,
+    };
+
+    let result = parser.parse_or().ok()?;
+
+    if parser.pos != parser.tokens.len() {
+        return None;
+    }
+
+    result
+}
+
+fn tokenize(expr: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in expr.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' || c == '/' {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+
+            match c {
+                '(' | ')' => tokens.push(c.to_string()),
+                '/' => tokens.push("OR".to_owned()),
+                _ => {}
+            }
+        } else {
+            current.push(c);
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+/// A recursive-descent parser for SPDX expressions that evaluates their
+/// categories as it goes. The error type indicates a syntax error; the inner
+/// Option is None if the expression involves unknown licenses.
+struct ExprParser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.tokens
+            .get(self.pos)
+            .map(|t| t.eq_ignore_ascii_case(keyword))
+            .unwrap_or(false)
+    }
+
+    fn parse_or(&mut self) -> Result<Option<LicenseCategory>, ()> {
+        let mut alternatives = vec![self.parse_and()?];
+
+        while self.peek_keyword("OR") {
+            self.pos += 1;
+            alternatives.push(self.parse_and()?);
+        }
+
+        // If some alternatives are unknown, judge by the known ones.
+        Ok(alternatives.into_iter().flatten().min())
+    }
+
+    fn parse_and(&mut self) -> Result<Option<LicenseCategory>, ()> {
+        let mut result = self.parse_term()?;
+
+        while self.peek_keyword("AND") {
+            self.pos += 1;
+            let rhs = self.parse_term()?;
+            result = match (result, rhs) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                _ => None,
+            };
+        }
+
+        Ok(result)
+    }
+
+    fn parse_term(&mut self) -> Result<Option<LicenseCategory>, ()> {
+        let token = self.tokens.get(self.pos).ok_or(())?.clone();
+        self.pos += 1;
+
+        if token == "(" {
+            let result = self.parse_or()?;
+
+            if self.tokens.get(self.pos).map(|t| t.as_str()) != Some(")") {
+                return Err(());
+            }
+
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        if token == ")"
+            || token.eq_ignore_ascii_case("AND")
+            || token.eq_ignore_ascii_case("OR")
+            || token.eq_ignore_ascii_case("WITH")
+        {
+            return Err(());
+        }
+
+        if self.peek_keyword("WITH") {
+            self.pos += 1;
+
+            if self.tokens.get(self.pos).is_none() {
+                return Err(());
+            }
+
+            self.pos += 1;
+        }
+
+        Ok(classify_identifier(&token))
+    }
+}
+
+/// Check the licenses of the specified projects against those of their
+/// internal dependencies.
+///
+/// This prints a table of the projects involved and their licenses, then logs
+/// an error for each dependency on a more restrictive project. Projects
+/// without a declared license, or with one that we can't classify, elicit
+/// warnings but aren't treated as conflicts. Returns the number of conflicts
+/// found.
+pub fn check_licenses(sess: &AppSession, idents: &[ProjectId]) -> usize {
+    let graph = sess.graph();
+
+    // Gather the projects of interest and their dependencies, and report them
+    // in a consistent order.
+
+    let mut involved = HashSet::new();
+
+    for ident in idents {
+        involved.insert(*ident);
+
+        for dep in &graph.lookup(*ident).internal_deps {
+            involved.insert(dep.ident);
+        }
+    }
+
+    let rows = graph
+        .toposorted()
+        .filter(|ident| involved.contains(ident))
+        .map(|ident| {
+            let proj = graph.lookup(ident);
+            let license = proj.license.as_deref();
+            let category = license.and_then(classify_expression);
+            (proj, license, category)
+        })
+        .collect::<Vec<_>>();
+
+    let name_width = rows
+        .iter()
+        .map(|r| r.0.user_facing_name.len())
+        .chain(std::iter::once(7))
+        .max()
+        .unwrap();
+    let license_width = rows
+        .iter()
+        .map(|r| r.1.map(|l| l.len()).unwrap_or(1))
+        .chain(std::iter::once(7))
+        .max()
+        .unwrap();
+
+    println!(
+        "{:nw$}  {:lw$}  category",
+        "project",
+        "license",
+        nw = name_width,
+        lw = license_width
+    );
+
+    for (proj, license, category) in &rows {
+        println!(
+            "{:nw$}  {:lw$}  {}",
+            proj.user_facing_name,
+            license.unwrap_or("-"),
+            category
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "?".to_owned()),
+            nw = name_width,
+            lw = license_width
+        );
+    }
+
+    for (proj, license, category) in &rows {
+        match (license, category) {
+            (None, _) => warn!(
+                "project `{}` does not declare a license",
+                proj.user_facing_name
+            ),
+            (Some(l), None) => warn!(
+                "cannot classify the license `{}` of project `{}`",
+                l, proj.user_facing_name
+            ),
+            _ => {}
+        }
+    }
+
+    // Now look for conflicts.
+
+    let mut n_conflicts = 0;
+
+    for ident in idents {
+        let proj = graph.lookup(*ident);
+        let cat = match proj.license.as_deref().and_then(classify_expression) {
+            Some(c) => c,
+            None => continue,
+        };
+
+        for dep in &proj.internal_deps {
+            let dep_proj = graph.lookup(dep.ident);
+            let dep_cat = match dep_proj.license.as_deref().and_then(classify_expression) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            if dep_cat > cat {
+                error!(
+                    "project `{}` ({}, {}) depends on the more restrictive project `{}` ({}, {})",
+                    proj.user_facing_name,
+                    proj.license.as_deref().unwrap(),
+                    cat,
+                    dep_proj.user_facing_name,
+                    dep_proj.license.as_deref().unwrap(),
+                    dep_cat
+                );
+                n_conflicts += 1;
+            }
+        }
+    }
+
+    if n_conflicts == 0 {
+        info!("no license conflicts found");
+    }
+
+    n_conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        use LicenseCategory::*;
+
+        assert_eq!(classify_expression("MIT"), Some(Permissive));
+        assert_eq!(classify_expression("MIT OR Apache-2.0"), Some(Permissive));
+        assert_eq!(classify_expression("MIT/Apache-2.0"), Some(Permissive));
+        assert_eq!(classify_expression("GPL-2.0+"), Some(StrongCopyleft));
+        assert_eq!(classify_expression("LGPL-2.1-only"), Some(WeakCopyleft));
+        assert_eq!(classify_expression("MIT OR GPL-3.0-only"), Some(Permissive));
+        assert_eq!(classify_expression("MIT AND MPL-2.0"), Some(WeakCopyleft));
+        assert_eq!(
+            classify_expression("GPL-2.0-only WITH Classpath-exception-2.0"),
+            Some(StrongCopyleft)
+        );
+        assert_eq!(
+            classify_expression("(MIT OR GPL-3.0) AND AGPL-3.0-or-later"),
+            Some(NetworkCopyleft)
+        );
+        assert_eq!(
+            classify_expression("Proprietary OR BSD-3-Clause"),
+            Some(Permissive)
+        );
+        assert_eq!(classify_expression("Proprietary"), None);
+        assert_eq!(classify_expression("MIT AND Proprietary"), None);
+        assert_eq!(classify_expression("(MIT"), None);
+        assert_eq!(classify_expression("MIT OR"), None);
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Preserving the line endings of the text files that Cranko writes.
+//!
+//! Whether a file should use LF or CRLF line endings is a property of the file
+//! (and the repository's `.gitattributes`), not of the platform that Cranko
+//! happens to be running on. So when we rewrite a file, we match the line
+//! endings that it already uses. New files, and files without any line breaks,
+//! get the platform's native convention.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// A line-ending convention.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LineEnding {
+    /// Unix-style line endings, `\n`.
+    Lf,
+
+    /// Windows-style line endings, `\r\n`.
+    Crlf,
+}
+
+impl Default for LineEnding {
+    /// The native line ending of the current platform.
+    fn default() -> Self {
+        if cfg!(windows) {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+impl LineEnding {
+    /// Detect the line ending used in some text, based on its first line break.
+    ///
+    /// Returns None if the text doesn't contain any line breaks.
+    pub fn detect(text: &[u8]) -> Option<Self> {
+        let idx = text.iter().position(|b| *b == b'\n')?;
+
+        if idx > 0 && text[idx - 1] == b'\r' {
+            Some(LineEnding::Crlf)
+        } else {
+            Some(LineEnding::Lf)
+        }
+    }
+
+    /// Determine the line ending to use when (re)writing the file at *path*.
+    ///
+    /// If the file exists and contains a line break, its existing convention is
+    /// used. Otherwise, the platform default is returned.
+    pub fn for_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut first_line = Vec::new();
+        BufReader::new(f).read_until(b'\n', &mut first_line)?;
+        Ok(Self::detect(&first_line).unwrap_or_default())
+    }
+
+    /// Get the bytes of this line ending.
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+}
+
+/// A writer wrapper that emits a specific line ending.
+///
+/// Data written through this wrapper should use bare `\n` line endings, which
+/// are translated into the desired ones. This way, `writeln!` and serializers
+/// like `serde_json` can be used without worrying about the target file's
+/// conventions.
+#[derive(Debug)]
+pub struct LineEndingWriter<W> {
+    inner: W,
+    ending: LineEnding,
+}
+
+impl<W: Write> LineEndingWriter<W> {
+    /// Wrap a writer so that it emits the specified line ending.
+    pub fn new(inner: W, ending: LineEnding) -> Self {
+        LineEndingWriter { inner, ending }
+    }
+
+    /// Unwrap this writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.ending == LineEnding::Lf {
+            return self.inner.write(buf);
+        }
+
+        for (i, chunk) in buf.split(|b| *b == b'\n').enumerate() {
+            if i > 0 {
+                self.inner.write_all(self.ending.as_bytes())?;
+            }
+
+            self.inner.write_all(chunk)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        assert_eq!(LineEnding::detect(b"a\nb\r\n"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::detect(b"a\r\nb\n"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect(b"\r\n"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect(b"abc"), None);
+    }
+
+    #[test]
+    fn translate() {
+        let mut w = LineEndingWriter::new(Vec::new(), LineEnding::Crlf);
+        writeln!(w, "a").unwrap();
+        write!(w, "b\n\nc").unwrap();
+        assert_eq!(w.into_inner(), b"a\r\nb\r\n\r\nc");
+
+        let mut w = LineEndingWriter::new(Vec::new(), LineEnding::Lf);
+        writeln!(w, "a").unwrap();
+        assert_eq!(w.into_inner(), b"a\n");
+    }
+}
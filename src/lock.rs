@@ -0,0 +1,138 @@
+// Copyright 2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Advisory locking to keep concurrent Cranko processes from clobbering each
+//! other.
+//!
+//! If two Cranko processes try to modify the same checkout at the same time
+//! (e.g., overlapping CI steps), they can corrupt the Git index or refs. So
+//! before it mutates anything, an [`crate::app::AppSession`] takes out a lock
+//! by exclusively creating a file inside the Git directory. The lock is
+//! released when the session is dropped. Read-only operations don't take the
+//! lock.
+//!
+//! If a process dies without cleaning up after itself, its lock file will be
+//! left behind. Cranko waits for an existing lock to be released, but if the
+//! lock file gets older than a fixed timeout, it is considered stale and is
+//! taken over. The `--break-lock` global option, or the `CRANKO_BREAK_LOCK`
+//! environment variable, removes any existing lock immediately.
+
+use anyhow::Context;
+use log::{info, warn};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    process, thread,
+    time::Duration,
+};
+
+use crate::errors::Result;
+
+/// A lock file older than this is considered stale.
+const STALE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How often to check whether an existing lock has been released.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A held advisory lock on a repository. The lock is released when this value
+/// is dropped.
+#[derive(Debug)]
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Acquire the lock represented by the file at *path*, waiting for any
+    /// other process holding it to finish up.
+    ///
+    /// If *break_existing* is true, any existing lock file is deleted first.
+    pub fn acquire(path: PathBuf, break_existing: bool) -> Result<Self> {
+        if break_existing {
+            match fs::remove_file(&path) {
+                Ok(_) => warn!("forcibly removed the lock file `{}`", path.display()),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to remove the lock file `{}`", path.display())
+                    })
+                }
+            }
+        }
+
+        let mut announced = false;
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut f) => {
+                    // The PID is only informational, so a failure here isn't
+                    // a problem.
+                    let _ignored = writeln!(f, "{}", process::id());
+                    return Ok(RepoLock { path });
+                }
+
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {}
+
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to create the lock file `{}`", path.display())
+                    })
+                }
+            }
+
+            // If the file has disappeared in the meantime, we'll just try
+            // again.
+
+            let age = fs::metadata(&path)
+                .and_then(|md| md.modified())
+                .ok()
+                .and_then(|t| t.elapsed().ok());
+
+            if let Some(age) = age {
+                if age > STALE_TIMEOUT {
+                    warn!(
+                        "taking over the stale lock file `{}` ({} seconds old)",
+                        path.display(),
+                        age.as_secs()
+                    );
+                    let _ignored = fs::remove_file(&path);
+                    continue;
+                }
+            }
+
+            if !announced {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                let holder = holder.trim();
+
+                if holder.is_empty() {
+                    info!(
+                        "waiting for another Cranko process to release the lock file `{}`",
+                        path.display()
+                    );
+                } else {
+                    info!(
+                        "waiting for another Cranko process (PID {}) to release the lock file `{}`",
+                        holder,
+                        path.display()
+                    );
+                }
+
+                announced = true;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(
+                "failed to remove the lock file `{}`: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
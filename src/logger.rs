@@ -11,9 +11,11 @@
 
 use lazy_static::lazy_static;
 use log::{Level, Log};
+use serde::Serialize;
 use std::{
     fmt::Display,
-    io::{self, Write},
+    io::{self, IsTerminal, Write},
+    str::FromStr,
     sync::RwLock,
 };
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
@@ -34,6 +36,53 @@ fn get_wrap_width() -> usize {
     }
 }
 
+/// The format in which log records are emitted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable text, colorized if appropriate.
+    Human,
+
+    /// One JSON object per line, for ingestion by log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::anyhow!(
+                "unrecognized log format `{}` (expected `human` or `json`)",
+                s
+            )),
+        }
+    }
+}
+
+/// A log record as emitted in the JSON format.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    level: &'a str,
+    target: &'a str,
+    message: &'a str,
+    timestamp: String,
+}
+
+fn write_json_record<W: Write>(stream: &mut W, level: Level, target: &str, message: &str) {
+    let record = JsonRecord {
+        level: &level.as_str().to_ascii_lowercase(),
+        target,
+        message,
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    };
+
+    if let Ok(text) = serde_json::to_string(&record) {
+        let _r = writeln!(stream, "{}", text);
+    }
+}
+
 /// A simple logger.
 pub struct Logger {
     inner: RwLock<InnerLogger>,
@@ -48,12 +97,33 @@ pub struct Logger {
 pub struct InnerLogger {
     stdout: StandardStream,
     stderr: StandardStream,
+    format: LogFormat,
+}
+
+impl InnerLogger {
+    /// Set up the output streams. In "auto" mode, color is only used for
+    /// streams that are terminals, and only if the `NO_COLOR` environment
+    /// variable is unset or empty. See <https://no-color.org/>.
+    fn new(choice: ColorChoice, format: LogFormat) -> Self {
+        let no_color = std::env::var_os("NO_COLOR")
+            .map(|v| !v.is_empty())
+            .unwrap_or(false);
+
+        let resolve = |is_tty: bool| match choice {
+            ColorChoice::Auto if no_color || !is_tty => ColorChoice::Never,
+            other => other,
+        };
+
+        InnerLogger {
+            stdout: StandardStream::stdout(resolve(io::stdout().is_terminal())),
+            stderr: StandardStream::stderr(resolve(io::stderr().is_terminal())),
+            format,
+        }
+    }
 }
 
 lazy_static! {
     static ref LOGGER: Logger = {
-        let stdout = StandardStream::stdout(ColorChoice::Auto);
-        let stderr = StandardStream::stderr(ColorChoice::Auto);
         let trace_cspec = ColorSpec::new();
         let debug_cspec = ColorSpec::new();
         let mut info_cspec = ColorSpec::new();
@@ -67,7 +137,7 @@ lazy_static! {
         highlight_cspec.set_fg(Some(Color::Cyan)).set_bold(true);
 
         Logger {
-            inner: RwLock::new(InnerLogger { stdout, stderr }),
+            inner: RwLock::new(InnerLogger::new(ColorChoice::Auto, LogFormat::Human)),
             trace_cspec,
             debug_cspec,
             info_cspec,
@@ -84,8 +154,41 @@ impl Logger {
         log::set_logger(&*LOGGER)
     }
 
+    /// Choose whether the logger's output should be colorized.
+    pub fn set_color_choice(choice: ColorChoice) {
+        if let Ok(mut inner) = LOGGER.inner.write() {
+            let format = inner.format;
+            *inner = InnerLogger::new(choice, format);
+        }
+    }
+
+    /// Choose the format in which log records are emitted.
+    pub fn set_format(format: LogFormat) {
+        if let Ok(mut inner) = LOGGER.inner.write() {
+            inner.format = format;
+        }
+    }
+
+    /// Print a blank line to separate parts of an error report. This is a
+    /// no-op in the JSON format, where every line must be a record.
+    pub fn print_err_spacer() {
+        if let Ok(inner) = LOGGER.inner.read() {
+            if inner.format == LogFormat::Json {
+                return;
+            }
+        }
+
+        eprintln!();
+    }
+
     pub fn print_cause(err: &(dyn std::error::Error + 'static)) {
         if let Ok(mut inner) = LOGGER.inner.write() {
+            if inner.format == LogFormat::Json {
+                let msg = format!("caused by: {}", err);
+                write_json_record(&mut inner.stderr, Level::Error, "cranko", &msg);
+                return;
+            }
+
             let _r = inner.stderr.set_color(&LOGGER.error_cspec);
             let _r = write!(&mut inner.stderr, "caused by:");
             let _r = inner.stderr.reset();
@@ -97,6 +200,15 @@ impl Logger {
 
     pub fn print_err_note<T: Display>(msg: T) {
         let msg = msg.to_string();
+
+        if let Ok(mut inner) = LOGGER.inner.write() {
+            if inner.format == LogFormat::Json {
+                let msg = format!("note: {}", msg);
+                write_json_record(&mut inner.stderr, Level::Error, "cranko", &msg);
+                return;
+            }
+        }
+
         let mut first = true;
 
         for line in textwrap::wrap(&msg, get_wrap_width() - 6) {
@@ -123,6 +235,12 @@ impl Logger {
         after: T3,
     ) {
         if let Ok(mut inner) = LOGGER.inner.write() {
+            if inner.format == LogFormat::Json {
+                let msg = format!("{}{}{}", before, highlight, after);
+                write_json_record(&mut inner.stdout, Level::Info, "cranko", &msg);
+                return;
+            }
+
             let _r = write!(&mut inner.stdout, "{}", before);
             let _r = inner.stdout.set_color(&LOGGER.highlight_cspec);
             let _r = write!(&mut inner.stdout, "{}", highlight);
@@ -135,13 +253,30 @@ impl Logger {
 }
 
 impl Log for Logger {
-    fn enabled(&self, _: &log::Metadata) -> bool {
-        // Rely on `log::set_max_level()` for filtering
-        true
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // Otherwise, rely on `log::set_max_level()` for filtering. But our
+        // dependencies (e.g. the HTTP stack) are very chatty at the debug and
+        // trace levels, so only show those messages from Cranko itself.
+        metadata.level() <= Level::Info || metadata.target().starts_with("cranko")
     }
 
     fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
         if let Ok(mut inner) = self.inner.write() {
+            if inner.format == LogFormat::Json {
+                let message = record.args().to_string();
+                let stream = if record.level() == Level::Info {
+                    &mut inner.stdout
+                } else {
+                    &mut inner.stderr
+                };
+                write_json_record(stream, record.level(), record.target(), &message);
+                return;
+            }
+
             match record.level() {
                 Level::Trace => {
                     let _r = inner.stderr.set_color(&self.trace_cspec);
@@ -207,3 +342,34 @@ impl Log for Logger {
         let _r = io::stdout().flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_records() {
+        let mut out = Vec::new();
+        write_json_record(&mut out, Level::Warn, "cranko::app", "say \"hi\"\nbye");
+        write_json_record(&mut out, Level::Error, "cranko", "oops");
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let rec: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(rec["level"], "warn");
+        assert_eq!(rec["target"], "cranko::app");
+        assert_eq!(rec["message"], "say \"hi\"\nbye");
+        let ts = rec["timestamp"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(ts).is_ok());
+        assert!(ts.ends_with('Z'));
+
+        let rec: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(rec["level"], "error");
+        assert_eq!(rec["message"], "oops");
+
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -10,9 +10,9 @@
 
 use anyhow::{anyhow, bail, Context};
 use base64::prelude::*;
-use log::{info, warn};
+use log::{error, info, warn};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap},
     env as stdenv,
     ffi::OsString,
     fs,
@@ -21,40 +21,87 @@ use std::{
     process,
 };
 use structopt::StructOpt;
+use termcolor::ColorChoice;
 
-mod app;
-mod bootstrap;
-mod cargo;
-mod changelog;
-mod config;
-mod csproj;
-mod env;
-mod errors;
-mod github;
-mod gitutil;
-mod graph;
-mod logger;
-mod npm;
-mod project;
-mod pypa;
-mod repository;
-mod rewriters;
-mod version;
-mod zenodo;
+use cranko::{
+    a_ok_or, app, atry, bootstrap, cargo, config, env, errors, github, gitutil, graph, licenses,
+    logger, npm, project, pypa, repository, version, zenodo, Command,
+};
 
 use errors::Result;
 
 #[derive(Debug, PartialEq, StructOpt)]
 #[structopt(about = "automate versioning and releasing")]
 struct CrankoOptions {
+    #[structopt(
+        long = "no-cache",
+        help = "Don't use cached data from previous invocations",
+        global = true
+    )]
+    no_cache: bool,
+
+    #[structopt(
+        long = "break-lock",
+        help = "Remove any existing lock on the repository held by another Cranko process",
+        global = true
+    )]
+    break_lock: bool,
+
+    #[structopt(
+        short = "C",
+        long = "repo-dir",
+        help = "Run as if Cranko was started in this directory",
+        global = true,
+        parse(from_os_str)
+    )]
+    repo_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "prefix",
+        help = "Only load projects located within this repository subdirectory",
+        global = true
+    )]
+    prefix: Option<String>,
+
+    #[structopt(
+        short = "v",
+        long = "verbose",
+        help = "Print more diagnostic output (repeat for even more)",
+        global = true,
+        parse(from_occurrences)
+    )]
+    verbose: u8,
+
+    #[structopt(
+        short = "q",
+        long = "quiet",
+        help = "Only print warnings and errors",
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+
+    #[structopt(
+        long = "color",
+        help = "Whether to colorize the output",
+        possible_values = &["auto", "always", "never"],
+        default_value = "auto",
+        global = true
+    )]
+    color: String,
+
+    #[structopt(
+        long = "log-format",
+        help = "The format of log messages [env: CRANKO_LOG_FORMAT]",
+        possible_values = &["human", "json"],
+        global = true
+    )]
+    log_format: Option<String>,
+
     #[structopt(subcommand)]
     command: Commands,
 }
 
-trait Command {
-    fn execute(self) -> Result<i32>;
-}
-
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, StructOpt)]
 enum Commands {
@@ -66,10 +113,18 @@ enum Commands {
     /// Commands specific to the Rust/Cargo packaging system.
     Cargo(cargo::CargoCommand),
 
+    #[structopt(name = "check-licenses")]
+    /// Check projects' licenses against those of their internal dependencies
+    CheckLicenses(CheckLicensesCommand),
+
     #[structopt(name = "ci-util")]
     /// Utilities useful in CI environments
     CiUtil(CiUtilCommand),
 
+    #[structopt(name = "config")]
+    /// Work with the repository's Cranko configuration
+    Config(ConfigCommand),
+
     #[structopt(name = "confirm")]
     /// Commit staged release requests to the `rc` branch
     Confirm(ConfirmCommand),
@@ -135,7 +190,9 @@ impl Command for Commands {
         match self {
             Commands::Bootstrap(o) => o.execute(),
             Commands::Cargo(o) => o.execute(),
+            Commands::CheckLicenses(o) => o.execute(),
             Commands::CiUtil(o) => o.execute(),
+            Commands::Config(o) => o.execute(),
             Commands::Confirm(o) => o.execute(),
             Commands::Diff(o) => o.execute(),
             Commands::Github(o) => o.execute(),
@@ -162,11 +219,109 @@ fn main() {
         eprintln!("error: cannot initialize logging backend: {}", e);
         process::exit(1);
     }
-    log::set_max_level(log::LevelFilter::Info);
+
+    logger::Logger::set_color_choice(match opts.color.as_str() {
+        "always" => ColorChoice::Always,
+        "never" => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    });
+
+    let log_format = match opts.log_format.clone().filter(|s| !s.is_empty()) {
+        Some(text) => Some(text),
+        None => match env::maybe_var("CRANKO_LOG_FORMAT") {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+    };
+
+    if let Some(text) = log_format {
+        match text.parse() {
+            Ok(format) => logger::Logger::set_format(format),
+            Err(e) => {
+                eprintln!("error: invalid `CRANKO_LOG_FORMAT` setting: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    log::set_max_level(if opts.quiet {
+        log::LevelFilter::Warn
+    } else {
+        match opts.verbose {
+            0 => log::LevelFilter::Info,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    });
+
+    // These settings are applied to every app session that the command sets
+    // up, so that we don't need to thread them through every command.
+    app::GlobalOptions::set(app::GlobalOptions {
+        no_cache: opts.no_cache,
+        break_lock: opts.break_lock,
+        prefix: opts
+            .prefix
+            .as_ref()
+            .map(|p| repository::RepoPathBuf::new(p.as_bytes())),
+    });
+
+    // Like `git -C`, we just change our working directory, so relative paths
+    // given to subcommands are also interpreted relative to the new directory.
+    if let Some(ref dir) = opts.repo_dir {
+        if let Err(e) = std::env::set_current_dir(dir) {
+            let e = errors::Error::new(e)
+                .context(format!("cannot change to directory `{}`", dir.display()));
+            process::exit(errors::report(Err(e)));
+        }
+    }
 
     process::exit(errors::report(opts.command.execute()));
 }
 
+// check-licenses
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct CheckLicensesCommand {
+    #[structopt(
+        long = "only-type",
+        help = "Only check projects of the specified type (e.g. \"cargo\")"
+    )]
+    only_type: Option<String>,
+
+    #[structopt(help = "Names of the projects to check (default: all)")]
+    proj_names: Vec<String>,
+}
+
+impl Command for CheckLicensesCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = app::AppSession::initialize_default()?;
+
+        let mut q = graph::GraphQueryBuilder::default();
+        q.names(self.proj_names);
+
+        if let Some(ref t) = self.only_type {
+            q.only_project_type(t);
+        }
+
+        let idents = sess
+            .graph()
+            .query(q)
+            .context("could not select projects for check-licenses")?;
+
+        let n = licenses::check_licenses(&sess, &idents);
+
+        if n == 0 {
+            Ok(0)
+        } else {
+            error!("found {} license conflict(s)", n);
+            Ok(1)
+        }
+    }
+}
+
 // ci-util
 
 #[derive(Debug, PartialEq, StructOpt)]
@@ -180,13 +335,104 @@ enum CiUtilCommands {
     #[structopt(name = "env-to-file")]
     /// Save an environment variable to a file
     EnvToFile(CiUtilEnvToFileCommand),
+
+    #[structopt(name = "set-output")]
+    /// Set an output variable using the current CI service's mechanism
+    SetOutput(CiUtilSetOutputCommand),
 }
 
 impl Command for CiUtilCommand {
     fn execute(self) -> Result<i32> {
         match self.command {
             CiUtilCommands::EnvToFile(o) => o.execute(),
+            CiUtilCommands::SetOutput(o) => o.execute(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct CiUtilSetOutputCommand {
+    #[structopt(
+        long = "dotenv-file",
+        default_value = "cranko.env",
+        help = "On GitLab, the dotenv file to which outputs are appended"
+    )]
+    dotenv_file: PathBuf,
+
+    #[structopt(help = "The name of the output variable")]
+    name: String,
+
+    #[structopt(help = "The value of the output variable")]
+    value: String,
+}
+
+impl Command for CiUtilSetOutputCommand {
+    fn execute(self) -> Result<i32> {
+        use std::fs::OpenOptions;
+
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!(
+                "illegal output name `{}`: only ASCII letters, numbers, `_`, and `-` are allowed",
+                self.name
+            );
         }
+
+        let sess = app::AppBuilder::new()?.populate_graph(false).initialize()?;
+
+        match sess.ci_provider()? {
+            Some(app::CiProvider::GithubActions) => {
+                if !github::append_actions_output(&self.name, &self.value)? {
+                    bail!("cannot set GitHub Actions output: $GITHUB_OUTPUT is not set");
+                }
+            }
+
+            Some(app::CiProvider::AzurePipelines) => {
+                // See the Azure Pipelines documentation on "logging commands"
+                // for the escaping rules.
+                let value = self
+                    .value
+                    .replace('%', "%AZP25")
+                    .replace('\r', "%0D")
+                    .replace('\n', "%0A");
+                println!(
+                    "##vso[task.setvariable variable={};isOutput=true]{}",
+                    self.name, value
+                );
+            }
+
+            Some(app::CiProvider::Gitlab) => {
+                if self.value.contains('\n') {
+                    bail!("GitLab dotenv outputs cannot contain multiple lines");
+                }
+
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&self.dotenv_file)
+                    .with_context(|| {
+                        format!("cannot open dotenv file `{}`", self.dotenv_file.display())
+                    })?;
+
+                writeln!(file, "{}={}", self.name, self.value).with_context(|| {
+                    format!(
+                        "cannot write to dotenv file `{}`",
+                        self.dotenv_file.display()
+                    )
+                })?;
+            }
+
+            Some(app::CiProvider::Other) | None => {
+                let name = self.name.replace('-', "_");
+                println!("export {}='{}'", name, self.value.replace('\'', "'\\''"));
+            }
+        }
+
+        Ok(0)
     }
 }
 
@@ -262,26 +508,47 @@ impl Command for CiUtilEnvToFileCommand {
             )
         })?;
 
-        // Write the data. Eventually we might have more options, but for now we
-        // always interpret the OsString into text, then convert it into a
-        // Vec<[u8]> for writing.
+        // Write the data. In text mode, we interpret the OsString into text,
+        // then convert it into a Vec<[u8]> for writing. BASE64 data are pure
+        // ASCII, so in that mode we can work with the raw bytes of the value
+        // and not reject values that happen not to be valid Unicode.
 
-        let value = value.into_string().map_err(|_| {
-            anyhow!(
-                "cannot interpret value of environment variable `{}` as Unicode text",
-                self.var_name.to_string_lossy()
-            )
-        })?;
+        #[cfg(unix)]
+        fn raw_bytes(s: &std::ffi::OsStr) -> Vec<u8> {
+            use std::os::unix::ffi::OsStrExt;
+            s.as_bytes().to_vec()
+        }
 
-        let b = match self.decode_mode {
-            EnvDecodingMode::Text => value.into_bytes(),
+        #[cfg(windows)]
+        fn raw_bytes(s: &std::ffi::OsStr) -> Vec<u8> {
+            use std::os::windows::ffi::OsStrExt;
 
-            EnvDecodingMode::Base64 => BASE64_STANDARD.decode(&value).with_context(|| {
-                format!(
-                    "failed to decode value of environment variable `{}` as BASE64",
-                    self.var_name.to_string_lossy()
-                )
-            })?,
+            // Non-ASCII code units can't be valid BASE64, so map them to a
+            // byte that will make the decoder fail.
+            s.encode_wide()
+                .map(|c| if c < 0x80 { c as u8 } else { 0xFF })
+                .collect()
+        }
+
+        let b = match self.decode_mode {
+            EnvDecodingMode::Text => value
+                .into_string()
+                .map_err(|_| {
+                    anyhow!(
+                        "cannot interpret value of environment variable `{}` as Unicode text",
+                        self.var_name.to_string_lossy()
+                    )
+                })?
+                .into_bytes(),
+
+            EnvDecodingMode::Base64 => {
+                BASE64_STANDARD.decode(raw_bytes(&value)).with_context(|| {
+                    format!(
+                        "failed to decode value of environment variable `{}` as BASE64",
+                        self.var_name.to_string_lossy()
+                    )
+                })?
+            }
         };
 
         file.write_all(&b[..]).with_context(|| {
@@ -295,6 +562,124 @@ impl Command for CiUtilEnvToFileCommand {
     }
 }
 
+// config
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct ConfigCommand {
+    #[structopt(subcommand)]
+    command: ConfigCommands,
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+enum ConfigCommands {
+    #[structopt(name = "validate")]
+    /// Check the repository configuration for problems
+    Validate(ConfigValidateCommand),
+}
+
+impl Command for ConfigCommand {
+    fn execute(self) -> Result<i32> {
+        match self.command {
+            ConfigCommands::Validate(o) => o.execute(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct ConfigValidateCommand {}
+
+impl Command for ConfigValidateCommand {
+    fn execute(self) -> Result<i32> {
+        let builder = app::AppBuilder::new()?;
+        let cfg_path = builder.config_path();
+        let mut problems = Vec::new();
+
+        let mut config = match config::ConfigurationFile::get(&cfg_path) {
+            Ok(c) => c,
+            Err(e) => {
+                errors::report(Err(e.context(format!(
+                    "failed to load repository config file `{}`",
+                    cfg_path.display()
+                ))));
+                return Ok(1);
+            }
+        };
+
+        // Repository-level checks that the normal setup process doesn't treat
+        // as fatal (or doesn't make until much later).
+
+        if !config.repo.upstream_urls.is_empty() {
+            if let Ok((name, false)) = builder.repo.identify_upstream(&config.repo.upstream_urls) {
+                problems.push(anyhow!(
+                    "none of the configured `upstream_urls` match a Git remote (falling back to `{}`)",
+                    name
+                ));
+            }
+        }
+
+        if let Some(ref fmt) = config.repo.release_tag_name_format {
+            if let Err(e) = repository::check_release_tag_name_format(fmt) {
+                problems.push(e);
+                config.repo.release_tag_name_format = None;
+            }
+        }
+
+        // Now go through the full setup process, which catches everything
+        // else that can go wrong at the repository level, and then check the
+        // project graph.
+
+        let mut config_names: Vec<String> = config.projects.keys().cloned().collect();
+        config_names.sort();
+
+        match builder.initialize_with_config(config) {
+            Err(e) => {
+                problems.push(e);
+                warn!("unable to load the project graph, so not checking project configuration");
+            }
+
+            Ok(sess) => {
+                let graph = sess.graph();
+
+                for name in &config_names {
+                    if !graph.was_detected(name) {
+                        problems.push(anyhow!(
+                            "the configuration file refers to project `{}`, but no such project was detected",
+                            name
+                        ));
+                    }
+                }
+
+                for proj in graph.projects() {
+                    for dep in &proj.internal_deps {
+                        if dep.cranko_requirement == project::DepRequirement::Unavailable {
+                            problems.push(anyhow!(
+                                "project `{}` is missing Cranko's version requirement for its dependency on `{}`",
+                                proj.user_facing_name,
+                                graph.lookup(dep.ident).user_facing_name
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            info!("no problems found in the configuration");
+            return Ok(0);
+        }
+
+        let n = problems.len();
+
+        for p in problems {
+            errors::report(Err(p));
+        }
+
+        logger::Logger::print_err_spacer();
+        error!("found {} configuration problem(s)", n);
+        Ok(1)
+    }
+}
+
 // confirm
 
 #[derive(Debug, PartialEq, StructOpt)]
@@ -305,6 +690,93 @@ struct ConfirmCommand {
         help = "Force operation even in unexpected conditions"
     )]
     force: bool,
+
+    #[structopt(
+        long = "allow-dirty",
+        help = "Proceed even if files besides changelogs have been modified"
+    )]
+    allow_dirty: bool,
+
+    #[structopt(
+        short = "i",
+        long = "interactive",
+        help = "Review and edit each project's bump specification before committing"
+    )]
+    interactive: bool,
+
+    #[structopt(
+        long = "amend",
+        help = "Update the existing rc commit rather than replacing it"
+    )]
+    amend: bool,
+
+    #[structopt(help = "Names of staged projects to confirm (default: all staged projects)")]
+    proj_names: Vec<String>,
+}
+
+impl ConfirmCommand {
+    /// Compute the old version text and new version that will result from
+    /// applying a bump specification to a project.
+    ///
+    /// Unless *allow_regression* is true, it is an error for a forced version
+    /// to not be newer than the project's latest release.
+    fn preview_bump(
+        proj: &project::Project,
+        last_release: Option<&repository::ReleasedProjectInfo>,
+        bump_spec: &str,
+        allow_regression: bool,
+    ) -> Result<(String, version::Version)> {
+        let scheme = proj.version.parse_bump_scheme(bump_spec)?;
+
+        if let Some(last_release) = last_release {
+            let last_version = proj.version.parse_like(&last_release.version)?;
+            let mut v = last_version.clone();
+            scheme.apply(&mut v)?;
+
+            if let version::VersionBumpScheme::Force(_) = scheme {
+                if !allow_regression && v <= last_version {
+                    bail!(
+                        "the forced version `{}` of project `{}` is not newer than its latest release, `{}` \
+                         (use `--force` to override)",
+                        v,
+                        proj.user_facing_name,
+                        last_version
+                    );
+                }
+            }
+
+            Ok((last_release.version.clone(), v))
+        } else {
+            let mut v = proj.version.clone();
+            scheme.apply(&mut v)?;
+            Ok(("[no previous releases]".to_owned(), v))
+        }
+    }
+
+    /// Prompt the user to accept or replace a project's bump specification.
+    /// Returns `None` if the current specification should be kept.
+    fn prompt_bump_spec(
+        name: &str,
+        bump_spec: &str,
+        old_version_text: &str,
+        new_version: &version::Version,
+    ) -> Result<Option<String>> {
+        print!(
+            "{}: {} ({} => {}); new bump spec, or Enter to accept: ",
+            name, bump_spec, old_version_text, new_version
+        );
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(line.to_owned()))
+        }
+    }
 }
 
 impl Command for ConfirmCommand {
@@ -319,8 +791,8 @@ impl Command for ConfirmCommand {
                 "not recommended to confirm with a modified working tree ({})",
                 e
             );
-            if !self.force {
-                bail!("refusing to proceed (use `--force` to override)");
+            if !self.force && !self.allow_dirty {
+                bail!("refusing to proceed (use `--allow-dirty` or `--force` to override)");
             }
         }
 
@@ -331,29 +803,102 @@ impl Command for ConfirmCommand {
         let mut changes = repository::ChangeList::default();
         let mut rc_info = Vec::new();
 
+        // When amending, we merge our release requests into those of the rc
+        // commit left by the previous `cranko confirm`.
+        let previous = if self.amend {
+            Some(a_ok_or!(
+                sess.repo.get_local_rc_info()?;
+                ["there is no existing `{}` commit to amend", sess.repo.upstream_rc_name()]
+                (note "use `cranko confirm` without `--amend` to create one")
+            ))
+        } else {
+            None
+        };
+
+        // If specific projects were requested, others that have been staged are
+        // left alone. Their changelog modifications are tracked separately so
+        // that they're neither committed nor reset.
+        let selected = if self.proj_names.is_empty() {
+            None
+        } else {
+            let mut q = graph::GraphQueryBuilder::default();
+            q.names(self.proj_names.clone());
+            Some(sess.graph().query(q)?)
+        };
+        let mut deferred_changes = repository::ChangeList::default();
+        let mut confirmed = Vec::new();
+
         sess.solve_internal_deps(|repo, graph, ident| {
             let history = histories.lookup(ident);
-            let dirty_allowed = self.force;
+            let dirty_allowed = self.force || self.allow_dirty;
             let mut updated_version = false;
+            let is_selected = selected.as_ref().map_or(true, |s| s.contains(&ident));
+            let proj_changes = if is_selected {
+                &mut changes
+            } else {
+                &mut deferred_changes
+            };
 
-            if let Some(info) =
-                repo.scan_rc_info(graph.lookup(ident), &mut changes, dirty_allowed)?
-            {
+            let mut maybe_info =
+                repo.scan_rc_info(graph.lookup(ident), proj_changes, dirty_allowed)?;
+            let mut kept = false;
+
+            // When amending, projects in the existing rc commit stay in it
+            // unless they have been newly staged and selected.
+            if maybe_info.is_none() || !is_selected {
+                if let Some(prev_info) = previous
+                    .as_ref()
+                    .and_then(|p| p.lookup_project(graph.lookup(ident)))
+                {
+                    info!(
+                        "{}: keeping the release request from the existing rc commit",
+                        graph.lookup(ident).user_facing_name
+                    );
+                    maybe_info = Some(prev_info.clone());
+                    kept = true;
+                }
+            }
+
+            if maybe_info.is_some() && !is_selected && !kept {
+                info!(
+                    "{}: staged, but leaving it for a later confirmation",
+                    graph.lookup(ident).user_facing_name
+                );
+            } else if let Some(mut info) = maybe_info {
                 // Analyze the version bump and apply it (in-memory only).
 
                 let (old_version_text, new_version) = {
-                    let proj = graph.lookup_mut(ident);
+                    let proj = graph.lookup(ident);
                     let last_rel_info = history.release_info(repo)?;
-                    let scheme = proj.version.parse_bump_scheme(&info.bump_spec)?;
+                    let last_release = last_rel_info.lookup_project(proj);
+                    let mut preview =
+                        Self::preview_bump(proj, last_release, &info.bump_spec, self.force)?;
+
+                    if self.interactive && !kept && !proj.is_meta() {
+                        while let Some(spec) = Self::prompt_bump_spec(
+                            &proj.user_facing_name,
+                            &info.bump_spec,
+                            &preview.0,
+                            &preview.1,
+                        )? {
+                            match Self::preview_bump(proj, last_release, &spec, self.force) {
+                                Ok(p) => {
+                                    preview = p;
+                                    info.bump_spec = spec;
+                                }
+
+                                Err(e) => warn!("invalid bump specification `{}`: {}", spec, e),
+                            }
+                        }
+                    }
 
-                    if let Some(last_release) = last_rel_info.lookup_project(proj) {
-                        proj.version = proj.version.parse_like(&last_release.version)?;
-                        scheme.apply(&mut proj.version)?;
-                        (last_release.version.clone(), proj.version.clone())
-                    } else {
-                        scheme.apply(&mut proj.version)?;
-                        ("[no previous releases]".to_owned(), proj.version.clone())
+                    // Meta-projects ignore their bumps and follow their members.
+                    if let Some(v) = graph.aggregate_version(ident)? {
+                        preview.1 = v;
                     }
+
+                    graph.lookup_mut(ident).version = preview.1.clone();
+                    preview
                 };
 
                 let proj = graph.lookup(ident);
@@ -371,6 +916,7 @@ impl Command for ConfirmCommand {
                     proj.user_facing_name, info.bump_spec, old_version_text, new_version
                 );
                 rc_info.push(info);
+                confirmed.push(ident);
                 updated_version = true;
 
                 for dep in &proj.internal_deps[..] {
@@ -398,18 +944,42 @@ impl Command for ConfirmCommand {
             Ok(updated_version)
         })?;
 
+        if let Some(selected) = selected {
+            for ident in selected {
+                if !confirmed.contains(&ident) {
+                    bail!(
+                        "project `{}` has not been staged for release; use \"cranko stage\"?",
+                        sess.graph().lookup(ident).user_facing_name
+                    );
+                }
+            }
+        }
+
         if rc_info.is_empty() {
             warn!("no releases seem to have been staged; use \"cranko stage\"?");
             return Ok(0);
         }
 
-        sess.make_rc_commit(rc_info, &changes)?;
+        let amend = if let Some(previous) = previous {
+            let mut merged: Vec<_> = previous
+                .projects
+                .into_iter()
+                .filter(|p| !rc_info.iter().any(|r| r.qnames == p.qnames))
+                .collect();
+            merged.append(&mut rc_info);
+            rc_info = merged;
+            previous.commit
+        } else {
+            None
+        };
+
+        sess.make_rc_commit(rc_info, &changes, amend)?;
         info!(
             "staged rc commit to `{}` branch",
             sess.repo.upstream_rc_name()
         );
 
-        sess.repo.hard_reset_changes(&changes)?;
+        sess.hard_reset_changes(&changes)?;
         Ok(0)
     }
 }
@@ -418,10 +988,55 @@ impl Command for ConfirmCommand {
 
 #[derive(Debug, PartialEq, StructOpt)]
 struct DiffCommand {
+    #[structopt(
+        long = "from",
+        help = "The commit to diff from, instead of the project's last release"
+    )]
+    from: Option<String>,
+
+    #[structopt(
+        long = "to",
+        help = "The commit to diff to, instead of the working tree"
+    )]
+    to: Option<String>,
+
+    #[structopt(
+        long = "since-tag",
+        help = "Diff from the most recent tag matching this pattern",
+        conflicts_with = "from"
+    )]
+    since_tag: Option<String>,
+
+    #[structopt(long = "name-only", help = "Only show the names of changed files")]
+    name_only: bool,
+
+    #[structopt(
+        long = "stat",
+        help = "Show a diffstat instead of the full diff",
+        conflicts_with = "name-only"
+    )]
+    stat: bool,
+
     #[structopt(help = "Name of the project to query")]
     proj_names: Vec<String>,
 }
 
+impl DiffCommand {
+    /// Convert a user-specified history reference into a revision argument
+    /// that `git diff` will understand.
+    fn ref_to_git_arg(repo: &repository::Repository, text: &str) -> Result<String> {
+        match repo.parse_history_ref(text)? {
+            repository::ParsedHistoryRef::Id(cid) => Ok(cid.to_string()),
+            repository::ParsedHistoryRef::Tag(name) => Ok(repo.resolve_tag(&name)?.to_string()),
+            repository::ParsedHistoryRef::Manual(_)
+            | repository::ParsedHistoryRef::ThisCommit { .. } => bail!(
+                "the reference `{}` doesn't identify a commit to diff with; use a commit ID or a `tag:` reference",
+                text
+            ),
+        }
+    }
+}
+
 impl Command for DiffCommand {
     fn execute(self) -> Result<i32> {
         // See also "log" -- these follow similar patterns
@@ -439,21 +1054,42 @@ impl Command for DiffCommand {
             .repo
             .resolve_workdir(sess.graph().lookup(ident).prefix());
 
-        let histories = atry!(
-            sess.analyze_histories();
-            ["failed to analyze the repository history"]
-        );
+        let to = self
+            .to
+            .as_ref()
+            .map(|t| DiffCommand::ref_to_git_arg(&sess.repo, t))
+            .transpose()?;
 
-        let history = histories.lookup(ident);
+        let since_tag = self
+            .since_tag
+            .as_ref()
+            .map(|p| find_since_tag(&sess, ident, p))
+            .transpose()?
+            .flatten();
 
-        let commit = match history.main_branch_commit(&sess.repo)? {
-            Some(c) => c,
-            None => {
-                println!(
-                    "no known last release commit to diff against for `{}`",
-                    sess.graph().lookup(ident).user_facing_name
+        let from = match (self.from, since_tag) {
+            (Some(ref f), _) => DiffCommand::ref_to_git_arg(&sess.repo, f)?,
+
+            (None, Some(cid)) => cid.to_string(),
+
+            (None, None) => {
+                let histories = atry!(
+                    sess.analyze_histories();
+                    ["failed to analyze the repository history"]
                 );
-                return Ok(0);
+
+                let history = histories.lookup(ident);
+
+                match history.main_branch_commit(&sess.repo)? {
+                    Some(c) => c.to_string()[..8].to_owned(),
+                    None => {
+                        println!(
+                            "no known last release commit to diff against for `{}`",
+                            sess.graph().lookup(ident).user_facing_name
+                        );
+                        return Ok(0);
+                    }
+                }
             }
         };
 
@@ -461,10 +1097,22 @@ impl Command for DiffCommand {
 
         let mut cmd = process::Command::new("git");
         cmd.arg("diff");
-        cmd.arg(&commit.to_string()[..8]);
+
+        if self.name_only {
+            cmd.arg("--name-only");
+        } else if self.stat {
+            cmd.arg("--stat");
+        }
+
+        cmd.arg(from);
+
+        if let Some(to) = to {
+            cmd.arg(to);
+        }
+
         cmd.arg("--");
         cmd.arg(dir);
-        exec_or_spawn(&mut cmd)
+        exec_git(&mut cmd)
     }
 }
 
@@ -517,8 +1165,53 @@ struct LogCommand {
     #[structopt(long = "stat", help = "Show a diffstat with each commit")]
     stat: bool,
 
-    #[structopt(help = "Name of the project to query")]
-    proj_names: Vec<String>,
+    #[structopt(
+        long = "message-format",
+        help = "How to format the commit messages",
+        possible_values = &["full", "short"],
+        default_value = "full"
+    )]
+    message_format: String,
+
+    #[structopt(
+        long = "oneline",
+        help = "Show each commit on one line; same as `--message-format=short`"
+    )]
+    oneline: bool,
+
+    #[structopt(
+        long = "since-tag",
+        help = "Show commits since the most recent tag matching this pattern"
+    )]
+    since_tag: Option<String>,
+
+    #[structopt(help = "Name of the project to query")]
+    proj_names: Vec<String>,
+}
+
+/// Resolve the `--since-tag` option of `diff` and `log`, warning if no tag
+/// matches.
+fn find_since_tag(
+    sess: &app::AppSession,
+    ident: project::ProjectId,
+    pattern: &str,
+) -> Result<Option<repository::CommitId>> {
+    let proj = sess.graph().lookup(ident);
+
+    match sess.repo.find_latest_tag_matching(proj, pattern)? {
+        Some((tagname, cid)) => {
+            info!("using tag `{}` as the baseline", tagname);
+            Ok(Some(cid))
+        }
+
+        None => {
+            warn!(
+                "no tag matching `{}` found for `{}`; using its last release instead",
+                pattern, proj.user_facing_name
+            );
+            Ok(None)
+        }
+    }
 }
 
 impl Command for LogCommand {
@@ -534,8 +1227,18 @@ impl Command for LogCommand {
         }
         let ident = idents[0];
 
+        let since_tag = self
+            .since_tag
+            .as_ref()
+            .map(|p| find_since_tag(&sess, ident, p))
+            .transpose()?
+            .flatten();
+
         let histories = atry!(
-            sess.analyze_histories();
+            match since_tag {
+                Some(cid) => sess.analyze_histories_since(cid),
+                None => sess.analyze_histories(),
+            };
             ["failed to analyze the repository history"]
         );
 
@@ -554,19 +1257,31 @@ impl Command for LogCommand {
         // somewhere where the Git CLI isn't actually available.
 
         let mut cmd = process::Command::new("git");
-        cmd.arg("show");
 
-        if self.stat {
-            cmd.arg("--stat");
+        if self.oneline || self.message_format == "short" {
+            // Show only the specified commits, in the order we give them.
+            cmd.arg("log");
+            cmd.arg("--oneline");
+            cmd.arg("--no-walk=unsorted");
+
+            if self.stat {
+                cmd.arg("--stat");
+            }
         } else {
-            cmd.arg("--no-patch");
+            cmd.arg("show");
+
+            if self.stat {
+                cmd.arg("--stat");
+            } else {
+                cmd.arg("--no-patch");
+            }
         }
 
         for cid in history.commits() {
             cmd.arg(&cid.to_string()[..8]);
         }
 
-        exec_or_spawn(&mut cmd)
+        exec_git(&mut cmd)
     }
 }
 
@@ -613,6 +1328,26 @@ struct ReleaseWorkflowApplyVersionsCommand {
         help = "Force operation even in unexpected conditions"
     )]
     force: bool,
+
+    #[structopt(
+        long = "post-release-bump",
+        help = "In development mode, mark the last release versions as in-development rather than applying date codes"
+    )]
+    post_release_bump: bool,
+
+    #[structopt(
+        long = "only-type",
+        alias = "only",
+        help = "Only rewrite the files of projects of this type (e.g. `cargo`, `npm`)"
+    )]
+    only_type: Option<String>,
+
+    #[structopt(
+        long = "names",
+        help = "Only rewrite the files of the named project(s)",
+        number_of_values = 1
+    )]
+    names: Vec<String>,
 }
 
 impl Command for ReleaseWorkflowApplyVersionsCommand {
@@ -620,20 +1355,49 @@ impl Command for ReleaseWorkflowApplyVersionsCommand {
         let mut sess = app::AppSession::initialize_default()?;
         sess.ensure_fully_clean()?;
 
-        let (dev_mode, rci) = sess.ensure_ci_rc_mode(self.force)?;
+        let (dev_mode, mut rci) = sess.ensure_ci_rc_mode(self.force)?;
         if dev_mode {
             info!("computing new versions for \"development\" mode");
+
+            if self.post_release_bump {
+                for proj in &mut rci.projects {
+                    proj.bump_spec = "post-release-dev".to_owned();
+                }
+            }
         } else {
             info!("computing new versions based on `rc` commit request data");
+
+            if self.post_release_bump {
+                warn!("ignoring `--post-release-bump` since not in development mode");
+            }
+        }
+
+        // Versions are computed for every project, since the selected ones
+        // might depend on others, but only the selected ones are rewritten.
+
+        let mut q = graph::GraphQueryBuilder::default();
+        q.names(self.names);
+
+        if let Some(ref t) = self.only_type {
+            q.only_project_type(t);
+        }
+
+        let idents = sess
+            .graph()
+            .query(q)
+            .context("could not select projects for `release-workflow apply-versions`")?;
+
+        if idents.is_empty() {
+            warn!("no projects were selected, so no files will be modified");
         }
 
         let rel_info = sess.repo.get_latest_release_info()?;
 
-        sess.apply_versions(&rci)?;
-        let mut changes = sess.rewrite()?;
+        sess.apply_versions(&rci, dev_mode)?;
+        let mut changes = sess.rewrite_selected(&idents)?;
 
         if !dev_mode {
-            sess.apply_changelogs(rel_info.commit, &rci, &mut changes)?;
+            sess.apply_changelogs(rel_info.commit, &rci, &idents, &mut changes)?;
         }
 
         Ok(0)
@@ -650,6 +1414,20 @@ struct ReleaseWorkflowCommitCommand {
         help = "Force operation even in unexpected conditions"
     )]
     force: bool,
+
+    #[structopt(
+        long = "output",
+        help = "Write a JSON summary of the new releases to this file"
+    )]
+    output: Option<PathBuf>,
+}
+
+/// One entry in the JSON summary emitted by `release-workflow commit --output`.
+#[derive(Debug, serde::Serialize)]
+struct ReleaseSummaryEntry {
+    name: String,
+    version: String,
+    tag_name: String,
 }
 
 impl Command for ReleaseWorkflowCommitCommand {
@@ -661,7 +1439,33 @@ impl Command for ReleaseWorkflowCommitCommand {
         // the workflow seems to be in good working order. Just so long as they
         // don't *push* that commit at the wrong time, it's OK.
         let (_dev, rci) = sess.ensure_ci_rc_mode(self.force)?;
-        sess.make_release_commit(&rci)?;
+        let rel_info = sess.make_release_commit(&rci)?;
+
+        if let Some(output) = self.output {
+            let mut summary = Vec::new();
+
+            for ident in sess.graph().toposorted() {
+                let proj = sess.graph().lookup(ident);
+
+                if let Some(rel) = rel_info.lookup_if_released(proj) {
+                    summary.push(ReleaseSummaryEntry {
+                        name: proj.user_facing_name.clone(),
+                        version: rel.version.clone(),
+                        tag_name: sess.repo.get_tag_name(proj, rel, rel_info.commit)?,
+                    });
+                }
+            }
+
+            let f = atry!(
+                fs::File::create(&output);
+                ["failed to open `{}` for writing", output.display()]
+            );
+            atry!(
+                serde_json::to_writer_pretty(f, &summary);
+                ["failed to write release summary to `{}`", output.display()]
+            );
+        }
+
         Ok(0)
     }
 }
@@ -669,7 +1473,33 @@ impl Command for ReleaseWorkflowCommitCommand {
 // release-workflow tag
 
 #[derive(Debug, PartialEq, StructOpt)]
-struct ReleaseWorkflowTagCommand {}
+struct ReleaseWorkflowTagCommand {
+    #[structopt(
+        short = "f",
+        long = "force",
+        help = "Overwrite any existing tags with the same names"
+    )]
+    force: bool,
+
+    #[structopt(
+        long = "print-only",
+        help = "Print the tags that would be created, and where they would point, but don't create them"
+    )]
+    print_only: bool,
+
+    #[structopt(
+        long = "only-type",
+        help = "Only tag released projects of this type (e.g. `cargo`, `npm`)"
+    )]
+    only_type: Option<String>,
+
+    #[structopt(
+        long = "names",
+        help = "Only tag the named project(s), if released",
+        number_of_values = 1
+    )]
+    names: Vec<String>,
+}
 
 impl Command for ReleaseWorkflowTagCommand {
     fn execute(self) -> Result<i32> {
@@ -680,7 +1510,28 @@ impl Command for ReleaseWorkflowTagCommand {
             bail!("refusing to create tags in dev mode");
         }
 
-        sess.create_tags(&rel_info)?;
+        let mut q = graph::GraphQueryBuilder::default();
+        q.only_new_releases(rel_info.clone());
+        q.names(self.names);
+
+        if let Some(ref t) = self.only_type {
+            q.only_project_type(t);
+        }
+
+        let idents = sess
+            .graph()
+            .query(q)
+            .context("could not select projects for `release-workflow tag`")?;
+
+        for ident in sess.graph().toposorted() {
+            let proj = sess.graph().lookup(ident);
+
+            if rel_info.lookup_if_released(proj).is_some() && !idents.contains(&proj.ident()) {
+                info!("skipping tag for `{}`: not selected", proj.user_facing_name);
+            }
+        }
+
+        sess.create_tags(&rel_info, &idents, self.force, self.print_only)?;
         Ok(0)
     }
 }
@@ -695,6 +1546,14 @@ struct ShowCommand {
 
 #[derive(Debug, PartialEq, StructOpt)]
 enum ShowCommands {
+    #[structopt(name = "changelog")]
+    /// Print the pending release notes of a project
+    Changelog(ShowChangelogCommand),
+
+    #[structopt(name = "commit-info")]
+    /// Print the release or release-request information recorded in HEAD
+    CommitInfo(ShowCommitInfoCommand),
+
     #[structopt(name = "cranko-version-doi")]
     /// Print the DOI associated with this specific version of Cranko.
     CrankoVersionDoi(ShowCrankoVersionDoiCommand),
@@ -703,10 +1562,22 @@ enum ShowCommands {
     /// Print the DOI uniting all versions of the Cranko software package.
     CrankoConceptDoi(ShowCrankoConceptDoiCommand),
 
+    #[structopt(name = "dependents")]
+    /// Print the projects that depend on a project
+    Dependents(ShowDependentsCommand),
+
     #[structopt(name = "if-released")]
     /// Report if a project was just released
     IfReleased(ShowIfReleasedCommand),
 
+    #[structopt(name = "prefix")]
+    /// Print the directory of a project
+    Prefix(ShowPrefixCommand),
+
+    #[structopt(name = "release-history")]
+    /// Print the past releases of a project
+    ReleaseHistory(ShowReleaseHistoryCommand),
+
     #[structopt(name = "tctag")]
     /// Print a "thiscommit:" tag for copy/pasting
     TcTag(ShowTcTagCommand),
@@ -723,9 +1594,14 @@ enum ShowCommands {
 impl Command for ShowCommand {
     fn execute(self) -> Result<i32> {
         match self.command {
+            ShowCommands::Changelog(o) => o.execute(),
+            ShowCommands::CommitInfo(o) => o.execute(),
             ShowCommands::CrankoVersionDoi(o) => o.execute(),
             ShowCommands::CrankoConceptDoi(o) => o.execute(),
+            ShowCommands::Dependents(o) => o.execute(),
             ShowCommands::IfReleased(o) => o.execute(),
+            ShowCommands::Prefix(o) => o.execute(),
+            ShowCommands::ReleaseHistory(o) => o.execute(),
             ShowCommands::TcTag(o) => o.execute(),
             ShowCommands::Toposort(o) => o.execute(),
             ShowCommands::Version(o) => o.execute(),
@@ -733,6 +1609,134 @@ impl Command for ShowCommand {
     }
 }
 
+#[derive(Debug, PartialEq, StructOpt)]
+struct ShowChangelogCommand {
+    #[structopt(
+        long = "staged",
+        help = "Print the notes drafted by `cranko stage` rather than generating them from the history"
+    )]
+    staged: bool,
+
+    #[structopt(help = "Name of the project to query")]
+    proj_names: Vec<String>,
+}
+
+impl Command for ShowChangelogCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = app::AppSession::initialize_default()?;
+
+        let mut q = graph::GraphQueryBuilder::default();
+        q.names(self.proj_names);
+        let idents = sess.graph().query(q)?;
+
+        if idents.len() != 1 {
+            bail!("must specify exactly one project to show");
+        }
+
+        let ident = idents[0];
+        let proj = sess.graph().lookup(ident);
+
+        let notes = if self.staged {
+            atry!(
+                proj.changelog.scan_staged_notes(proj, &sess.repo);
+                ["could not read the staged release notes of project `{}`", proj.user_facing_name]
+                (note "has the project been staged with `cranko stage`?")
+            )
+        } else {
+            let histories = atry!(
+                sess.analyze_histories();
+                ["failed to analyze the repository history"]
+            );
+
+            let commits: Vec<repository::CommitId> = histories
+                .lookup(ident)
+                .commits()
+                .into_iter()
+                .copied()
+                .collect();
+
+            if commits.is_empty() {
+                info!(
+                    "no changes detected for project `{}` since its last release",
+                    proj.user_facing_name
+                );
+                return Ok(0);
+            }
+
+            proj.changelog.render_draft(proj, &sess, &commits[..])?
+        };
+
+        print!("{}", notes);
+        Ok(0)
+    }
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct ShowCommitInfoCommand {
+    #[structopt(
+        long = "format",
+        help = "The output format",
+        possible_values = &["toml", "json"],
+        default_value = "toml"
+    )]
+    format: String,
+}
+
+/// The output of `show commit-info`.
+#[derive(Debug, serde::Serialize)]
+struct CommitInfoOutput<P> {
+    /// Either "release" or "rc".
+    kind: &'static str,
+    commit: Option<String>,
+    projects: Vec<P>,
+}
+
+impl ShowCommitInfoCommand {
+    fn emit<P: serde::Serialize>(&self, output: CommitInfoOutput<P>) -> Result<()> {
+        if self.format == "json" {
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        } else {
+            print!("{}", toml::to_string_pretty(&output)?);
+        }
+
+        Ok(())
+    }
+}
+
+impl Command for ShowCommitInfoCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = app::AppBuilder::new()?.populate_graph(false).initialize()?;
+
+        // A release commit is identified by its fenced release information. If
+        // that's missing, HEAD might be an rc commit instead.
+
+        match sess.repo.parse_release_info_from_head() {
+            Ok(info) => self.emit(CommitInfoOutput {
+                kind: "release",
+                commit: info.commit.map(|c| c.to_string()),
+                projects: info.projects,
+            })?,
+
+            Err(e) if e.is::<repository::MissingReleaseInfoError>() => {
+                let info = atry!(
+                    sess.repo.parse_rc_info_from_head();
+                    ["HEAD does not seem to contain either release or release-request information"]
+                );
+
+                self.emit(CommitInfoOutput {
+                    kind: "rc",
+                    commit: info.commit.map(|c| c.to_string()),
+                    projects: info.projects,
+                })?
+            }
+
+            Err(e) => return Err(e),
+        }
+
+        Ok(0)
+    }
+}
+
 #[derive(Debug, PartialEq, StructOpt)]
 struct ShowCrankoVersionDoiCommand {}
 
@@ -767,6 +1771,56 @@ impl Command for ShowCrankoConceptDoiCommand {
     }
 }
 
+#[derive(Debug, PartialEq, StructOpt)]
+struct ShowDependentsCommand {
+    #[structopt(
+        long = "transitive",
+        help = "Also list projects that depend on the project indirectly"
+    )]
+    transitive: bool,
+
+    #[structopt(help = "Name of the project to query")]
+    proj_names: Vec<String>,
+}
+
+impl Command for ShowDependentsCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = app::AppSession::initialize_default()?;
+        let graph = sess.graph();
+
+        let mut q = graph::GraphQueryBuilder::default();
+        q.names(self.proj_names);
+        let idents = graph.query(q)?;
+
+        if idents.len() != 1 {
+            bail!("must specify exactly one project to show");
+        }
+
+        let mut dependents = graph.dependents_of(idents[0]);
+
+        if self.transitive {
+            let mut seen: BTreeSet<_> = dependents.iter().copied().collect();
+            let mut to_visit = dependents;
+
+            while let Some(ident) = to_visit.pop() {
+                for dependent in graph.dependents_of(ident) {
+                    if seen.insert(dependent) {
+                        to_visit.push(dependent);
+                    }
+                }
+            }
+
+            dependents = graph.toposorted().filter(|i| seen.contains(i)).collect();
+        }
+
+        for ident in dependents {
+            println!("{}", graph.lookup(ident).user_facing_name);
+        }
+
+        Ok(0)
+    }
+}
+
 #[derive(Debug, PartialEq, StructOpt)]
 struct ShowIfReleasedCommand {
     #[structopt(
@@ -778,7 +1832,20 @@ struct ShowIfReleasedCommand {
     #[structopt(long = "tf", help = "Print \"true\" if released, \"false\" if not")]
     true_false: bool,
 
-    #[structopt(help = "Name of the project to query")]
+    #[structopt(
+        long = "format",
+        help = "Print the release status of every selected project in this format",
+        possible_values = &["json"]
+    )]
+    format: Option<String>,
+
+    #[structopt(
+        long = "any",
+        help = "With --exit-code, succeed if any of the projects was released, rather than all"
+    )]
+    any: bool,
+
+    #[structopt(help = "Name(s) of the project(s) to query")]
     proj_names: Vec<String>,
 }
 
@@ -786,7 +1853,7 @@ impl Command for ShowIfReleasedCommand {
     fn execute(self) -> Result<i32> {
         let sess = app::AppSession::initialize_default()?;
 
-        if !(self.exit_code || self.true_false) {
+        if !(self.exit_code || self.true_false || self.format.is_some()) {
             bail!("must specify at least one output mechanism");
         }
 
@@ -794,28 +1861,129 @@ impl Command for ShowIfReleasedCommand {
         q.names(self.proj_names);
         let idents = sess.graph().query(q)?;
 
-        if idents.len() != 1 {
-            bail!("must specify exactly one project to show");
+        if idents.is_empty() {
+            bail!("must specify at least one project to show");
+        }
+
+        if self.true_false && idents.len() != 1 {
+            bail!("must specify exactly one project to show with `--tf`");
         }
 
         let (_dev_mode, rel_info) = sess.ensure_ci_release_mode()?;
 
-        let proj = sess.graph().lookup(idents[0]);
-        let was_released = rel_info.lookup_if_released(proj).is_some();
+        let statuses: Vec<(&str, bool)> = idents
+            .iter()
+            .map(|ident| {
+                let proj = sess.graph().lookup(*ident);
+                (
+                    proj.user_facing_name.as_ref(),
+                    rel_info.lookup_if_released(proj).is_some(),
+                )
+            })
+            .collect();
 
         if self.true_false {
-            println!("{}", if was_released { "true" } else { "false" });
+            println!("{}", if statuses[0].1 { "true" } else { "false" });
         }
 
-        Ok(if self.exit_code {
-            if was_released {
-                0
-            } else {
-                1
-            }
+        if self.format.is_some() {
+            let map: std::collections::BTreeMap<_, _> = statuses.iter().copied().collect();
+            println!("{}", serde_json::to_string(&map)?);
+        }
+
+        let success = if self.any {
+            statuses.iter().any(|s| s.1)
         } else {
-            0
-        })
+            statuses.iter().all(|s| s.1)
+        };
+
+        Ok(if self.exit_code && !success { 1 } else { 0 })
+    }
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct ShowPrefixCommand {
+    #[structopt(
+        long = "repo-relative",
+        help = "Print the path relative to the repository root"
+    )]
+    repo_relative: bool,
+
+    #[structopt(help = "Name of the project to query")]
+    proj_names: Vec<String>,
+}
+
+impl Command for ShowPrefixCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = app::AppSession::initialize_default()?;
+
+        let mut q = graph::GraphQueryBuilder::default();
+        q.names(self.proj_names);
+        let idents = sess.graph().query(q)?;
+
+        if idents.len() != 1 {
+            bail!("must specify exactly one project to show");
+        }
+
+        let proj = sess.graph().lookup(idents[0]);
+
+        // Prefixes generally have trailing slashes, which we don't want.
+        if !self.repo_relative {
+            let path = sess.repo.resolve_workdir(proj.prefix());
+            println!("{}", path.components().collect::<PathBuf>().display());
+        } else if proj.prefix().is_empty() {
+            println!(".");
+        } else {
+            println!("{}", proj.prefix().escaped().trim_end_matches('/'));
+        }
+
+        Ok(0)
+    }
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct ShowReleaseHistoryCommand {
+    #[structopt(help = "Name of the project to query")]
+    proj_names: Vec<String>,
+}
+
+impl Command for ShowReleaseHistoryCommand {
+    fn execute(self) -> Result<i32> {
+        use chrono::prelude::*;
+
+        let sess = app::AppSession::initialize_default()?;
+
+        let mut q = graph::GraphQueryBuilder::default();
+        q.names(self.proj_names);
+        let idents = sess.graph().query(q)?;
+
+        if idents.len() != 1 {
+            bail!("must specify exactly one project to show");
+        }
+
+        let proj = sess.graph().lookup(idents[0]);
+        let history = atry!(
+            sess.repo.get_release_history(proj);
+            ["failed to walk the release history of project `{}`", proj.user_facing_name]
+        );
+
+        if history.is_empty() {
+            info!(
+                "project `{}` has no recorded releases",
+                proj.user_facing_name
+            );
+        }
+
+        for entry in &history {
+            let date = match Utc.timestamp_opt(entry.time, 0).single() {
+                Some(t) => format!("{:04}-{:02}-{:02}", t.year(), t.month(), t.day()),
+                None => "?".to_owned(),
+            };
+
+            println!("{} {} {}", entry.version, entry.commit, date);
+        }
+
+        Ok(0)
     }
 }
 
@@ -848,16 +2016,67 @@ impl Command for ShowTcTagCommand {
 }
 
 #[derive(Debug, PartialEq, StructOpt)]
-struct ShowToposortCommand {}
+struct ShowToposortCommand {
+    #[structopt(
+        long = "reverse",
+        help = "List projects in reverse topological order (dependents first)"
+    )]
+    reverse: bool,
+
+    #[structopt(
+        long = "format",
+        help = "The output format",
+        possible_values = &["text", "json"],
+        default_value = "text"
+    )]
+    format: String,
+}
+
+/// One entry in the JSON output of `show toposort --format=json`.
+#[derive(Debug, serde::Serialize)]
+struct ToposortEntry {
+    name: String,
+    qnames: Vec<String>,
+    deps: Vec<String>,
+    release_priority: i32,
+}
 
 impl Command for ShowToposortCommand {
     fn execute(self) -> Result<i32> {
         let sess = app::AppSession::initialize_default()?;
         let graph = sess.graph();
 
-        for ident in graph.toposorted() {
-            let proj = graph.lookup(ident);
-            println!("{}", proj.user_facing_name);
+        let mut idents = graph.toposorted().collect::<Vec<_>>();
+
+        if self.reverse {
+            idents.reverse();
+        }
+
+        if self.format == "json" {
+            let entries = idents
+                .into_iter()
+                .map(|ident| {
+                    let proj = graph.lookup(ident);
+
+                    ToposortEntry {
+                        name: proj.user_facing_name.clone(),
+                        qnames: proj.qualified_names().clone(),
+                        deps: proj
+                            .internal_deps
+                            .iter()
+                            .map(|dep| graph.lookup(dep.ident).user_facing_name.clone())
+                            .collect(),
+                        release_priority: proj.release_priority,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        } else {
+            for ident in idents {
+                let proj = graph.lookup(ident);
+                println!("{}", proj.user_facing_name);
+            }
         }
 
         Ok(0)
@@ -869,6 +2088,12 @@ impl Command for ShowToposortCommand {
 // this project.
 #[derive(Debug, PartialEq, StructOpt)]
 struct ShowVersionCommand {
+    #[structopt(
+        long = "github-output",
+        help = "Also set a GitHub Actions step output with this name to the version"
+    )]
+    github_output: Option<String>,
+
     #[structopt(help = "Name of the project to query")]
     proj_names: Vec<String>,
 }
@@ -886,7 +2111,18 @@ impl Command for ShowVersionCommand {
         }
 
         let proj = sess.graph().lookup(idents[0]);
-        println!("{}", proj.version);
+        let version = proj.version.to_string();
+        println!("{}", version);
+
+        if let Some(ref name) = self.github_output {
+            if !github::append_actions_output(name, &version)? {
+                warn!(
+                    "not setting GitHub Actions output `{}`: $GITHUB_OUTPUT is not set",
+                    name
+                );
+            }
+        }
+
         Ok(0)
     }
 }
@@ -902,10 +2138,89 @@ struct StageCommand {
     )]
     force: bool,
 
+    #[structopt(
+        long = "allow-dirty",
+        help = "Proceed even if files besides changelogs have been modified"
+    )]
+    allow_dirty: bool,
+
+    #[structopt(
+        long = "all-since",
+        help = "Consider all commits since this commit, rather than since each project's latest release"
+    )]
+    all_since: Option<String>,
+
+    #[structopt(
+        long = "from-file",
+        help = "Read names of projects to stage, and optionally their bumps, from this file"
+    )]
+    from_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "bump",
+        help = "Write this bump specification into each drafted changelog (e.g. \"minor bump\")"
+    )]
+    bump: Option<String>,
+
     #[structopt(help = "Name(s) of the project(s) to stage for release")]
     proj_names: Vec<String>,
 }
 
+impl StageCommand {
+    /// Read a `--from-file` list; see [`Self::parse_stage_list`].
+    fn read_stage_list(
+        path: &Path,
+        is_project: impl Fn(&str) -> bool,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let text = atry!(
+            fs::read_to_string(path);
+            ["failed to read the list of projects to stage from `{}`", path.display()]
+        );
+        Self::parse_stage_list(&text, path, is_project)
+    }
+
+    /// Parse a `--from-file` list. Each non-blank line is a project name,
+    /// optionally followed by a colon, a space, and a bump spec (`name: minor
+    /// bump`). Lines starting with `#` are comments.
+    ///
+    /// Qualified project names contain colons too, so if the whole line names
+    /// a project according to *is_project*, it is taken as such. Otherwise,
+    /// the line is split at its last `": "`.
+    fn parse_stage_list(
+        text: &str,
+        path: &Path,
+        is_project: impl Fn(&str) -> bool,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let mut entries = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let entry = if is_project(line) {
+                (line.to_owned(), None)
+            } else if let Some((name, spec)) = line.rsplit_once(": ") {
+                (name.trim().to_owned(), Some(spec.trim().to_owned()))
+            } else if let Some(name) = line.strip_suffix(':') {
+                bail!(
+                    "empty bump specification for project `{}` in `{}`",
+                    name.trim(),
+                    path.display()
+                );
+            } else {
+                (line.to_owned(), None)
+            };
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
 impl Command for StageCommand {
     fn execute(self) -> Result<i32> {
         let sess = app::AppSession::initialize_default()?;
@@ -915,16 +2230,51 @@ impl Command for StageCommand {
                 "not recommended to stage with a modified working tree ({})",
                 e
             );
-            if !self.force {
-                bail!("refusing to proceed (use `--force` to override)");
+            if !self.force && !self.allow_dirty {
+                bail!("refusing to proceed (use `--allow-dirty` or `--force` to override)");
             }
         }
 
         sess.ensure_not_ci(self.force)?;
 
+        // If we're reading from a file, gather its names and any bumps it
+        // requests, validating everything before touching any changelogs.
+        let mut proj_names = self.proj_names;
+        let mut bump_specs = HashMap::new();
+
+        if let Some(ref path) = self.from_file {
+            let entries =
+                Self::read_stage_list(path, |name| sess.graph().lookup_ident(name).is_some())?;
+
+            if entries.is_empty() && proj_names.is_empty() {
+                info!("no projects listed in `{}`", path.display());
+                return Ok(0);
+            }
+
+            for (name, spec) in entries {
+                if let Some(spec) = spec {
+                    let ident = sess.graph().lookup_ident(&name).ok_or_else(|| {
+                        anyhow!(
+                            "no such project `{}` (listed in `{}`)",
+                            name,
+                            path.display()
+                        )
+                    })?;
+                    let proj = sess.graph().lookup(ident);
+                    atry!(
+                        proj.version.parse_bump_scheme(&spec);
+                        ["invalid bump specification `{}` for project `{}`", spec, name]
+                    );
+                    bump_specs.insert(ident, spec);
+                }
+
+                proj_names.push(name);
+            }
+        }
+
         // Get the list of projects that we're interested in.
         let mut q = graph::GraphQueryBuilder::default();
-        q.names(self.proj_names);
+        q.names(proj_names);
         let no_names = q.no_names();
         let idents = sess
             .graph()
@@ -936,8 +2286,40 @@ impl Command for StageCommand {
             return Ok(0);
         }
 
+        // A `--bump` applies to every selected project that didn't get a more
+        // specific one from the `--from-file` list.
+        if let Some(ref spec) = self.bump {
+            for ident in &idents {
+                if bump_specs.contains_key(ident) {
+                    continue;
+                }
+
+                let proj = sess.graph().lookup(*ident);
+                atry!(
+                    proj.version.parse_bump_scheme(spec);
+                    ["invalid bump specification `{}` for project `{}`", spec, proj.user_facing_name]
+                );
+                bump_specs.insert(*ident, spec.clone());
+            }
+        }
+
         // Scan the repository histories for everybody.
-        let histories = sess.analyze_histories()?;
+        let histories = if let Some(ref text) = self.all_since {
+            let cid = match sess.repo.parse_history_ref(text)? {
+                repository::ParsedHistoryRef::Id(cid) => cid,
+                repository::ParsedHistoryRef::Tag(name) => sess.repo.resolve_tag(&name)?,
+                _ => bail!(
+                    "the `--all-since` option must specify a Git commit ID or a `tag:` reference"
+                ),
+            };
+
+            atry!(
+                sess.analyze_histories_since(cid);
+                ["failed to analyze the repository history since commit `{}`", text]
+            )
+        } else {
+            sess.analyze_histories()?
+        };
 
         // Update the changelogs
         let mut n_staged = 0;
@@ -947,7 +2329,7 @@ impl Command for StageCommand {
         for ident in &idents {
             let proj = sess.graph().lookup(*ident);
             let history = histories.lookup(*ident);
-            let dirty_allowed = self.force;
+            let dirty_allowed = self.force || self.allow_dirty;
 
             if sess
                 .repo
@@ -982,8 +2364,13 @@ impl Command for StageCommand {
                 // generic types :-(
                 let commits: Vec<repository::CommitId> =
                     history.commits().into_iter().copied().collect();
-                proj.changelog
-                    .draft_release_update(proj, &sess, &commits[..], rel_info.commit)?;
+                proj.changelog.draft_release_update(
+                    proj,
+                    &sess,
+                    &commits[..],
+                    rel_info.commit,
+                    bump_specs.get(ident).map(|s: &String| s.as_str()),
+                )?;
                 n_staged += 1;
             }
         }
@@ -1002,12 +2389,187 @@ impl Command for StageCommand {
 
 // status
 
+/// A maximum age specified on the command line, like `30d`.
+#[derive(Debug, PartialEq)]
+struct AgeLimit {
+    text: String,
+    duration: chrono::Duration,
+}
+
+impl std::str::FromStr for AgeLimit {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (num, unit) = s.split_at(split);
+
+        let num: i64 = num.parse().map_err(|_| {
+            anyhow!(
+                "cannot parse age `{}`: expected a number and unit, like `30d`",
+                s
+            )
+        })?;
+
+        let unit_secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 7 * 86400,
+            _ => bail!(
+                "unrecognized unit in age `{}` (expected `s`, `m`, `h`, `d`, or `w`)",
+                s
+            ),
+        };
+
+        let duration = num
+            .checked_mul(unit_secs)
+            .and_then(chrono::Duration::try_seconds)
+            .ok_or_else(|| anyhow!("the age `{}` is too large", s))?;
+
+        Ok(AgeLimit {
+            text: s.to_owned(),
+            duration,
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, StructOpt)]
 struct StatusCommand {
+    #[structopt(
+        long = "porcelain",
+        help = "Print stable, tab-separated output suitable for scripts"
+    )]
+    porcelain: bool,
+
+    #[structopt(
+        long = "since",
+        help = "Only count commits newer than this age (e.g. `12h`, `30d`, `2w`)"
+    )]
+    since: Option<AgeLimit>,
+
+    #[structopt(
+        long = "check-drift",
+        help = "Check for project versions that don't match the release history",
+        conflicts_with_all = &["porcelain", "since"]
+    )]
+    check_drift: bool,
+
+    #[structopt(
+        long = "fail-on-unstaged",
+        help = "Exit with an error if any changed project has not been staged for release",
+        conflicts_with_all = &["porcelain", "since", "check-drift"]
+    )]
+    fail_on_unstaged: bool,
+
+    #[structopt(
+        long = "ignore-whitespace",
+        help = "Don't count commits that only change whitespace",
+        conflicts_with = "check-drift"
+    )]
+    ignore_whitespace: bool,
+
     #[structopt(help = "Name(s) of the project(s) to query (default: all)")]
     proj_names: Vec<String>,
 }
 
+impl StatusCommand {
+    /// Compare each project's version, as loaded from its metadata files,
+    /// with what Cranko expects it to be. That's either the development-mode
+    /// version or the version recorded in the latest release commit, which is
+    /// what the metadata files of a release commit will carry.
+    fn check_drift(sess: &app::AppSession, idents: Vec<project::ProjectId>) -> Result<i32> {
+        let rel_info = sess.repo.get_latest_release_info()?;
+        let mut n_drifted = 0;
+
+        for ident in idents {
+            let proj = sess.graph().lookup(ident);
+            let dev_version = proj.dev_mode_version();
+
+            if proj.version == dev_version {
+                continue;
+            }
+
+            let rel_version = rel_info.lookup_project(proj).map(|rel| &rel.version);
+
+            if rel_version == Some(&proj.version.to_string()) {
+                continue;
+            }
+
+            n_drifted += 1;
+
+            if let Some(rv) = rel_version {
+                println!(
+                    "{}: version is {}, but expected {} (development mode) or {} (latest release)",
+                    proj.user_facing_name, proj.version, dev_version, rv
+                );
+            } else {
+                println!(
+                    "{}: version is {}, but expected {} (development mode; no releases on record)",
+                    proj.user_facing_name, proj.version, dev_version
+                );
+            }
+        }
+
+        if n_drifted == 0 {
+            info!("no version drift detected");
+            Ok(0)
+        } else {
+            warn!(
+                "{} project(s) have versions inconsistent with Cranko's expectations",
+                n_drifted
+            );
+            warn!("... were project metadata files edited manually?");
+            Ok(1)
+        }
+    }
+
+    /// Find projects that have been changed since their last release, but
+    /// haven't been staged in the working tree. This is the same analysis
+    /// that leads `cranko confirm` to warn about projects that aren't part of
+    /// the rc submission.
+    fn check_unstaged(
+        sess: &app::AppSession,
+        idents: Vec<project::ProjectId>,
+        histories: graph::RepoHistories,
+    ) -> Result<i32> {
+        let mut changes = repository::ChangeList::default();
+        let mut n_unstaged = 0;
+
+        for ident in idents {
+            let proj = sess.graph().lookup(ident);
+            let history = histories.lookup(ident);
+
+            if history.n_commits() == 0 {
+                continue;
+            }
+
+            if sess.repo.scan_rc_info(proj, &mut changes, true)?.is_some() {
+                continue;
+            }
+
+            n_unstaged += 1;
+            println!(
+                "{}: {} relevant commit(s) since its last release, but not staged",
+                proj.user_facing_name,
+                history.n_commits()
+            );
+        }
+
+        if n_unstaged == 0 {
+            info!("all changed projects are staged");
+            Ok(0)
+        } else {
+            warn!(
+                "{} project(s) have been changed since their last release, but are not staged",
+                n_unstaged
+            );
+            warn!("... use `cranko stage` to stage them for release");
+            Ok(1)
+        }
+    }
+}
+
 impl Command for StatusCommand {
     fn execute(self) -> Result<i32> {
         let sess = app::AppSession::initialize_default()?;
@@ -1019,26 +2581,64 @@ impl Command for StatusCommand {
             .query(q)
             .context("cannot get requested statuses")?;
 
-        let histories = sess.analyze_histories()?;
+        if self.check_drift {
+            return Self::check_drift(&sess, idents);
+        }
+
+        let histories = if self.ignore_whitespace {
+            sess.analyze_histories_ignoring_whitespace()?
+        } else {
+            sess.analyze_histories()?
+        };
+
+        if self.fail_on_unstaged {
+            return Self::check_unstaged(&sess, idents, histories);
+        }
+
+        let now = chrono::Utc::now();
 
         for ident in idents {
             let proj = sess.graph().lookup(ident);
             let history = histories.lookup(ident);
-            let n = history.n_commits();
             let rel_info = history.release_info(&sess.repo)?;
 
+            let (n, window) = if let Some(ref since) = self.since {
+                let n = history
+                    .commits_with_dates(&sess.repo)?
+                    .into_iter()
+                    .filter(|(_, date)| now.signed_duration_since(*date) <= since.duration)
+                    .count();
+                (n, format!(" in the last {}", since.text))
+            } else {
+                (history.n_commits(), String::new())
+            };
+
+            if self.porcelain {
+                // Columns: name, commit count, last version, age. Keep this
+                // format stable!
+                match rel_info.lookup_project(proj) {
+                    Some(this_info) => println!(
+                        "{}\t{}\t{}\t{}",
+                        proj.user_facing_name, n, this_info.version, this_info.age
+                    ),
+                    None => println!("{}\t{}\t-\t-1", proj.user_facing_name, n),
+                }
+
+                continue;
+            }
+
             if let Some(this_info) = rel_info.lookup_project(proj) {
                 if this_info.age == 0 {
                     if n == 0 {
                         println!(
-                            "{}: no relevant commits since {}",
-                            proj.user_facing_name, this_info.version
+                            "{}: no relevant commits since {}{}",
+                            proj.user_facing_name, this_info.version, window
                         );
                     } else {
                         logger::Logger::println_highlighted(
                             format!("{}: ", proj.user_facing_name),
                             n,
-                            format!(" relevant commit(s) since {}", this_info.version),
+                            format!(" relevant commit(s) since {}{}", this_info.version, window),
                         );
                     }
                 } else {
@@ -1046,15 +2646,15 @@ impl Command for StatusCommand {
                         format!("{}: no more than ", proj.user_facing_name),
                         n,
                         format!(
-                            " relevant commit(s) since {} (unable to track in detail)",
-                            this_info.version
+                            " relevant commit(s) since {}{} (unable to track in detail)",
+                            this_info.version, window
                         ),
                     );
                 }
             } else {
                 println!(
-                    "{}: {} relevant commit(s) since start of history (no releases on record)",
-                    proj.user_facing_name, n
+                    "{}: {} relevant commit(s) since start of history{} (no releases on record)",
+                    proj.user_facing_name, n, window
                 );
             }
         }
@@ -1083,6 +2683,30 @@ fn do_external(all_args: Vec<String>) -> Result<i32> {
     exec_or_spawn(process::Command::new(command).args(args))
 }
 
+/// Run the Git command-line program, which some of our commands use for
+/// presentation. Cranko otherwise uses libgit2, so we can't assume that the CLI
+/// is installed.
+fn exec_git(cmd: &mut process::Command) -> Result<i32> {
+    let r = exec_or_spawn(cmd);
+    let git_missing = matches!(
+        r,
+        Err(ref e) if e
+            .downcast_ref::<std::io::Error>()
+            .map(|e| e.kind() == std::io::ErrorKind::NotFound)
+            .unwrap_or(false)
+    );
+
+    if git_missing {
+        Ok(atry!(
+            r;
+            ["could not run the `git` command-line program"]
+            (note "this command uses the Git CLI; install it, or make sure that it is on your $PATH")
+        ))
+    } else {
+        r
+    }
+}
+
 #[cfg(unix)]
 /// On Unix, exec() to replace ourselves with the child process. This function
 /// *should* never return.
@@ -1175,24 +2799,26 @@ fn search_directories() -> Vec<PathBuf> {
     dirs
 }
 
-// I tried to set up the line-ending character as a macro that evaluated to a string
-// literal, but couldn't get the macro imports to work, for some reason along the
-// lines of https://github.com/rust-lang/rust/issues/57966.
-
-#[cfg(not(windows))]
-#[macro_export]
-macro_rules! write_crlf {
-    ($stream:expr, $format:literal $($rest:tt)*) => {{
-        use std::io::Write;
-        write!($stream, $format $($rest)*).and_then(|_x| write!($stream, "\n"))
-    }}
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_list_qualified_names() {
+        let text = "# plan\n\nnpm:foo\nnpm:foo: minor bump\ncargo:bar:  major bump \nbaz\n";
+        let is_project = |name: &str| ["npm:foo", "cargo:bar", "baz"].contains(&name);
+        let entries = StageCommand::parse_stage_list(text, Path::new("list"), is_project).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("npm:foo".to_owned(), None),
+                ("npm:foo".to_owned(), Some("minor bump".to_owned())),
+                ("cargo:bar".to_owned(), Some("major bump".to_owned())),
+                ("baz".to_owned(), None),
+            ]
+        );
 
-#[cfg(windows)]
-#[macro_export]
-macro_rules! write_crlf {
-    ($stream:expr, $format:literal $($rest:tt)*) => {{
-        use std::io::Write;
-        write!($stream, $format $($rest)*).and_then(|_x| write!($stream, "\r\n"))
-    }}
+        assert!(StageCommand::parse_stage_list("baz:\n", Path::new("list"), is_project).is_err());
+    }
 }
@@ -0,0 +1,599 @@
+// Copyright 2020-2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Maven (Java) projects.
+//!
+//! We rewrite `pom.xml` files by splicing new text into the original file
+//! contents, so that formatting and comments are preserved.
+
+use anyhow::anyhow;
+use log::warn;
+use quick_xml::{events::Event, Reader};
+use std::{collections::HashMap, fs, io::Write, ops::Range, path::Path};
+
+use crate::{
+    app::{AppBuilder, AppSession},
+    atry,
+    config::ProjectConfiguration,
+    errors::Result,
+    line_endings::{LineEnding, LineEndingWriter},
+    project::{DepRequirement, DependencyTarget, ProjectId},
+    repository::{ChangeList, RepoPath, RepoPathBuf},
+    rewriters::{RewriteContext, Rewriter},
+    version::Version,
+};
+
+/// The prefix of the `<properties>` entries that record Cranko's internal
+/// dependency version requirements. The full property name is this prefix
+/// followed by the artifactId of the dependency.
+const INTERNAL_DEP_PROPERTY_PREFIX: &str = "cranko.internal-dep-version.";
+
+/// Framework for auto-loading Maven projects from the repository contents.
+#[derive(Debug, Default)]
+pub struct MavenLoader {
+    pom_paths: Vec<RepoPathBuf>,
+}
+
+impl MavenLoader {
+    pub fn process_index_item(&mut self, repopath: &RepoPath, basename: &RepoPath) {
+        if basename.as_ref() == b"pom.xml" {
+            self.pom_paths.push(repopath.to_owned());
+        }
+    }
+
+    /// Finalize autoloading any Maven projects. Consumes this object.
+    pub fn finalize(
+        self,
+        app: &mut AppBuilder,
+        pconfig: &HashMap<String, ProjectConfiguration>,
+    ) -> Result<()> {
+        struct Info {
+            ident: ProjectId,
+            pom_path: RepoPathBuf,
+            pom: PomData,
+        }
+
+        // (groupId, artifactId) => project info
+        let mut coords_to_info = HashMap::new();
+
+        for pom_path in self.pom_paths {
+            let p = app.repo.resolve_workdir(&pom_path);
+            let text = atry!(
+                fs::read_to_string(&p);
+                ["failed to read file `{}`", p.display()]
+            );
+            let pom = atry!(
+                PomData::parse(&text);
+                ["failed to parse Maven file `{}`", p.display()]
+            );
+
+            let group_id = match pom.group_id.as_ref().or(pom.parent_group_id.as_ref()) {
+                Some(g) => g.clone(),
+                None => {
+                    warn!(
+                        "ignoring Maven file `{}`: cannot find its groupId",
+                        p.display()
+                    );
+                    continue;
+                }
+            };
+
+            let artifact_id = match pom.artifact_id {
+                Some(ref a) => a.clone(),
+                None => {
+                    warn!(
+                        "ignoring Maven file `{}`: cannot find its artifactId",
+                        p.display()
+                    );
+                    continue;
+                }
+            };
+
+            let version = match pom.version {
+                Some((ref v, _)) => v,
+                None => {
+                    warn!(
+                        "ignoring Maven file `{}`: it does not specify its own <version>",
+                        p.display()
+                    );
+                    continue;
+                }
+            };
+
+            // Maven versions like `1.0` or `2.3-SNAPSHOT` are common, but we
+            // can only manage semver ones.
+            let version = match semver::Version::parse(version) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!(
+                        "ignoring Maven file `{}`: cannot parse its <version> \"{}\" as a semver version: {}",
+                        p.display(),
+                        version,
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            let qnames = vec![artifact_id.clone(), group_id.clone(), "maven".to_owned()];
+
+            if let Some(ident) = app.graph.try_add_project(qnames, pconfig) {
+                let proj = app.graph.lookup_mut(ident);
+                let (dirname, _basename) = pom_path.split_basename();
+                proj.prefix = Some(dirname.to_owned());
+                proj.version = Some(Version::Semver(version));
+
+                // Auto-register a rewriter to update this project's pom.xml.
+                let rewrite = PomRewriter::new(ident, pom_path.clone());
+                proj.rewriters.push(Box::new(rewrite));
+
+                coords_to_info.insert(
+                    (group_id, artifact_id),
+                    Info {
+                        ident,
+                        pom_path,
+                        pom,
+                    },
+                );
+            }
+        }
+
+        // Now that we've registered them all, we can populate the
+        // interdependencies.
+
+        for info in coords_to_info.values() {
+            for dep in &info.pom.deps {
+                let (group_id, artifact_id) = match (&dep.group_id, &dep.artifact_id) {
+                    (Some(g), Some(a)) => (g, a),
+                    _ => continue,
+                };
+
+                let dep_info = match coords_to_info.get(&(group_id.clone(), artifact_id.clone())) {
+                    Some(i) => i,
+                    None => continue,
+                };
+
+                let req = if let Some((text, _)) = info
+                    .pom
+                    .properties
+                    .get(&format!("{}{}", INTERNAL_DEP_PROPERTY_PREFIX, artifact_id))
+                {
+                    match app
+                        .repo
+                        .parse_history_ref(text)
+                        .and_then(|cref| app.repo.resolve_history_ref(&cref, &info.pom_path))
+                    {
+                        Ok(r) => r,
+
+                        Err(e) => {
+                            warn!(
+                                "invalid property `{}{}` in `{}`: {}",
+                                INTERNAL_DEP_PROPERTY_PREFIX,
+                                artifact_id,
+                                info.pom_path.escaped(),
+                                e
+                            );
+                            DepRequirement::Unavailable
+                        }
+                    }
+                } else {
+                    DepRequirement::Unavailable
+                };
+
+                let literal = dep
+                    .version
+                    .as_ref()
+                    .map(|(v, _)| v.clone())
+                    .unwrap_or_else(|| "UNDEFINED".to_owned());
+
+                app.graph.add_dependency(
+                    info.ident,
+                    DependencyTarget::Ident(dep_info.ident),
+                    literal,
+                    req,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Information gleaned from a `pom.xml` file.
+///
+/// Text values are stored along with their byte ranges in the file, so that
+/// they can be replaced in-place.
+#[derive(Debug, Default)]
+struct PomData {
+    group_id: Option<String>,
+    artifact_id: Option<String>,
+    version: Option<(String, Range<usize>)>,
+    parent_group_id: Option<String>,
+    deps: Vec<PomDependency>,
+    properties: HashMap<String, (String, Range<usize>)>,
+
+    /// The byte offset of the closing `</properties>` tag, if any.
+    properties_end: Option<usize>,
+
+    /// The byte offset of the closing `</project>` tag.
+    project_end: Option<usize>,
+}
+
+/// A `<dependency>` entry in a `pom.xml` file.
+#[derive(Debug, Default)]
+struct PomDependency {
+    group_id: Option<String>,
+    artifact_id: Option<String>,
+    version: Option<(String, Range<usize>)>,
+}
+
+impl PomData {
+    fn parse(text: &str) -> Result<Self> {
+        let mut data = PomData::default();
+        let mut reader = Reader::from_str(text);
+        let mut stack: Vec<String> = Vec::new();
+        let mut cur_dep: Option<PomDependency> = None;
+
+        loop {
+            let start = reader.buffer_position();
+
+            match reader.read_event()? {
+                Event::Start(ref e) => {
+                    let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+
+                    if name == "dependency" && in_dependency_list(&stack) {
+                        cur_dep = Some(PomDependency::default());
+                    }
+
+                    stack.push(name);
+                }
+
+                Event::End(ref e) => {
+                    let name = e.name();
+                    let name = name.as_ref();
+
+                    if name == b"dependency" {
+                        if let Some(dep) = cur_dep.take() {
+                            data.deps.push(dep);
+                        }
+                    } else if name == b"properties" && stack.len() == 2 {
+                        data.properties_end = Some(start);
+                    } else if name == b"project" && stack.len() == 1 {
+                        data.project_end = Some(start);
+                    }
+
+                    stack.pop();
+                }
+
+                Event::Text(ref t) => {
+                    let value = t.unescape()?.trim().to_owned();
+                    let range = start..reader.buffer_position();
+                    let path: Vec<&str> = stack.iter().map(|s| s.as_ref()).collect();
+
+                    match &path[..] {
+                        ["project", "groupId"] => data.group_id = Some(value),
+                        ["project", "artifactId"] => data.artifact_id = Some(value),
+                        ["project", "version"] => data.version = Some((value, range)),
+                        ["project", "parent", "groupId"] => data.parent_group_id = Some(value),
+                        ["project", "properties", name] => {
+                            data.properties.insert((*name).to_owned(), (value, range));
+                        }
+                        [.., "dependency", field] => {
+                            if let Some(ref mut dep) = cur_dep {
+                                match *field {
+                                    "groupId" => dep.group_id = Some(value),
+                                    "artifactId" => dep.artifact_id = Some(value),
+                                    "version" => dep.version = Some((value, range)),
+                                    _ => {}
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                Event::Eof => break,
+
+                _ => {}
+            }
+        }
+
+        return Ok(data);
+
+        fn in_dependency_list(stack: &[String]) -> bool {
+            let path: Vec<&str> = stack.iter().map(|s| s.as_ref()).collect();
+            matches!(
+                &path[..],
+                ["project", "dependencies"] | ["project", "dependencyManagement", "dependencies"]
+            )
+        }
+    }
+}
+
+/// Apply a set of text replacements to *text*. The replacements are ranges in
+/// the original text and must not overlap.
+fn splice_text(text: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(r, _)| r.start);
+
+    let mut new_text = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for (range, replacement) in edits {
+        new_text.push_str(&text[last..range.start]);
+        new_text.push_str(&replacement);
+        last = range.end;
+    }
+
+    new_text.push_str(&text[last..]);
+    new_text
+}
+
+/// Rewrite `pom.xml` to include real version numbers.
+#[derive(Debug)]
+pub struct PomRewriter {
+    proj_id: ProjectId,
+    pom_path: RepoPathBuf,
+}
+
+impl PomRewriter {
+    /// Create a new `pom.xml` rewriter.
+    pub fn new(proj_id: ProjectId, pom_path: RepoPathBuf) -> Self {
+        PomRewriter { proj_id, pom_path }
+    }
+
+    /// Apply a set of text replacements to the file, which is located at
+    /// *path*. See [`splice_text`].
+    fn splice(
+        &self,
+        path: &Path,
+        text: &str,
+        edits: Vec<(Range<usize>, String)>,
+        changes: &mut ChangeList,
+    ) -> Result<()> {
+        atry!(
+            fs::write(path, splice_text(text, edits));
+            ["failed to overwrite file `{}`", path.display()]
+        );
+        changes.add_path(&self.pom_path);
+        Ok(())
+    }
+
+    fn load(&self, path: &Path) -> Result<(String, PomData)> {
+        let text = atry!(
+            fs::read_to_string(path);
+            ["failed to read file `{}`", path.display()]
+        );
+        let pom = atry!(
+            PomData::parse(&text);
+            ["failed to parse Maven file `{}`", path.display()]
+        );
+        Ok((text, pom))
+    }
+}
+
+impl Rewriter for PomRewriter {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let path = ctx.resolve_workdir(&self.pom_path);
+        let _lock = ctx.lock_path(&self.pom_path);
+        let (text, pom) = self.load(&path)?;
+
+        // Helper table for applying internal deps. Note that we use the 0'th
+        // qname, the artifactId, since that is what is used in Maven-land.
+
+        let proj = ctx.graph().lookup(self.proj_id);
+        let mut internal_reqs = HashMap::new();
+
+        for dep in &proj.internal_deps[..] {
+            let req_text = match dep.cranko_requirement {
+                DepRequirement::Manual(ref t) => t.clone(),
+
+                DepRequirement::Commit(_) => {
+                    if let Some(ref v) = dep.resolved_version {
+                        v.to_string()
+                    } else {
+                        continue;
+                    }
+                }
+
+                DepRequirement::Unavailable => continue,
+            };
+
+            let dep_qnames = ctx.graph().lookup(dep.ident).qualified_names();
+            internal_reqs.insert((dep_qnames[1].clone(), dep_qnames[0].clone()), req_text);
+        }
+
+        // Figure out what to update.
+
+        let mut edits = Vec::new();
+
+        let (_, range) = pom.version.ok_or_else(|| {
+            anyhow!(
+                "no project <version> element in `{}`",
+                self.pom_path.escaped()
+            )
+        })?;
+        edits.push((range, proj.version.to_string()));
+
+        for dep in pom.deps {
+            let key = match (dep.group_id, dep.artifact_id) {
+                (Some(g), Some(a)) => (g, a),
+                _ => continue,
+            };
+
+            if let (Some(req_text), Some((cur_text, range))) =
+                (internal_reqs.get(&key), dep.version)
+            {
+                // Leave property references like `${project.version}` alone.
+                if !cur_text.starts_with("${") {
+                    edits.push((range, req_text.clone()));
+                }
+            }
+        }
+
+        self.splice(&path, &text, edits, changes)
+    }
+
+    /// Rewriting just the special Cranko requirement metadata.
+    fn rewrite_cranko_requirements(
+        &self,
+        app: &AppSession,
+        changes: &mut ChangeList,
+    ) -> Result<()> {
+        // Short-circuit if no deps.
+
+        if app.graph().lookup(self.proj_id).internal_deps.is_empty() {
+            return Ok(());
+        }
+
+        let path = app.repo.resolve_workdir(&self.pom_path);
+        let (text, pom) = self.load(&path)?;
+        let graph = app.graph();
+        let proj = graph.lookup(self.proj_id);
+        let mut edits = Vec::new();
+        let mut new_props = Vec::new();
+
+        for dep in &proj.internal_deps {
+            let prop_name = format!(
+                "{}{}",
+                INTERNAL_DEP_PROPERTY_PREFIX,
+                graph.lookup(dep.ident).qualified_names()[0]
+            );
+
+            let spec = match &dep.cranko_requirement {
+                DepRequirement::Commit(cid) => cid.to_string(),
+                DepRequirement::Manual(t) => format!("manual:{}", t),
+                DepRequirement::Unavailable => continue,
+            };
+
+            if let Some((_, range)) = pom.properties.get(&prop_name) {
+                edits.push((range.clone(), spec));
+            } else {
+                new_props.push(format!("<{0}>{1}</{0}>", prop_name, spec));
+            }
+        }
+
+        // New properties are inserted as simply as possible. If there's no
+        // `<properties>` section, we have to create one.
+
+        if !new_props.is_empty() {
+            let ending = LineEnding::detect(text.as_bytes()).unwrap_or_default();
+            let mut insertion = LineEndingWriter::new(Vec::new(), ending);
+
+            let offset = if let Some(offset) = pom.properties_end {
+                for prop in &new_props {
+                    atry!(
+                        writeln!(insertion, "  {}", prop);
+                        ["failed to format new pom.xml properties"]
+                    );
+                }
+
+                atry!(
+                    insertion.write_all(b"  ");
+                    ["failed to format new pom.xml properties"]
+                );
+                offset
+            } else if let Some(offset) = pom.project_end {
+                atry!(
+                    writeln!(insertion, "  <properties>");
+                    ["failed to format new pom.xml properties"]
+                );
+
+                for prop in &new_props {
+                    atry!(
+                        writeln!(insertion, "    {}", prop);
+                        ["failed to format new pom.xml properties"]
+                    );
+                }
+
+                atry!(
+                    writeln!(insertion, "  </properties>");
+                    ["failed to format new pom.xml properties"]
+                );
+                offset
+            } else {
+                return Err(anyhow!(
+                    "cannot find where to insert properties in `{}`",
+                    self.pom_path.escaped()
+                ));
+            };
+
+            edits.push((offset..offset, String::from_utf8(insertion.into_inner())?));
+        }
+
+        self.splice(&path, &text, edits, changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project>
+  <parent>
+    <groupId>org.example.parent</groupId>
+    <version>7</version>
+  </parent>
+  <artifactId>app</artifactId>
+  <!-- keep this comment -->
+  <version> 1.2.3 </version>
+  <properties>
+    <cranko.internal-dep-version.lib>manual:^1</cranko.internal-dep-version.lib>
+  </properties>
+  <dependencies>
+    <dependency>
+      <groupId>org.example</groupId>
+      <artifactId>lib</artifactId>
+      <version>1.0.0</version>
+    </dependency>
+    <dependency>
+      <groupId>org.example</groupId>
+      <artifactId>other</artifactId>
+      <version>${project.version}</version>
+    </dependency>
+  </dependencies>
+</project>
+"#;
+
+    #[test]
+    fn parse_pom() {
+        let pom = PomData::parse(POM).unwrap();
+
+        assert_eq!(pom.group_id, None);
+        assert_eq!(pom.parent_group_id.as_deref(), Some("org.example.parent"));
+        assert_eq!(pom.artifact_id.as_deref(), Some("app"));
+        assert_eq!(pom.version.as_ref().unwrap().0, "1.2.3");
+
+        assert_eq!(pom.deps.len(), 2);
+        assert_eq!(pom.deps[0].artifact_id.as_deref(), Some("lib"));
+        assert_eq!(pom.deps[0].version.as_ref().unwrap().0, "1.0.0");
+        assert_eq!(
+            pom.deps[1].version.as_ref().unwrap().0,
+            "${project.version}"
+        );
+
+        let (prop, _) = &pom.properties["cranko.internal-dep-version.lib"];
+        assert_eq!(prop, "manual:^1");
+        assert!(POM[pom.properties_end.unwrap()..].starts_with("</properties>"));
+        assert!(POM[pom.project_end.unwrap()..].starts_with("</project>"));
+    }
+
+    #[test]
+    fn splice_versions() {
+        let pom = PomData::parse(POM).unwrap();
+        let edits = vec![
+            (pom.deps[0].version.clone().unwrap().1, "1.1.0".to_owned()),
+            (pom.version.clone().unwrap().1, "1.3.0".to_owned()),
+        ];
+        let spliced = splice_text(POM, edits);
+
+        assert!(spliced.contains("<!-- keep this comment -->\n  <version>1.3.0</version>"));
+        assert!(spliced.contains("<artifactId>lib</artifactId>\n      <version>1.1.0</version>"));
+        assert!(spliced.contains("<version>${project.version}</version>"));
+        assert!(spliced.contains("<version>7</version>"));
+
+        let reparsed = PomData::parse(&spliced).unwrap();
+        assert_eq!(reparsed.version.unwrap().0, "1.3.0");
+        assert_eq!(reparsed.deps[0].version.as_ref().unwrap().0, "1.1.0");
+    }
+}
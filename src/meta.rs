@@ -0,0 +1,111 @@
+// Copyright 2022 Peter Williams <peter@newton.cx> and collaborators
+// Licensed under the MIT License.
+
+//! Virtual "meta" projects.
+//!
+//! A meta-project doesn't correspond to any files in the repository. Instead,
+//! it is defined in the configuration file and aggregates several other
+//! projects: it is considered to be affected by any change that affects one of
+//! them, and its version is the highest of their versions. This is useful for
+//! things like a documentation website that covers several components of a
+//! monorepo.
+
+use anyhow::bail;
+use std::collections::HashMap;
+
+use crate::{
+    app::AppBuilder,
+    atry,
+    config::ProjectConfiguration,
+    errors::Result,
+    repository::RepoPathBuf,
+    version::{DotNetVersion, Pep440Version, Version, VersionScheme},
+};
+
+/// Register all of the meta-projects defined in the configuration file.
+///
+/// This should be called after all of the other loaders have run, although
+/// the aggregated projects are only looked up once the graph is completed.
+pub fn load_meta_projects(
+    app: &mut AppBuilder,
+    pconfig: &HashMap<String, ProjectConfiguration>,
+) -> Result<()> {
+    // Sort for reproducibility of the project IDs.
+    let mut names: Vec<_> = pconfig.keys().collect();
+    names.sort();
+
+    for config_name in names {
+        let cfg = &pconfig[config_name];
+
+        match cfg.project_type.as_deref() {
+            None => {
+                if !cfg.aggregates.is_empty() {
+                    bail!(
+                        "project `{}` is configured with `aggregates`, but its `type` is not `meta`",
+                        config_name
+                    );
+                }
+
+                continue;
+            }
+
+            Some("meta") => {}
+
+            Some(other) => bail!(
+                "unrecognized `type` `{}` for project `{}` (expected `meta`)",
+                other,
+                config_name
+            ),
+        }
+
+        let name = match config_name.strip_prefix("meta:") {
+            Some(n) if !n.is_empty() && !n.contains(':') => n,
+            _ => bail!(
+                "the configuration of meta-project `{}` must be keyed as `meta:NAME`",
+                config_name
+            ),
+        };
+
+        if cfg.aggregates.is_empty() {
+            bail!(
+                "meta-project `{}` must list the projects that it `aggregates`",
+                config_name
+            );
+        }
+
+        if cfg.changelog_path.is_none() {
+            bail!(
+                "meta-project `{}` must specify its `changelog_path`",
+                config_name
+            );
+        }
+
+        // The version is just a placeholder that establishes the versioning
+        // scheme; the real value is computed when the graph is completed.
+
+        let scheme = match cfg.version_scheme {
+            Some(ref text) => atry!(
+                text.parse();
+                ["invalid `version_scheme` configuration for project `{}`", config_name]
+            ),
+            None => VersionScheme::Semver,
+        };
+
+        let version = match scheme {
+            VersionScheme::Semver => Version::Semver(semver::Version::new(0, 0, 0)),
+            VersionScheme::Pep440 => Version::Pep440(Pep440Version::default()),
+            VersionScheme::DotNet => Version::DotNet(DotNetVersion::default()),
+        };
+
+        let qnames = vec![name.to_owned(), "meta".to_owned()];
+
+        if let Some(ident) = app.graph.try_add_project(qnames, pconfig) {
+            let proj = app.graph.lookup_mut(ident);
+            proj.prefix = Some(RepoPathBuf::new(b""));
+            proj.version = Some(version);
+            proj.aggregates = Some(cfg.aggregates.clone());
+        }
+    }
+
+    Ok(())
+}
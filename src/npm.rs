@@ -9,12 +9,14 @@
 //! fix that.
 
 use anyhow::{anyhow, Context};
-use log::warn;
+use log::{info, warn};
 use std::{
     collections::HashMap,
     env,
     ffi::OsString,
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
     process,
 };
 use structopt::StructOpt;
@@ -22,16 +24,17 @@ use structopt::StructOpt;
 use super::Command;
 
 use crate::{
-    app::{AppBuilder, AppSession},
+    a_ok_or,
+    app::{self, AppBuilder, AppSession},
     atry,
     config::ProjectConfiguration,
     errors::Result,
     graph::{GraphQueryBuilder, ProjectGraphBuilder},
+    line_endings::{LineEnding, LineEndingWriter},
     project::{DepRequirement, DependencyTarget, ProjectId},
     repository::{ChangeList, RepoPath, RepoPathBuf, Repository},
-    rewriters::Rewriter,
+    rewriters::{RewriteContext, Rewriter},
     version::Version,
-    write_crlf,
 };
 
 const DEPENDENCY_KEYS: &[&str] = &["dependencies", "devDependencies", "optionalDependencies"];
@@ -99,31 +102,45 @@ impl NpmLoader {
             })?
             .to_owned();
 
-        let version = pkg_data
-            .get("version")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| {
-                anyhow!(
-                    "NPM file `{}` does not have a string-typed `version` field",
-                    path.display()
-                )
-            })?;
-        let version = atry!(
-            semver::Version::parse(version);
-            ["cannot parse `version` field \"{}\" in `{}` as a semver version",
-             version, path.display()]
-        );
-
         let qnames = vec![name.to_owned(), "npm".to_owned()];
+        let version_text = pkg_data.get("version").and_then(|v| v.as_str());
+        let version_in_file = version_text.is_some();
+
+        let version = match version_text {
+            Some(text) => Version::Semver(atry!(
+                semver::Version::parse(text);
+                ["cannot parse `version` field \"{}\" in `{}` as a semver version",
+                 text, path.display()]
+            )),
+
+            None => a_ok_or!(
+                app::version_from_command(
+                    repo,
+                    pconfig,
+                    &qnames,
+                    dirname,
+                    &Version::Semver(semver::Version::new(0, 0, 0)),
+                )?;
+                ["NPM file `{}` does not have a string-typed `version` field", path.display()]
+            ),
+        };
 
         if let Some(ident) = graph.try_add_project(qnames, pconfig) {
             let proj = graph.lookup_mut(ident);
             proj.prefix = Some(dirname.to_owned());
-            proj.version = Some(Version::Semver(version));
-
-            // Auto-register a rewriter to update this package's package.json.
-            let rewrite = PackageJsonRewriter::new(ident, repopath.to_owned());
-            proj.rewriters.push(Box::new(rewrite));
+            proj.version = Some(version);
+            proj.license = pkg_data
+                .get("license")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_owned());
+
+            // Auto-register a rewriter to update this package's package.json,
+            // unless its version came from elsewhere, in which case the file
+            // should be left alone.
+            if version_in_file {
+                let rewrite = PackageJsonRewriter::new(ident, repopath.to_owned());
+                proj.rewriters.push(Box::new(rewrite));
+            }
 
             // Save the info for dep-linking later.
             self.npm_to_graph.insert(
@@ -151,6 +168,21 @@ impl NpmLoader {
                 .get("internalDepVersions")
                 .and_then(|v| v.as_object());
 
+            // A dependency on a package that we haven't loaded will be treated
+            // as external, which is probably not what's wanted if there's
+            // internal version information for it. This can happen if the
+            // package lies outside of the `--prefix` directory.
+            if let Some(specs) = maybe_internal_specs {
+                for dep_name in specs.keys() {
+                    if !self.npm_to_graph.contains_key(dep_name) {
+                        warn!(
+                            "`internalDepVersions` of {} mentions `{}`, but no such package has been loaded",
+                            name, dep_name
+                        );
+                    }
+                }
+            }
+
             for dep_key in DEPENDENCY_KEYS {
                 if let Some(dep_map) = load_data.pkg_data.get(*dep_key).and_then(|v| v.as_object())
                 {
@@ -206,8 +238,9 @@ impl PackageJsonRewriter {
 }
 
 impl Rewriter for PackageJsonRewriter {
-    fn rewrite(&self, app: &AppSession, changes: &mut ChangeList) -> Result<()> {
-        let path = app.repo.resolve_workdir(&self.json_path);
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let path = ctx.resolve_workdir(&self.json_path);
+        let _lock = ctx.lock_path(&self.json_path);
 
         // Parse the JSON.
         let mut pkg_data: serde_json::Map<String, serde_json::Value> = {
@@ -225,7 +258,7 @@ impl Rewriter for PackageJsonRewriter {
         // qname, not the user-facing name, since that is what is used in
         // NPM-land.
 
-        let proj = app.graph().lookup(self.proj_id);
+        let proj = ctx.graph().lookup(self.proj_id);
         let mut internal_reqs = HashMap::new();
 
         for dep in &proj.internal_deps[..] {
@@ -240,7 +273,7 @@ impl Rewriter for PackageJsonRewriter {
                         // Yarn workspaces, which helps ensure that we always
                         // resolve internal deps internally.
 
-                        let (protocol, sep) = app
+                        let (protocol, sep) = ctx
                             .npm_config
                             .internal_dep_protocol
                             .as_ref()
@@ -268,7 +301,7 @@ impl Rewriter for PackageJsonRewriter {
             };
 
             internal_reqs.insert(
-                app.graph().lookup(dep.ident).qualified_names()[0].clone(),
+                ctx.graph().lookup(dep.ident).qualified_names()[0].clone(),
                 req_text,
             );
         }
@@ -281,6 +314,19 @@ impl Rewriter for PackageJsonRewriter {
             if let Some(dep_map) = pkg_data.get_mut(*dep_key).and_then(|v| v.as_object_mut()) {
                 for (dep_name, dep_spec) in dep_map.iter_mut() {
                     if let Some(text) = internal_reqs.get(dep_name) {
+                        // Leave `workspace:` requirements alone, so that the
+                        // package manager continues to resolve the dependency
+                        // within the workspace, unless we've been configured
+                        // to write them ourselves.
+                        let in_workspace = dep_spec
+                            .as_str()
+                            .map(|s| s.starts_with("workspace:"))
+                            .unwrap_or(false);
+
+                        if in_workspace && !text.starts_with("workspace:") {
+                            continue;
+                        }
+
                         *dep_spec = serde_json::Value::String(text.clone());
                     }
                 }
@@ -290,13 +336,14 @@ impl Rewriter for PackageJsonRewriter {
         // Write it out again.
 
         {
-            let mut f = File::create(&path)?;
+            let ending = LineEnding::for_file(&path)?;
+            let mut f = LineEndingWriter::new(File::create(&path)?, ending);
             atry!(
                 serde_json::to_writer_pretty(&mut f, &pkg_data);
                 ["failed to overwrite JSON file `{}`", path.display()]
             );
             atry!(
-                write_crlf!(f, "");
+                writeln!(f);
                 ["failed to overwrite JSON file `{}`", path.display()]
             );
             changes.add_path(&self.json_path);
@@ -369,7 +416,8 @@ impl Rewriter for PackageJsonRewriter {
         // Write it out again.
 
         {
-            let f = File::create(&path)?;
+            let ending = LineEnding::for_file(&path)?;
+            let f = LineEndingWriter::new(File::create(&path)?, ending);
             atry!(
                 serde_json::to_writer_pretty(f, &pkg_data);
                 ["failed to overwrite JSON file `{}`", path.display()]
@@ -396,6 +444,10 @@ pub enum NpmCommands {
     /// Write incorrect internal version requirements so that Lerna can
     /// understand them.
     LernaWorkaround(LernaWorkaroundCommand),
+
+    #[structopt(name = "pack-released")]
+    /// Create package tarballs for each released NPM project.
+    PackReleased(PackReleasedCommand),
 }
 
 #[derive(Debug, Eq, PartialEq, StructOpt)]
@@ -410,6 +462,7 @@ impl Command for NpmCommand {
             NpmCommands::ForeachReleased(o) => o.execute(),
             NpmCommands::InstallToken(o) => o.execute(),
             NpmCommands::LernaWorkaround(o) => o.execute(),
+            NpmCommands::PackReleased(o) => o.execute(),
         }
     }
 }
@@ -417,10 +470,48 @@ impl Command for NpmCommand {
 /// `cranko npm foreach-released`
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct ForeachReleasedCommand {
+    #[structopt(
+        long = "exclude",
+        help = "Skip the named project(s), even if released",
+        number_of_values = 1
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        long = "dist-tag",
+        help = "Append `--tag <channel>` to the command, deriving the npm dist-tag from each project's version"
+    )]
+    dist_tag: bool,
+
     #[structopt(help = "The command to run", required = true)]
     command: Vec<OsString>,
 }
 
+/// Determine the npm "dist-tag" under which a release should be published.
+///
+/// Stable releases go to `latest`. Prereleases go to a channel named after the
+/// first identifier of their prerelease component, so that `1.2.0-beta.1` goes
+/// to `beta`. npm rejects tags that look like versions, so if that identifier
+/// is numeric, we use `next` instead.
+fn dist_tag_for_version(version: &Version) -> Option<String> {
+    let v = match version {
+        Version::Semver(v) => v,
+        _ => return None,
+    };
+
+    if v.pre.is_empty() {
+        return Some("latest".to_owned());
+    }
+
+    let channel = v.pre.split('.').next().unwrap();
+
+    if channel.chars().all(|c| c.is_ascii_digit()) {
+        Some("next".to_owned())
+    } else {
+        Some(channel.to_owned())
+    }
+}
+
 impl Command for ForeachReleasedCommand {
     fn execute(self) -> Result<i32> {
         let sess = AppSession::initialize_default()?;
@@ -433,24 +524,35 @@ impl Command for ForeachReleasedCommand {
         let mut q = GraphQueryBuilder::default();
         q.only_new_releases(rel_info);
         q.only_project_type("npm");
+        q.exclude_names(self.exclude);
         let idents = sess
             .graph()
             .query(q)
             .context("could not select projects for `npm foreach-released`")?;
 
-        let mut cmd = process::Command::new(&self.command[0]);
-        if self.command.len() > 1 {
-            cmd.args(&self.command[1..]);
-        }
-
         let print_which = idents.len() > 1;
         let mut first = true;
 
         for ident in &idents {
             let proj = sess.graph().lookup(*ident);
             let dir = sess.repo.resolve_workdir(proj.prefix());
+
+            let mut cmd = process::Command::new(&self.command[0]);
+            if self.command.len() > 1 {
+                cmd.args(&self.command[1..]);
+            }
             cmd.current_dir(&dir);
 
+            if self.dist_tag {
+                let tag = a_ok_or!(
+                    dist_tag_for_version(&proj.version);
+                    ["cannot derive an npm dist-tag for project `{}`", proj.user_facing_name]
+                    (note "its version `{}` does not follow semver", proj.version)
+                );
+                info!("{}: using npm dist-tag `{}`", proj.user_facing_name, tag);
+                cmd.arg("--tag").arg(&tag);
+            }
+
             if print_which {
                 if first {
                     first = false;
@@ -476,6 +578,115 @@ impl Command for ForeachReleasedCommand {
     }
 }
 
+/// `cranko npm pack-released`
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+pub struct PackReleasedCommand {
+    #[structopt(
+        long = "command-name",
+        help = "The command name to use for NPM",
+        default_value = "npm"
+    )]
+    command_name: String,
+
+    #[structopt(
+        long = "exclude",
+        help = "Skip the named project(s), even if released",
+        number_of_values = 1
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        long = "dest-dir",
+        help = "The directory into which the tarballs should be placed",
+        required = true
+    )]
+    dest_dir: PathBuf,
+}
+
+impl Command for PackReleasedCommand {
+    fn execute(self) -> Result<i32> {
+        let sess = AppSession::initialize_default()?;
+        let command_name = self.command_name;
+        let dest_dir = self.dest_dir;
+
+        let (dev_mode, rel_info) = sess.ensure_ci_release_mode()?;
+        if dev_mode {
+            warn!("proceeding even though in dev mode");
+        }
+
+        let mut q = GraphQueryBuilder::default();
+        q.only_new_releases(rel_info);
+        q.only_project_type("npm");
+        q.exclude_names(self.exclude);
+        let idents = sess
+            .graph()
+            .query(q)
+            .context("could not select projects for `npm pack-released`")?;
+
+        atry!(
+            fs::create_dir_all(&dest_dir);
+            ["could not create directory `{}`", dest_dir.display()]
+        );
+
+        for ident in &idents {
+            let proj = sess.graph().lookup(*ident);
+            let dir = sess.repo.resolve_workdir(proj.prefix());
+
+            // `npm pack` prints the name of the tarball that it created as the
+            // last line of its output.
+            let output = process::Command::new(&command_name)
+                .arg("pack")
+                .current_dir(&dir)
+                .stderr(process::Stdio::inherit())
+                .output()
+                .with_context(|| {
+                    format!(
+                        "could not run `{} pack` for NPM project `{}`",
+                        command_name, proj.user_facing_name
+                    )
+                })?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "`{} pack` failed for NPM project `{}`",
+                    command_name,
+                    proj.user_facing_name
+                ));
+            }
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let tarball_name = atry!(
+                stdout.lines().rev().map(|l| l.trim()).find(|l| !l.is_empty())
+                    .ok_or_else(|| anyhow!("no output"));
+                ["couldn't determine the tarball created by `{} pack` for NPM project `{}`",
+                 command_name, proj.user_facing_name]
+            );
+
+            let src = dir.join(tarball_name);
+            let dest = dest_dir.join(a_ok_or!(
+                src.file_name();
+                ["unexpected tarball name `{}` from `{} pack`", tarball_name, command_name]
+            ));
+
+            // A rename won't work across filesystems, so fall back to copying.
+            if fs::rename(&src, &dest).is_err() {
+                atry!(
+                    fs::copy(&src, &dest);
+                    ["could not copy `{}` to `{}`", src.display(), dest.display()]
+                );
+                atry!(
+                    fs::remove_file(&src);
+                    ["could not remove `{}`", src.display()]
+                );
+            }
+
+            info!("`{}` => {}", proj.user_facing_name, dest.display());
+        }
+
+        Ok(0)
+    }
+}
+
 /// `cranko npm install-token`
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct InstallTokenCommand {
@@ -503,21 +714,23 @@ impl Command for InstallTokenCommand {
 
             p.push(".yarnrc.yml");
 
-            let mut file = atry!(
+            let ending = LineEnding::for_file(&p)?;
+            let file = atry!(
                 OpenOptions::new().create(true).append(true).open(&p);
                 ["failed to open file `{}` for appending", p.display()]
             );
+            let mut file = LineEndingWriter::new(file, ending);
 
             atry!(
-                write_crlf!(file, "npmRegistries:");
+                writeln!(file, "npmRegistries:");
                 ["failed to write token data to file `{}`", p.display()]
             );
             atry!(
-                write_crlf!(file, "  \"{}\":", registry);
+                writeln!(file, "  \"{}\":", registry);
                 ["failed to write token data to file `{}`", p.display()]
             );
             atry!(
-                write_crlf!(file, "    npmAuthToken: {}", token);
+                writeln!(file, "    npmAuthToken: {}", token);
                 ["failed to write token data to file `{}`", p.display()]
             );
         } else {
@@ -527,13 +740,15 @@ impl Command for InstallTokenCommand {
 
             p.push(".npmrc");
 
-            let mut file = atry!(
+            let ending = LineEnding::for_file(&p)?;
+            let file = atry!(
                 OpenOptions::new().create(true).append(true).open(&p);
                 ["failed to open file `{}` for appending", p.display()]
             );
+            let mut file = LineEndingWriter::new(file, ending);
 
             atry!(
-                write_crlf!(file, "{}:_authToken={}", registry, token);
+                writeln!(file, "{}:_authToken={}", registry, token);
                 ["failed to write token data to file `{}`", p.display()]
             );
         }
@@ -559,19 +774,123 @@ impl Command for LernaWorkaroundCommand {
 
         sess.fake_internal_deps();
 
-        let mut changes = ChangeList::default();
+        sess.rewrite_selected(&idents)?;
+        Ok(0)
+    }
+}
 
-        for ident in &idents {
-            let proj = sess.graph().lookup(*ident);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn tag_for(text: &str) -> Option<String> {
+        dist_tag_for_version(&Version::Semver(semver::Version::parse(text).unwrap()))
+    }
+
+    #[test]
+    fn dist_tags() {
+        assert_eq!(tag_for("1.2.0").as_deref(), Some("latest"));
+        assert_eq!(tag_for("1.2.0-beta.1").as_deref(), Some("beta"));
+        assert_eq!(tag_for("1.2.0-rc").as_deref(), Some("rc"));
+        assert_eq!(tag_for("1.2.0-0").as_deref(), Some("next"));
+        assert_eq!(tag_for("1.2.0-20211012.3").as_deref(), Some("next"));
+
+        let v = Version::Pep440("1.2.0b1".parse().unwrap());
+        assert_eq!(dist_tag_for_version(&v), None);
+    }
+
+    #[test]
+    fn workspace_members() {
+        use crate::{
+            app::AppBuilder, config::NpmConfiguration, project::DepRequirement,
+            repository::test_util::ScratchRepo,
+        };
+
+        let mut scratch = ScratchRepo::new("npm-workspace");
+        let paths = [
+            "package.json",
+            "packages/a/package.json",
+            "packages/b/package.json",
+            "packages/c/package.json",
+        ];
+
+        scratch.write(
+            paths[0],
+            r#"{"name": "root", "version": "0.0.0", "private": true, "workspaces": ["packages/*"]}"#,
+        );
+        scratch.write(paths[1], r#"{"name": "a", "version": "1.0.0"}"#);
+        scratch.write(
+            paths[2],
+            r#"{
+                "name": "b",
+                "version": "0.1.0",
+                "dependencies": {"a": "workspace:*"},
+                "internalDepVersions": {"a": "manual:^1.0.0"}
+            }"#,
+        );
+        scratch.write(
+            paths[3],
+            r#"{
+                "name": "c",
+                "version": "0.1.0",
+                "devDependencies": {"a": "^0.9.0", "left-pad": "^1.0.0"},
+                "internalDepVersions": {"a": "manual:^1.0.0"}
+            }"#,
+        );
+
+        let mut app = AppBuilder::new_for_testing(scratch.repo.take().unwrap());
+        let mut loader = NpmLoader::default();
+        let pconfig = HashMap::new();
+
+        for path in &paths {
+            let p = RepoPathBuf::new(path.as_bytes());
+            let (dirname, basename) = p.split_basename();
+            loader
+                .process_index_item(&app.repo, &mut app.graph, &p, dirname, basename, &pconfig)
+                .unwrap();
+        }
+
+        loader.finalize(&mut app).unwrap();
+        let graph = app.graph.complete_loading().unwrap();
+
+        // A private workspace root is loaded like any other package.
+
+        assert!(graph.lookup_ident("root").is_some());
+        let a = graph.lookup_ident("a").unwrap();
+        let b = graph.lookup(graph.lookup_ident("b").unwrap());
+        let c = graph.lookup(graph.lookup_ident("c").unwrap());
+
+        for (proj, literal) in [(b, "workspace:*"), (c, "^0.9.0")] {
+            assert_eq!(proj.internal_deps.len(), 1);
+            let dep = &proj.internal_deps[0];
+            assert_eq!(dep.ident, a);
+            assert_eq!(dep.literal, literal);
+            assert_eq!(
+                dep.cranko_requirement,
+                DepRequirement::Manual("^1.0.0".to_owned())
+            );
+        }
+
+        // Rewriting updates the version range, but not the `workspace:`
+        // requirement.
+
+        let npm_config = NpmConfiguration::default();
+        let ctx = RewriteContext::new(scratch.dir.clone(), &graph, &npm_config);
+        let mut changes = ChangeList::default();
+
+        for proj in [b, c] {
             for rw in &proj.rewriters {
-                atry!(
-                    rw.rewrite(&sess, &mut changes);
-                    ["failed to rewrite metadata for `{}`", proj.user_facing_name]
-                );
+                rw.rewrite(&ctx, &mut changes).unwrap();
             }
         }
 
-        Ok(0)
+        let dep_spec = |path: &str, key: &str| {
+            let text = std::fs::read_to_string(scratch.dir.join(path)).unwrap();
+            let data: serde_json::Value = serde_json::from_str(&text).unwrap();
+            data[key]["a"].as_str().unwrap().to_owned()
+        };
+
+        assert_eq!(dep_spec(paths[2], "dependencies"), "workspace:*");
+        assert_eq!(dep_spec(paths[3], "devDependencies"), "^1.0.0");
     }
 }
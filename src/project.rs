@@ -12,11 +12,13 @@
 use anyhow::{anyhow, bail};
 
 use crate::{
+    atry,
     changelog::{self, Changelog},
+    config::ReplacementConfiguration,
     errors::Result,
     repository::{CommitId, PathMatcher, RepoPath, RepoPathBuf},
-    rewriters::Rewriter,
-    version::Version,
+    rewriters::{ReplacementRewriter, Rewriter},
+    version::{Version, VersionScheme},
 };
 
 /// An internal, unique identifier for a project in this app session.
@@ -69,8 +71,23 @@ pub struct Project {
     /// How this project's changelog is formatted and updated.
     pub changelog: Box<dyn Changelog>,
 
+    /// The version to use in "development" mode, if one has been configured.
+    pub dev_version: Option<Version>,
+
     /// This project's internal dependencies.
     pub internal_deps: Vec<Dependency>,
+
+    /// The tiebreaker used to order this project relative to others that it
+    /// has no dependency relationship with. Lower values come first.
+    pub release_priority: i32,
+
+    /// If this is a virtual "meta" project, the projects that it aggregates.
+    /// Otherwise, this is empty.
+    pub aggregates: Vec<ProjectId>,
+
+    /// The project's declared license, as an SPDX expression, if the project
+    /// metadata specify one.
+    pub license: Option<String>,
 }
 
 impl Project {
@@ -95,6 +112,30 @@ impl Project {
     pub fn prefix(&self) -> &RepoPath {
         &self.prefix
     }
+
+    /// Get this project's "development mode" version: the configured
+    /// `dev_version` if there is one, or the scheme-specific default
+    /// otherwise.
+    pub fn dev_mode_version(&self) -> Version {
+        if let Some(ref v) = self.dev_version {
+            v.clone()
+        } else {
+            let mut v = self.version.clone();
+            v.set_to_dev_value();
+            v
+        }
+    }
+
+    /// Test whether this is a virtual "meta" project, whose version is derived
+    /// from the projects that it aggregates.
+    pub fn is_meta(&self) -> bool {
+        !self.aggregates.is_empty()
+    }
+
+    /// Reset this project's version to its "development mode" value.
+    pub fn set_to_dev_version(&mut self) {
+        self.version = self.dev_mode_version();
+    }
 }
 
 /// Metadata about internal interdependencies between projects.
@@ -164,6 +205,53 @@ pub struct ProjectBuilder {
     pub prefix: Option<RepoPathBuf>,
     pub rewriters: Vec<Box<dyn Rewriter>>,
     pub internal_deps: Vec<DependencyBuilder>,
+
+    /// A versioning scheme requested in the configuration file, overriding
+    /// the one implied by the project's autodetected version.
+    pub version_scheme: Option<String>,
+
+    /// Versioning schemes, besides that of the autodetected version, that the
+    /// project's rewriters can express. Loaders should fill this in if they
+    /// can support a `version_scheme` override.
+    pub alternate_version_schemes: Vec<VersionScheme>,
+
+    /// The changelog format requested in the configuration file, if any.
+    pub changelog_format: Option<String>,
+
+    /// The changelog path requested in the configuration file, if any.
+    pub changelog_path: Option<String>,
+
+    /// The news fragments directory requested in the configuration file, if
+    /// any.
+    pub changelog_fragments_dir: Option<String>,
+
+    /// The bump inference mode requested in the configuration file, if any.
+    pub infer_bump: Option<String>,
+
+    /// The development-mode version requested in the configuration file, if
+    /// any.
+    pub dev_version: Option<String>,
+
+    /// Paths to ignore when analyzing the project's history, as requested in
+    /// the configuration file.
+    pub ignore_paths: Vec<String>,
+
+    /// The release priority requested in the configuration file.
+    pub release_priority: i32,
+
+    /// Additional repository directories whose files affect the project.
+    pub extra_paths: Vec<String>,
+
+    /// User-configured textual replacements to make when rewriting the
+    /// project's version metadata.
+    pub replacements: Vec<ReplacementConfiguration>,
+
+    /// If this is a virtual "meta" project, the names of the projects that it
+    /// aggregates.
+    pub aggregates: Option<Vec<String>>,
+
+    /// The license declared in the project metadata, if any.
+    pub license: Option<String>,
 }
 
 /// An in-process dependency. We haven't necessarily yet resolved references to
@@ -198,6 +286,19 @@ impl ProjectBuilder {
             prefix: None,
             rewriters: Vec::new(),
             internal_deps: Vec::new(),
+            version_scheme: None,
+            alternate_version_schemes: Vec::new(),
+            changelog_format: None,
+            changelog_path: None,
+            changelog_fragments_dir: None,
+            infer_bump: None,
+            dev_version: None,
+            ignore_paths: Vec::new(),
+            release_priority: 0,
+            extra_paths: Vec::new(),
+            replacements: Vec::new(),
+            aggregates: None,
+            license: None,
         }
     }
 
@@ -207,6 +308,7 @@ impl ProjectBuilder {
         ident: ProjectId,
         user_facing_name: String,
         internal_deps: Vec<Dependency>,
+        aggregates: Vec<ProjectId>,
     ) -> Result<Project> {
         if self.qnames.is_empty() {
             bail!(
@@ -215,13 +317,54 @@ impl ProjectBuilder {
             );
         }
 
-        let version = self.version.ok_or_else(|| {
+        let mut version = self.version.ok_or_else(|| {
             anyhow!(
                 "could not load project `{}`: never figured out its version",
                 user_facing_name
             )
         })?;
 
+        if let Some(ref text) = self.version_scheme {
+            let scheme: VersionScheme = atry!(
+                text.parse();
+                ["invalid `version_scheme` configuration for project `{}`", user_facing_name]
+            );
+
+            if scheme != version.scheme() && !self.alternate_version_schemes.contains(&scheme) {
+                bail!(
+                    "project `{}` is configured to use the `{}` version scheme, but its project files \
+                     can only express `{}` versions",
+                    user_facing_name,
+                    scheme,
+                    version.scheme()
+                );
+            }
+
+            version = atry!(
+                version.convert_to_scheme(scheme);
+                ["cannot express version `{}` of project `{}` in the `{}` version scheme",
+                 version, user_facing_name, scheme]
+            );
+        }
+
+        let changelog = atry!(
+            changelog::from_config(
+                self.changelog_format.as_deref(),
+                self.changelog_path.as_deref(),
+                self.changelog_fragments_dir.as_deref(),
+                self.infer_bump.as_deref(),
+            );
+            ["invalid changelog configuration for project `{}`", user_facing_name]
+        );
+
+        let dev_version = match self.dev_version {
+            Some(ref text) => Some(atry!(
+                version.parse_like(text);
+                ["invalid `dev_version` configuration for project `{}`", user_facing_name]
+            )),
+            None => None,
+        };
+
         let prefix = self.prefix.ok_or_else(|| {
             anyhow!(
                 "could not load project `{}`: never figured out its directory prefix",
@@ -229,16 +372,63 @@ impl ProjectBuilder {
             )
         })?;
 
+        // Meta-projects don't have any files of their own, except for any
+        // that are explicitly configured.
+        let mut repo_paths = if self.aggregates.is_some() {
+            PathMatcher::new_empty()
+        } else {
+            PathMatcher::new_include(prefix.clone())
+        };
+
+        for path in &self.extra_paths {
+            let dir = path.trim_matches('/');
+
+            if dir.is_empty()
+                || dir
+                    .split('/')
+                    .any(|c| c.is_empty() || c == "." || c == "..")
+            {
+                bail!(
+                    "invalid `extra_paths` entry `{}` for project `{}`: it must name a subdirectory \
+                     of the repository",
+                    path,
+                    user_facing_name
+                );
+            }
+
+            repo_paths.add_include(RepoPathBuf::new(format!("{}/", dir).as_bytes()));
+        }
+
+        if !self.ignore_paths.is_empty() {
+            atry!(
+                repo_paths.set_ignores(prefix.clone(), &self.ignore_paths);
+                ["invalid `ignore_paths` configuration for project `{}`", user_facing_name]
+            );
+        }
+
+        let mut rewriters = self.rewriters;
+
+        for cfg in &self.replacements {
+            rewriters.push(Box::new(atry!(
+                ReplacementRewriter::new(ident, &prefix, cfg);
+                ["invalid `replacements` configuration for project `{}`", user_facing_name]
+            )));
+        }
+
         Ok(Project {
             ident,
             qnames: self.qnames,
             user_facing_name,
             version,
-            prefix: prefix.clone(),
-            rewriters: self.rewriters,
-            repo_paths: PathMatcher::new_include(prefix),
-            changelog: changelog::default(),
+            prefix,
+            rewriters,
+            repo_paths,
+            changelog,
+            dev_version,
             internal_deps,
+            release_priority: self.release_priority,
+            aggregates,
+            license: self.license,
         })
     }
 }
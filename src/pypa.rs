@@ -9,10 +9,9 @@ use log::warn;
 use serde::Deserialize;
 use std::{
     collections::{HashMap, HashSet},
-    env,
     ffi::OsString,
     fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Read},
+    io::{BufRead, BufReader, Read, Write},
     process,
 };
 use structopt::StructOpt;
@@ -22,16 +21,17 @@ use super::Command;
 
 use crate::{
     a_ok_or,
-    app::{AppBuilder, AppSession},
+    app::{self, AppBuilder, AppSession},
     atry,
     config::ProjectConfiguration,
+    env::require_secret,
     errors::{Error, Result},
     graph::GraphQueryBuilder,
+    line_endings::{LineEnding, LineEndingWriter},
     project::{DepRequirement, DependencyTarget, ProjectId},
     repository::{ChangeList, RepoPath, RepoPathBuf},
-    rewriters::Rewriter,
+    rewriters::{RewriteContext, Rewriter},
     version::{Pep440Version, Version},
-    write_crlf,
 };
 
 /// Framework for auto-loading PyPA projects from the repository contents.
@@ -44,7 +44,7 @@ impl PypaLoader {
     pub fn process_index_item(&mut self, dirname: &RepoPath, basename: &RepoPath) {
         let b = basename.as_ref();
 
-        if b == b"setup.py" || b == b"setup.cfg" && b == b"pyproject.toml" {
+        if b == b"setup.py" || b == b"setup.cfg" || b == b"pyproject.toml" {
             self.dirs_of_interest.insert(dirname.to_owned());
         }
     }
@@ -55,16 +55,14 @@ impl PypaLoader {
         app: &mut AppBuilder,
         pconfig: &HashMap<String, ProjectConfiguration>,
     ) -> Result<()> {
-        if self.dirs_of_interest.len() > 1 {
-            warn!("multiple Python projects detected. Internal interdependenciess are not yet supported.")
-        }
+        let mut n_projects = 0;
 
         for dirname in &self.dirs_of_interest {
             let mut name = None;
             let mut version = None;
             let mut main_version_file = None;
 
-            let dir_desc = if dirname.len() == 0 {
+            let dir_desc = if dirname.is_empty() {
                 "the toplevel directory".to_owned()
             } else {
                 format!("directory `{}`", dirname.escaped())
@@ -76,7 +74,7 @@ impl PypaLoader {
             let mut toml_repopath = dirname.clone();
             toml_repopath.push("pyproject.toml");
 
-            let config = {
+            let (config, pep621) = {
                 let toml_path = app.repo.resolve_workdir(&toml_repopath);
                 let f = match File::open(&toml_path) {
                     Ok(f) => Some(f),
@@ -107,14 +105,21 @@ impl PypaLoader {
                     })
                     .transpose()?;
 
-                let data = data.and_then(|d| d.tool).and_then(|t| t.cranko);
+                let (data, pep621) = match data {
+                    Some(d) => (d.tool.and_then(|t| t.cranko), d.project),
+                    None => (None, None),
+                };
 
                 if let Some(ref data) = data {
                     name = data.name.clone();
                     main_version_file = data.main_version_file.clone();
                 }
 
-                data
+                if name.is_none() {
+                    name = pep621.as_ref().and_then(|p| p.name.clone());
+                }
+
+                (data, pep621)
             };
 
             // Now let's see if we have anything to learn from `setup.cfg`.
@@ -165,15 +170,61 @@ impl PypaLoader {
                 }
             }
 
-            let main_version_file = main_version_file.unwrap_or_else(|| "setup.py".to_owned());
+            // Tools like Black and flake8 store their settings in
+            // `pyproject.toml` and `setup.cfg`, so those files don't
+            // necessarily mean that there's a Python project here. If we didn't
+            // find a name in them, only proceed if there's a `setup.py`.
+
+            let mut setup_repopath = dirname.clone();
+            setup_repopath.push("setup.py");
+
+            if name.is_none() && !app.repo.resolve_workdir(&setup_repopath).exists() {
+                warn!(
+                    "ignoring {}: its `pyproject.toml` or `setup.cfg` doesn't name a Python project, \
+                     and there is no `setup.py`",
+                    dir_desc
+                );
+                continue;
+            }
+
+            let mut main_version_file = main_version_file.unwrap_or_else(|| "setup.py".to_owned());
+
+            // If the main version file is `pyproject.toml`, the version should
+            // come from the PEP 621 `[project]` table ... unless it's marked as
+            // dynamic, in which case we fall back to the marker-based approach.
+
+            let mut main_version_in_pyproject = false;
+
+            if main_version_file == "pyproject.toml" {
+                let pep621 = a_ok_or!(
+                    pep621.as_ref();
+                    ["the `main_version_file` of the Python project in {} is `pyproject.toml`, \
+                      but it has no `[project]` section", dir_desc]
+                );
+
+                match pep621_version(pep621, &toml_repopath)? {
+                    Some(v) => {
+                        version = Some(v);
+                        main_version_in_pyproject = true;
+                    }
+
+                    None => {
+                        warn!(
+                            "the Python project in {} has a dynamic `project.version`; looking for a \
+                             `# cranko project-version` marker in `setup.py` instead",
+                            dir_desc
+                        );
+                        main_version_file = "setup.py".to_owned();
+                    }
+                }
+            }
+
             let main_version_in_setup = main_version_file == "setup.py";
 
             // Finally, how about setup.py?
 
             {
-                let mut setup_path = dirname.clone();
-                setup_path.push("setup.py");
-                let setup_path = app.repo.resolve_workdir(&setup_path);
+                let setup_path = app.repo.resolve_workdir(&setup_repopath);
 
                 let f = match File::open(&setup_path) {
                     Ok(f) => Some(f),
@@ -233,7 +284,7 @@ impl PypaLoader {
 
             // Do we need to look in yet another file to pull out the version?
 
-            if !main_version_in_setup {
+            if !main_version_in_setup && !main_version_in_pyproject {
                 let mut version_path = dirname.clone();
                 version_path.push(&main_version_file);
                 let version_path = app.repo.resolve_workdir(&version_path);
@@ -271,6 +322,22 @@ impl PypaLoader {
                       `[tool.cranko]` section of its `pyproject.toml`")
             );
 
+            // If we couldn't find the version in the files, the user might
+            // have configured a command to get it.
+
+            let version_in_file = version.is_some();
+
+            let version = match version {
+                Some(v) => Some(Version::Pep440(v)),
+                None => app::version_from_command(
+                    &app.repo,
+                    pconfig,
+                    &[name.clone(), "pypa".to_owned()],
+                    dirname,
+                    &Version::Pep440(Pep440Version::default()),
+                )?,
+            };
+
             let version = a_ok_or!(version;
                 ["could not identify the version of the Python project in {}", dir_desc]
                 (note "try adding a `# cranko project-version` comment at the end of a line containing \
@@ -281,18 +348,24 @@ impl PypaLoader {
             // OMG, we actually have the core info.
 
             let qnames = vec![name.clone(), "pypa".to_owned()];
+            n_projects += 1;
 
             if let Some(ident) = app.graph.try_add_project(qnames, pconfig) {
                 {
                     let proj = app.graph.lookup_mut(ident);
 
-                    proj.version = Some(Version::Pep440(version));
+                    proj.version = Some(version);
                     proj.prefix = Some(dirname.to_owned());
-
-                    let mut rw_path = dirname.clone();
-                    rw_path.push(main_version_file.as_bytes());
-                    let rw = PythonRewriter::new(ident, rw_path);
-                    proj.rewriters.push(Box::new(rw));
+                    proj.license = pep621.as_ref().and_then(|p| p.license());
+
+                    // If the version didn't come from the main version file,
+                    // there's nothing in it for us to rewrite.
+                    if version_in_file {
+                        let mut rw_path = dirname.clone();
+                        rw_path.push(main_version_file.as_bytes());
+                        let rw = PythonRewriter::new(ident, rw_path);
+                        proj.rewriters.push(Box::new(rw));
+                    }
                 }
 
                 // Handle the other annotated files. Besides registering them for
@@ -355,10 +428,60 @@ impl PypaLoader {
             }
         }
 
+        if n_projects > 1 {
+            warn!("multiple Python projects detected. Internal interdependenciess are not yet supported.")
+        }
+
         Ok(())
     }
 }
 
+/// Get a project's version from its PEP 621 `[project]` table. Returns None if
+/// the version is marked as dynamic, in which case it has to be found
+/// elsewhere.
+fn pep621_version(
+    pep621: &PyProjectProject,
+    toml_repopath: &RepoPath,
+) -> Result<Option<Pep440Version>> {
+    if pep621.dynamic.iter().any(|d| d == "version") {
+        return Ok(None);
+    }
+
+    let text = a_ok_or!(
+        pep621.version.as_ref();
+        ["the `[project]` section of `{}` has no `version` field", toml_repopath.escaped()]
+    );
+
+    Ok(Some(atry!(
+        text.parse();
+        ["failed to parse `project.version` value `{}` in `{}`", text, toml_repopath.escaped()]
+    )))
+}
+
+/// Set the `version` field in the `[project]` table of a `pyproject.toml`
+/// document, preserving any comments or whitespace around an existing value.
+/// Returns false if there's no `[project]` table.
+fn set_pep621_version(doc: &mut toml_edit::DocumentMut, version: &str) -> bool {
+    let project = match doc.get_mut("project").and_then(|i| i.as_table_like_mut()) {
+        Some(p) => p,
+        None => return false,
+    };
+
+    match project.get_mut("version").and_then(|i| i.as_value_mut()) {
+        Some(v) => {
+            let decor = v.decor().clone();
+            *v = version.into();
+            *v.decor_mut() = decor;
+        }
+
+        None => {
+            project.insert("version", toml_edit::value(version));
+        }
+    }
+
+    true
+}
+
 fn scan_rewritten_file(
     app: &mut AppBuilder,
     path: &RepoPath,
@@ -410,96 +533,79 @@ pub(crate) mod simple_py_parse {
         }
     }
 
-    pub fn extract_text_from_string_literal(line: &str) -> Result<String> {
-        let mut sq_loc = line.find('\'');
-        let mut dq_loc = line.find('"');
-
-        // if both kinds of quotes, go with whichever we saw first.
-        if let (Some(sq_idx), Some(dq_idx)) = (sq_loc, dq_loc) {
-            if sq_idx < dq_idx {
-                dq_loc = None;
-            } else {
-                sq_loc = None;
+    /// Locate the contents of the string literal in a line of code that
+    /// assigns a value, e.g. `__version__ = "1.0"  # cranko project-version`.
+    ///
+    /// We look for the first literal after the first `=` in the line, or the
+    /// first one in the line if there's no `=`. Quotation marks in a trailing
+    /// `#` comment are not considered, backslash escapes inside the literal are
+    /// skipped over, and triple-quoted literals are recognized. The returned
+    /// range excludes the quotation marks.
+    fn find_string_literal(line: &str) -> Result<std::ops::Range<usize>> {
+        let search_start = line.find('=').map(|i| i + 1).unwrap_or(0);
+        let mut opening = None;
+
+        for (i, c) in line[search_start..].char_indices() {
+            match c {
+                '#' => break,
+                '\'' | '"' => {
+                    opening = Some((search_start + i, c));
+                    break;
+                }
+                _ => {}
             }
         }
 
-        let inside = if let Some(sq_left) = sq_loc {
-            let sq_right = line.rfind('\'').unwrap();
-            if sq_right <= sq_left {
-                bail!(
-                    "expected a string literal in Python line `{}`, but only found one quote?",
-                    line
-                );
-            }
-
-            &line[sq_left + 1..sq_right]
-        } else if let Some(dq_left) = dq_loc {
-            let dq_right = line.rfind('"').unwrap();
-            if dq_right <= dq_left {
-                bail!(
-                    "expected a string literal in Python line `{}`, but only found one quote?",
-                    line
-                );
-            }
+        let (left_idx, quote) = match opening {
+            Some(t) => t,
+            None => bail!(
+                "expected a string literal in line `{}`, but didn't find any quotation marks",
+                line
+            ),
+        };
 
-            &line[dq_left + 1..dq_right]
+        let triple = quote.to_string().repeat(3);
+        let delim = if line[left_idx..].starts_with(&triple) {
+            triple
         } else {
-            bail!(
-                "expected a string literal in Python line `{}`, but didn't find any quotation marks",
-                line
-            );
+            quote.to_string()
         };
 
-        if inside.find('\\').is_some() {
-            bail!("the string literal in Python line `{}` seems to contain \\ escapes, which I can't handle", line);
+        let content_start = left_idx + delim.len();
+        let rest = &line[content_start..];
+        let mut escaped = false;
+
+        for (i, c) in rest.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if rest[i..].starts_with(&delim) {
+                return Ok(content_start..content_start + i);
+            }
         }
 
-        Ok(inside.to_owned())
+        bail!(
+            "expected a string literal in line `{}`, but it doesn't seem to be terminated",
+            line
+        );
     }
 
-    pub fn replace_text_in_string_literal(line: &str, new_val: &str) -> Result<String> {
-        let mut sq_loc = line.find('\'');
-        let mut dq_loc = line.find('"');
+    pub fn extract_text_from_string_literal(line: &str) -> Result<String> {
+        let inside = &line[find_string_literal(line)?];
 
-        // if both kinds of quotes, go with whichever we saw first.
-        if let (Some(sq_idx), Some(dq_idx)) = (sq_loc, dq_loc) {
-            if sq_idx < dq_idx {
-                dq_loc = None;
-            } else {
-                sq_loc = None;
-            }
+        if inside.find('\\').is_some() {
+            bail!("the string literal in Python line `{}` seems to contain \\ escapes, which I can't handle", line);
         }
 
-        let (left_idx, right_idx) = if let Some(sq_left) = sq_loc {
-            let sq_right = line.rfind('\'').unwrap();
-            if sq_right <= sq_left {
-                bail!(
-                    "expected a string literal in Python line `{}`, but only found one quote?",
-                    line
-                );
-            }
-
-            (sq_left, sq_right)
-        } else if let Some(dq_left) = dq_loc {
-            let dq_right = line.rfind('"').unwrap();
-            if dq_right <= dq_left {
-                bail!(
-                    "expected a string literal in Python line `{}`, but only found one quote?",
-                    line
-                );
-            }
-
-            (dq_left, dq_right)
-        } else {
-            bail!(
-                "expected a string literal in Python line `{}`, but didn't find any quotation marks",
-                line
-            );
-        };
+        Ok(inside.to_owned())
+    }
 
-        let mut replaced = line[..left_idx + 1].to_owned();
+    pub fn replace_text_in_string_literal(line: &str, new_val: &str) -> Result<String> {
+        let range = find_string_literal(line)?;
+        let mut replaced = line[..range.start].to_owned();
         replaced.push_str(new_val);
-        replaced.push_str(&line[right_idx..]);
+        replaced.push_str(&line[range.end..]);
         Ok(replaced)
     }
 
@@ -526,11 +632,189 @@ pub(crate) mod simple_py_parse {
         replaced.push_str(&line[right_idx + 1..]);
         Ok(replaced)
     }
+
+    /// Normalize a Python project name as per PEP 503, so that names that
+    /// differ only in case or punctuation compare equal.
+    pub fn normalize_project_name(name: &str) -> String {
+        let mut norm = String::with_capacity(name.len());
+        let mut in_sep = false;
+
+        for c in name.chars() {
+            if c == '-' || c == '_' || c == '.' {
+                if !in_sep {
+                    norm.push('-');
+                }
+
+                in_sep = true;
+            } else {
+                norm.extend(c.to_lowercase());
+                in_sep = false;
+            }
+        }
+
+        norm
+    }
+
+    /// Parse a line from a pip requirements file.
+    ///
+    /// If the line is a simple requirement, returns the name of the required
+    /// project and the byte range of its version specifiers, which may be
+    /// empty. Option lines (`-r other.txt`, `--index-url ...`), URL
+    /// requirements, comments, and blank lines yield None.
+    pub fn parse_requirement_line(line: &str) -> Option<(&str, std::ops::Range<usize>)> {
+        // A comment starts with a `#` at the beginning of the line or after
+        // whitespace.
+        let content_end = line
+            .char_indices()
+            .find(|&(i, c)| {
+                c == '#' && (i == 0 || line[..i].ends_with(|p: char| p.is_whitespace()))
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(line.len());
+        let content = &line[..content_end];
+        let start = content.len() - content.trim_start().len();
+        let rest = &content[start..];
+
+        if rest.is_empty() || rest.starts_with('-') {
+            return None;
+        }
+
+        let name_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+
+        if name_len == 0 {
+            return None;
+        }
+
+        let name = &rest[..name_len];
+        let mut spec_start = start + name_len;
+
+        // Skip any extras, e.g. `name[extra1,extra2]`.
+        let after_name = &content[spec_start..];
+        let after_ws = after_name.trim_start();
+
+        if after_ws.starts_with('[') {
+            let close = after_ws.find(']')?;
+            spec_start += after_name.len() - after_ws.len() + close + 1;
+        }
+
+        // Environment markers follow a `;`.
+        let spec_end = content[spec_start..]
+            .find(';')
+            .map(|i| spec_start + i)
+            .unwrap_or(content_end);
+
+        if content[spec_start..spec_end].trim_start().starts_with('@') {
+            return None;
+        }
+
+        Some((name, spec_start..spec_end))
+    }
+
+    /// Rewrite the version specifiers of a requirements-file line to refer to
+    /// a new version.
+    ///
+    /// Pins and lower bounds (`==`, `===`, `~=`, and `>=`) are updated to the
+    /// new version, preserving their operators, while other specifiers (such as
+    /// upper bounds) are left alone. Returns None if the line has no
+    /// specifiers that could be updated.
+    pub fn replace_requirement_version(line: &str, new_version: &str) -> Option<String> {
+        const OPERATORS: &[&str] = &["===", "==", "~=", ">=", "<=", "!=", "<", ">"];
+
+        let (_name, spec_range) = parse_requirement_line(line)?;
+        let mut did_anything = false;
+        let mut clauses = Vec::new();
+
+        for clause in line[spec_range.clone()].split(',') {
+            let lead = clause.len() - clause.trim_start().len();
+            let body = clause.trim();
+            let trail = &clause[lead + body.len()..];
+
+            let op = match OPERATORS.iter().find(|op| body.starts_with(*op)) {
+                Some(op) if ["===", "==", "~=", ">="].contains(op) => op,
+                _ => {
+                    clauses.push(clause.to_owned());
+                    continue;
+                }
+            };
+
+            let after_op = &body[op.len()..];
+            let ws = &after_op[..after_op.len() - after_op.trim_start().len()];
+            clauses.push(format!(
+                "{}{}{}{}{}",
+                &clause[..lead],
+                op,
+                ws,
+                new_version,
+                trail
+            ));
+            did_anything = true;
+        }
+
+        if !did_anything {
+            return None;
+        }
+
+        let mut replaced = line[..spec_range.start].to_owned();
+        replaced.push_str(&clauses.join(","));
+        replaced.push_str(&line[spec_range.end..]);
+        Some(replaced)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn rep(line: &str) -> String {
+            replace_text_in_string_literal(line, "1.2.3").unwrap()
+        }
+
+        #[test]
+        fn replace_literal() {
+            assert_eq!(rep("__version__ = '0.0.0'"), "__version__ = '1.2.3'");
+            assert_eq!(
+                rep(r#"__version__ = "0.0.0"  # noqa: E501"#),
+                r#"__version__ = "1.2.3"  # noqa: E501"#
+            );
+            assert_eq!(
+                rep("__version__ = '0.0.0'  # cranko project-version; don't edit"),
+                "__version__ = '1.2.3'  # cranko project-version; don't edit"
+            );
+            assert_eq!(
+                rep(r#"__version__ = "0.0.0"  # the "version""#),
+                r#"__version__ = "1.2.3"  # the "version""#
+            );
+            assert_eq!(
+                rep("version = '''0.0.0'''  # it's"),
+                "version = '''1.2.3'''  # it's"
+            );
+            assert_eq!(
+                rep(r#"[assembly: AssemblyVersion("1.0.0.0")] // it's"#),
+                r#"[assembly: AssemblyVersion("1.2.3")] // it's"#
+            );
+            assert!(
+                replace_text_in_string_literal("__version__ = VERSION  # 'x'", "1.2.3").is_err()
+            );
+            assert!(replace_text_in_string_literal("__version__ = '0.0.0", "1.2.3").is_err());
+        }
+
+        #[test]
+        fn extract_literal() {
+            assert_eq!(
+                extract_text_from_string_literal("__version__ = '0.1.0'  # don't touch").unwrap(),
+                "0.1.0"
+            );
+            assert!(extract_text_from_string_literal(r"x = 'a\'b'").is_err());
+        }
+    }
 }
 
 /// Toplevel `pyproject.toml` deserialization container.
 #[derive(Debug, Deserialize)]
 struct PyProjectFile {
+    pub project: Option<PyProjectProject>,
+
     pub tool: Option<PyProjectTool>,
 
     #[allow(dead_code)]
@@ -538,6 +822,36 @@ struct PyProjectFile {
     pub rest: Value,
 }
 
+/// `pyproject.toml` section `project` deserialization container, as
+/// standardized in PEP 621.
+#[derive(Debug, Deserialize)]
+struct PyProjectProject {
+    pub name: Option<String>,
+
+    pub version: Option<String>,
+
+    /// Fields whose values are computed by the build backend rather than
+    /// specified statically.
+    #[serde(default)]
+    pub dynamic: Vec<String>,
+
+    #[serde(flatten)]
+    pub rest: Value,
+}
+
+impl PyProjectProject {
+    /// Get the declared license, which may either be an SPDX expression (as
+    /// in PEP 639) or a table with a `text` field.
+    fn license(&self) -> Option<String> {
+        let license = self.rest.get("license")?;
+
+        license
+            .as_str()
+            .or_else(|| license.get("text").and_then(|t| t.as_str()))
+            .map(|s| s.to_owned())
+    }
+}
+
 /// `pyproject.toml` section `tool` deserialization container.
 #[derive(Debug, Deserialize)]
 struct PyProjectTool {
@@ -561,6 +875,7 @@ struct PyProjectCranko {
     pub main_version_file: Option<String>,
 
     /// Additional Python files that should be rewritten on metadata changes.
+    /// Pip requirements files are also supported.
     #[serde(default)]
     pub annotated_files: Vec<String>,
 
@@ -583,10 +898,151 @@ impl PythonRewriter {
     }
 }
 
+impl PythonRewriter {
+    /// Rewrite the PEP 621 `project.version` field of a `pyproject.toml` file.
+    fn rewrite_pyproject(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let toml_path = ctx.resolve_workdir(&self.file_path);
+        let s = atry!(
+            std::fs::read_to_string(&toml_path);
+            ["failed to read file `{}`", toml_path.display()]
+        );
+        let mut doc: toml_edit::DocumentMut = atry!(
+            s.parse();
+            ["could not parse file `{}` as TOML", toml_path.display()]
+        );
+
+        let proj = ctx.graph().lookup(self.proj_id);
+
+        if !set_pep621_version(&mut doc, &proj.version.to_string()) {
+            bail!("no `[project]` section in `{}`", self.file_path.escaped());
+        }
+
+        {
+            let mut f = atry!(
+                File::create(&toml_path);
+                ["failed to open file `{}` for writing", toml_path.display()]
+            );
+            atry!(
+                write!(f, "{}", doc);
+                ["failed to write file `{}`", toml_path.display()]
+            );
+        }
+
+        changes.add_path(&self.file_path);
+        Ok(())
+    }
+
+    /// Whether our file looks like a pip requirements or constraints file.
+    fn is_requirements_file(&self) -> bool {
+        let basename = String::from_utf8_lossy(self.file_path.split_basename().1.as_ref());
+        basename.ends_with(".txt")
+            && (basename.starts_with("requirements") || basename.starts_with("constraints"))
+    }
+
+    /// Rewrite the requirements on internal dependencies in a pip
+    /// requirements file.
+    fn rewrite_requirements(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let file_path = ctx.resolve_workdir(&self.file_path);
+        let proj = ctx.graph().lookup(self.proj_id);
+
+        // Only dependencies expressed as commits have versions for us to
+        // fill in. Manual requirements are the user's responsibility.
+
+        let mut internal_versions = HashMap::new();
+
+        for dep in &proj.internal_deps[..] {
+            if let (DepRequirement::Commit(_), Some(v)) =
+                (&dep.cranko_requirement, &dep.resolved_version)
+            {
+                internal_versions.insert(
+                    simple_py_parse::normalize_project_name(
+                        &ctx.graph().lookup(dep.ident).user_facing_name,
+                    ),
+                    v.to_string(),
+                );
+            }
+        }
+
+        let cur_f = atry!(
+            File::open(&file_path);
+            ["failed to open file `{}` for reading", file_path.display()]
+        );
+        let cur_reader = BufReader::new(cur_f);
+        let mut did_anything = false;
+
+        let ending = LineEnding::for_file(&file_path)?;
+
+        let new_af = atomicwrites::AtomicFile::new(
+            &file_path,
+            atomicwrites::OverwriteBehavior::AllowOverwrite,
+        );
+
+        let r = new_af.write(|new_f| {
+            let mut new_f = LineEndingWriter::new(new_f, ending);
+
+            for line in cur_reader.lines() {
+                let line = atry!(
+                    line;
+                    ["error reading data from file `{}`", file_path.display()]
+                );
+
+                let new_version =
+                    simple_py_parse::parse_requirement_line(&line).and_then(|(name, _)| {
+                        internal_versions.get(&simple_py_parse::normalize_project_name(name))
+                    });
+
+                let line = match new_version
+                    .and_then(|v| simple_py_parse::replace_requirement_version(&line, v))
+                {
+                    Some(new_line) => {
+                        did_anything = true;
+                        new_line
+                    }
+
+                    None => line,
+                };
+
+                atry!(
+                    writeln!(new_f, "{}", line);
+                    ["error writing data to `{}`", new_af.path().display()]
+                );
+            }
+
+            Ok(())
+        });
+
+        match r {
+            Err(atomicwrites::Error::Internal(e)) => Err(e.into()),
+            Err(atomicwrites::Error::User(e)) => Err(e),
+            Ok(()) => {
+                if !did_anything {
+                    warn!(
+                        "rewriter for Python requirements file `{}` didn't make any modifications",
+                        file_path.display()
+                    );
+                }
+
+                changes.add_path(&self.file_path);
+                Ok(())
+            }
+        }
+    }
+}
+
 impl Rewriter for PythonRewriter {
-    fn rewrite(&self, app: &AppSession, changes: &mut ChangeList) -> Result<()> {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let _lock = ctx.lock_path(&self.file_path);
+
+        if self.file_path.split_basename().1.as_ref() == b"pyproject.toml" {
+            return self.rewrite_pyproject(ctx, changes);
+        }
+
+        if self.is_requirements_file() {
+            return self.rewrite_requirements(ctx, changes);
+        }
+
         let mut did_anything = false;
-        let file_path = app.repo.resolve_workdir(&self.file_path);
+        let file_path = ctx.resolve_workdir(&self.file_path);
 
         let cur_f = atry!(
             File::open(&file_path);
@@ -596,7 +1052,7 @@ impl Rewriter for PythonRewriter {
 
         // Helper table for applying internal deps if needed.
 
-        let proj = app.graph().lookup(self.proj_id);
+        let proj = ctx.graph().lookup(self.proj_id);
         let mut internal_reqs = HashMap::new();
 
         for dep in &proj.internal_deps[..] {
@@ -615,21 +1071,25 @@ impl Rewriter for PythonRewriter {
             };
 
             internal_reqs.insert(
-                app.graph().lookup(dep.ident).user_facing_name.clone(),
+                ctx.graph().lookup(dep.ident).user_facing_name.clone(),
                 req_text,
             );
         }
 
         // OK, now rewrite the file.
 
+        let ending = LineEnding::for_file(&file_path)?;
+
         let new_af = atomicwrites::AtomicFile::new(
             &file_path,
             atomicwrites::OverwriteBehavior::AllowOverwrite,
         );
 
-        let proj = app.graph().lookup(self.proj_id);
+        let proj = ctx.graph().lookup(self.proj_id);
 
         let r = new_af.write(|new_f| {
+            let mut new_f = LineEndingWriter::new(new_f, ending);
+
 
             for (line_num0, line) in cur_reader.lines().enumerate() {
                 let line = atry!(
@@ -687,7 +1147,7 @@ impl Rewriter for PythonRewriter {
                 };
 
                 atry!(
-                    write_crlf!(new_f, "{}", line);
+                    writeln!(new_f, "{}", line);
                     ["error writing data to `{}`", new_af.path().display()]
                 );
             }
@@ -743,6 +1203,19 @@ impl Command for PythonCommand {
 /// `cranko python foreach-released`
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct ForeachReleasedCommand {
+    #[structopt(
+        long = "exclude",
+        help = "Skip the named project(s), even if released",
+        number_of_values = 1
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        long = "at-root",
+        help = "Run the command from the repository root, replacing `{dir}` in its arguments with each project's directory"
+    )]
+    at_root: bool,
+
     #[structopt(help = "The command to run", required = true)]
     command: Vec<OsString>,
 }
@@ -759,22 +1232,41 @@ impl Command for ForeachReleasedCommand {
         let mut q = GraphQueryBuilder::default();
         q.only_new_releases(rel_info);
         q.only_project_type("pypa");
+        q.exclude_names(self.exclude);
         let idents = sess
             .graph()
             .query(q)
             .context("could not select projects for `python foreach-released`")?;
 
-        let mut cmd = process::Command::new(&self.command[0]);
-        if self.command.len() > 1 {
-            cmd.args(&self.command[1..]);
-        }
-
+        let root = sess.repo.resolve_workdir(&RepoPathBuf::new(b""));
         let print_which = idents.len() > 1;
         let mut first = true;
 
         for ident in &idents {
             let proj = sess.graph().lookup(*ident);
-            let dir = sess.repo.resolve_workdir(proj.prefix());
+            let mut cmd = process::Command::new(&self.command[0]);
+
+            let dir = if self.at_root {
+                let prefix = proj.prefix().pop_sep();
+                let dir_text = if prefix.is_empty() {
+                    ".".to_owned()
+                } else {
+                    prefix.as_path().display().to_string()
+                };
+
+                // Arguments that aren't valid Unicode can't contain the
+                // placeholder in any meaningful way, so we pass them through.
+                cmd.args(self.command[1..].iter().map(|arg| match arg.to_str() {
+                    Some(s) => OsString::from(s.replace("{dir}", &dir_text)),
+                    None => arg.clone(),
+                }));
+
+                root.clone()
+            } else {
+                cmd.args(&self.command[1..]);
+                sess.repo.resolve_workdir(proj.prefix())
+            };
+
             cmd.current_dir(&dir);
 
             if print_which {
@@ -783,7 +1275,12 @@ impl Command for ForeachReleasedCommand {
                 } else {
                     println!();
                 }
-                println!("### in `{}`:", dir.display());
+
+                if self.at_root {
+                    println!("### for `{}`:", proj.user_facing_name);
+                } else {
+                    println!("### in `{}`:", dir.display());
+                }
             }
 
             let status = cmd.status().context(format!(
@@ -815,24 +1312,23 @@ pub struct InstallTokenCommand {
 
 impl Command for InstallTokenCommand {
     fn execute(self) -> Result<i32> {
-        let token = atry!(
-            env::var("PYPI_TOKEN");
-            ["missing or non-textual environment variable PYPI_TOKEN"]
-        );
+        let token = require_secret("PYPI_TOKEN")?;
 
         let mut p =
             dirs::home_dir().ok_or_else(|| anyhow!("cannot determine user's home directory"))?;
         p.push(".pypirc");
 
-        let mut file = atry!(
+        let ending = LineEnding::for_file(&p)?;
+        let file = atry!(
             OpenOptions::new().create(true).append(true).open(&p);
             ["failed to open file `{}` for appending", p.display()]
         );
+        let mut file = LineEndingWriter::new(file, ending);
 
         let mut write = || -> Result<()> {
-            write_crlf!(file, "[{}]", self.repository)?;
-            write_crlf!(file, "username = __token__")?;
-            write_crlf!(file, "password = {}", token)?;
+            writeln!(file, "[{}]", self.repository)?;
+            writeln!(file, "username = __token__")?;
+            writeln!(file, "password = {}", token)?;
             Ok(())
         };
 
@@ -844,3 +1340,58 @@ impl Command for InstallTokenCommand {
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_project(text: &str) -> PyProjectProject {
+        toml::from_str::<PyProjectFile>(text)
+            .unwrap()
+            .project
+            .unwrap()
+    }
+
+    #[test]
+    fn pep621_versions() {
+        let path = RepoPathBuf::new(b"pyproject.toml");
+
+        let p = parse_project("[project]\nname = \"foo\"\nversion = \"1.2.3\"\n");
+        assert_eq!(
+            pep621_version(&p, &path).unwrap().unwrap().to_string(),
+            "1.2.3"
+        );
+
+        let p = parse_project("[project]\nname = \"foo\"\ndynamic = [\"version\"]\n");
+        assert!(pep621_version(&p, &path).unwrap().is_none());
+
+        let p = parse_project("[project]\nname = \"foo\"\n");
+        assert!(pep621_version(&p, &path).is_err());
+
+        let p = parse_project("[project]\nname = \"foo\"\nversion = \"not a version\"\n");
+        assert!(pep621_version(&p, &path).is_err());
+    }
+
+    #[test]
+    fn rewrite_pep621_version() {
+        fn rewrite(text: &str) -> Option<String> {
+            let mut doc: toml_edit::DocumentMut = text.parse().unwrap();
+
+            if set_pep621_version(&mut doc, "1.3.0") {
+                Some(doc.to_string())
+            } else {
+                None
+            }
+        }
+
+        assert_eq!(
+            rewrite("[project]\nname = \"foo\"\nversion =  \"1.2.0\"  # the version\n").unwrap(),
+            "[project]\nname = \"foo\"\nversion =  \"1.3.0\"  # the version\n"
+        );
+        assert_eq!(
+            rewrite("[project]\nname = \"foo\"\n").unwrap(),
+            "[project]\nname = \"foo\"\nversion = \"1.3.0\"\n"
+        );
+        assert_eq!(rewrite("[tool.black]\nline-length = 100\n"), None);
+    }
+}
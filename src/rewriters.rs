@@ -3,13 +3,116 @@
 
 //! Updating project versioning metadata in the repository.
 
-use crate::{app::AppSession, errors::Result, repository::ChangeList};
+use log::warn;
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    sync::{Condvar, Mutex},
+};
+
+use crate::{
+    app::AppSession,
+    atry,
+    config::{NpmConfiguration, ReplacementConfiguration},
+    errors::Result,
+    graph::ProjectGraph,
+    project::ProjectId,
+    repository::{ChangeList, RepoPath, RepoPathBuf},
+};
+
+/// The context in which metadata rewriting happens.
+///
+/// The rewriters for different projects are run in parallel, so they can't be
+/// given the full app session, which isn't thread-safe. Instead they get this
+/// subset of it. Some files are shared between projects (e.g., a Cargo
+/// workspace's root manifest), so rewriters must also hold a lock on each file
+/// while they update it, using [`RewriteContext::lock_path`].
+#[derive(Debug)]
+pub struct RewriteContext<'a> {
+    /// The NPM configuration, as in the same-named field of the app session.
+    pub npm_config: &'a NpmConfiguration,
+
+    workdir: PathBuf,
+    graph: &'a ProjectGraph,
+    busy_paths: Mutex<HashSet<RepoPathBuf>>,
+    path_released: Condvar,
+}
+
+impl<'a> RewriteContext<'a> {
+    /// Create a new context for rewriting in the specified working directory.
+    pub fn new(
+        workdir: PathBuf,
+        graph: &'a ProjectGraph,
+        npm_config: &'a NpmConfiguration,
+    ) -> Self {
+        RewriteContext {
+            npm_config,
+            workdir,
+            graph,
+            busy_paths: Mutex::new(HashSet::new()),
+            path_released: Condvar::new(),
+        }
+    }
+
+    /// Get the graph of projects being rewritten.
+    pub fn graph(&self) -> &ProjectGraph {
+        self.graph
+    }
+
+    /// Resolve a path within the repository working directory to a filesystem
+    /// path, like `Repository::resolve_workdir`.
+    pub fn resolve_workdir(&self, p: &RepoPath) -> PathBuf {
+        let mut fullpath = self.workdir.clone();
+        fullpath.push(p.as_path());
+        fullpath
+    }
+
+    /// Take out an exclusive lock on a file in the working directory, blocking
+    /// until any other rewriter has finished with it. The lock is released
+    /// when the returned guard is dropped. Don't try to lock more than one file
+    /// at once, or rewriters may deadlock.
+    pub fn lock_path(&self, p: &RepoPath) -> PathLock<'_, 'a> {
+        let path = p.to_owned();
+        let mut busy = self.busy_paths.lock().unwrap();
+
+        while busy.contains(&path) {
+            busy = self.path_released.wait(busy).unwrap();
+        }
+
+        busy.insert(path.clone());
+        PathLock { ctx: self, path }
+    }
+}
+
+/// A lock on a file being rewritten, as obtained from
+/// [`RewriteContext::lock_path`].
+#[derive(Debug)]
+pub struct PathLock<'c, 'a> {
+    ctx: &'c RewriteContext<'a>,
+    path: RepoPathBuf,
+}
+
+impl Drop for PathLock<'_, '_> {
+    fn drop(&mut self) {
+        if let Ok(mut busy) = self.ctx.busy_paths.lock() {
+            busy.remove(&self.path);
+        }
+
+        self.ctx.path_released.notify_all();
+    }
+}
 
 /// A trait for something that can perform some kind of metadata rewriting.
-pub trait Rewriter: std::fmt::Debug {
+pub trait Rewriter: std::fmt::Debug + Send + Sync {
     /// Rewrite the metafiles to embed the project versions and internal
     /// dependency specifications captured in the current runtime state.
-    fn rewrite(&self, app: &AppSession, changes: &mut ChangeList) -> Result<()>;
+    ///
+    /// Rewriters for different projects may run concurrently, so
+    /// implementations should hold [`RewriteContext::lock_path`] for each
+    /// file while they're updating it.
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()>;
 
     /// Rewrite the metafiles to embed the Cranko-specific internal dependency
     /// version requirement metadata. This should not be done as part of the
@@ -24,3 +127,162 @@ pub trait Rewriter: std::fmt::Debug {
         Ok(())
     }
 }
+
+/// A rewriter that makes a user-configured textual replacement in an arbitrary
+/// file, in the style of `cargo-release`'s `replacements` configuration.
+#[derive(Debug)]
+pub struct ReplacementRewriter {
+    proj_id: ProjectId,
+    path: RepoPathBuf,
+    search: Regex,
+    replace: String,
+}
+
+impl ReplacementRewriter {
+    /// Create a new replacement rewriter for the specified project. The file
+    /// path in the configuration is interpreted relative to the project's
+    /// prefix.
+    pub fn new(
+        proj_id: ProjectId,
+        prefix: &RepoPath,
+        cfg: &ReplacementConfiguration,
+    ) -> Result<Self> {
+        let search = atry!(
+            Regex::new(&cfg.search);
+            ["invalid search pattern `{}`", cfg.search]
+        );
+
+        let mut path = prefix.to_owned();
+        path.push(&cfg.file);
+
+        Ok(ReplacementRewriter {
+            proj_id,
+            path,
+            search,
+            replace: cfg.replace.clone(),
+        })
+    }
+
+    /// Apply the replacement to *text* for a project with the specified
+    /// version. Returns None if the search pattern doesn't match anything.
+    fn apply(&self, text: &str, version: &str) -> Option<String> {
+        if !self.search.is_match(text) {
+            return None;
+        }
+
+        let replace = self.replace.replace("{{version}}", version);
+        Some(self.search.replace_all(text, replace.as_str()).into_owned())
+    }
+}
+
+impl Rewriter for ReplacementRewriter {
+    fn rewrite(&self, ctx: &RewriteContext, changes: &mut ChangeList) -> Result<()> {
+        let proj = ctx.graph().lookup(self.proj_id);
+        let path = ctx.resolve_workdir(&self.path);
+        let _lock = ctx.lock_path(&self.path);
+
+        let text = atry!(
+            fs::read_to_string(&path);
+            ["failed to read file `{}`", path.display()]
+        );
+
+        let new_text = match self.apply(&text, &proj.version.to_string()) {
+            Some(t) => t,
+            None => {
+                warn!(
+                    "replacement pattern `{}` for project `{}` didn't match anything in `{}`",
+                    self.search,
+                    proj.user_facing_name,
+                    path.display()
+                );
+                return Ok(());
+            }
+        };
+
+        atry!(
+            fs::write(&path, new_text.as_bytes());
+            ["failed to overwrite file `{}`", path.display()]
+        );
+        changes.add_path(&self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::ProjectGraphBuilder, repository::test_util::ScratchRepo, version::Version};
+
+    fn replacement(search: &str, replace: &str) -> ReplacementConfiguration {
+        ReplacementConfiguration {
+            file: "README.md".to_owned(),
+            search: search.to_owned(),
+            replace: replace.to_owned(),
+        }
+    }
+
+    #[test]
+    fn replacement_substitution() {
+        let prefix = RepoPathBuf::new(b"");
+        let rw = |search: &str, replace: &str| {
+            ReplacementRewriter::new(0, &prefix, &replacement(search, replace)).unwrap()
+        };
+
+        assert_eq!(
+            rw(r"mytool@\S+", "mytool@{{version}}")
+                .apply("Install mytool@1.0.0 or mytool@0.9\n", "1.2.0")
+                .as_deref(),
+            Some("Install mytool@1.2.0 or mytool@1.2.0\n")
+        );
+
+        assert_eq!(
+            rw(r#"(?m)^(version\s*=\s*")[^"]*(")"#, "${1}{{version}}${2}")
+                .apply("name = \"x\"\nversion = \"1.0.0\"\n", "1.2.0")
+                .as_deref(),
+            Some("name = \"x\"\nversion = \"1.2.0\"\n")
+        );
+
+        assert_eq!(
+            rw(r"(?P<tool>mytool)@\S+", "$tool v{{version}}")
+                .apply("use mytool@1.0\n", "2.0.0")
+                .as_deref(),
+            Some("use mytool v2.0.0\n")
+        );
+
+        assert_eq!(
+            rw(r"othertool@\S+", "othertool@{{version}}").apply("mytool@1.0\n", "1.2.0"),
+            None
+        );
+    }
+
+    #[test]
+    fn unmatched_replacement() {
+        let scratch = ScratchRepo::new("replacement");
+        scratch.write("README.md", "mytool@1.0.0\n");
+
+        let mut graph = ProjectGraphBuilder::new();
+        let ident = graph
+            .try_add_project(vec!["proj".to_owned()], &Default::default())
+            .unwrap();
+        let b = graph.lookup_mut(ident);
+        b.version = Some(Version::Semver(semver::Version::new(1, 2, 0)));
+        b.prefix = Some(RepoPathBuf::new(b""));
+        let graph = graph.complete_loading().unwrap();
+
+        let npm_config = NpmConfiguration::default();
+        let ctx = RewriteContext::new(scratch.dir.clone(), &graph, &npm_config);
+        let prefix = RepoPathBuf::new(b"");
+        let run = |search: &str| {
+            let rw = ReplacementRewriter::new(ident, &prefix, &replacement(search, "X{{version}}"))
+                .unwrap();
+            let mut changes = ChangeList::default();
+            rw.rewrite(&ctx, &mut changes).unwrap();
+            let text = std::fs::read_to_string(scratch.dir.join("README.md")).unwrap();
+            (changes.paths().count(), text)
+        };
+
+        // A pattern that doesn't match only produces a warning.
+        assert_eq!(run("othertool"), (0, "mytool@1.0.0\n".to_owned()));
+        assert_eq!(run(r"@\S+"), (1, "mytoolX1.2.0\n".to_owned()));
+    }
+}
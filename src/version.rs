@@ -11,7 +11,7 @@ use thiserror::Error as ThisError;
 use crate::errors::Result;
 
 pub use dotnet::DotNetVersion;
-pub use pep440::Pep440Version;
+pub use pep440::{Pep440Prerelease, Pep440Version};
 
 /// A version number associated with a project.
 ///
@@ -107,10 +107,59 @@ impl Version {
             "minor bump" => Ok(VersionBumpScheme::MinorBump),
             "major bump" => Ok(VersionBumpScheme::MajorBump),
             "dev-datecode" => Ok(VersionBumpScheme::DevDatecode),
+            "post-release-dev" => Ok(VersionBumpScheme::PostReleaseDev),
+            "alpha bump" | "beta bump" | "rc bump" | "finalize"
+                if self.scheme() == VersionScheme::DotNet =>
+            {
+                Err(UnsupportedBumpSchemeError(text.to_owned(), self.clone()))
+            }
+            "alpha bump" => Ok(VersionBumpScheme::PreRelease(PrereleaseKind::Alpha)),
+            "beta bump" => Ok(VersionBumpScheme::PreRelease(PrereleaseKind::Beta)),
+            "rc bump" => Ok(VersionBumpScheme::PreRelease(PrereleaseKind::Rc)),
+            "finalize" => Ok(VersionBumpScheme::Finalize),
             _ => Err(UnsupportedBumpSchemeError(text.to_owned(), self.clone())),
         }
     }
 
+    /// Get the versioning scheme that this version subscribes to.
+    pub fn scheme(&self) -> VersionScheme {
+        match self {
+            Version::Semver(_) => VersionScheme::Semver,
+            Version::Pep440(_) => VersionScheme::Pep440,
+            Version::DotNet(_) => VersionScheme::DotNet,
+        }
+    }
+
+    /// Re-express this version in a different versioning scheme.
+    ///
+    /// This works by reparsing the textual form of the version, with a special
+    /// case to let .NET versions with a zero revision number become semver
+    /// versions. The operation fails if the version can't be expressed in the
+    /// new scheme.
+    pub fn convert_to_scheme(&self, scheme: VersionScheme) -> Result<Version> {
+        if self.scheme() == scheme {
+            return Ok(self.clone());
+        }
+
+        if let (Version::DotNet(v), VersionScheme::Semver) = (self, scheme) {
+            if v.revision == 0 {
+                return Ok(Version::Semver(semver::Version::new(
+                    v.major as u64,
+                    v.minor as u64,
+                    v.build as u64,
+                )));
+            }
+        }
+
+        let text = self.to_string();
+
+        Ok(match scheme {
+            VersionScheme::Semver => Version::Semver(semver::Version::parse(&text)?),
+            VersionScheme::Pep440 => Version::Pep440(text.parse()?),
+            VersionScheme::DotNet => Version::DotNet(text.parse()?),
+        })
+    }
+
     pub fn as_pep440_tuple_literal(&self) -> Result<String> {
         if let Version::Pep440(v) = self {
             v.as_tuple_literal()
@@ -127,12 +176,97 @@ impl Version {
 #[error("illegal version-bump scheme \"{0}\" for version template {1:?}")]
 pub struct UnsupportedBumpSchemeError(pub String, pub Version);
 
+/// A versioning scheme: the family of version numbers that a project
+/// subscribes to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VersionScheme {
+    /// Semantic versioning.
+    Semver,
+
+    /// Python PEP-440 versioning.
+    Pep440,
+
+    /// .NET System.Version versioning.
+    DotNet,
+}
+
+impl Display for VersionScheme {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            VersionScheme::Semver => write!(f, "semver"),
+            VersionScheme::Pep440 => write!(f, "pep440"),
+            VersionScheme::DotNet => write!(f, "dotnet"),
+        }
+    }
+}
+
+impl std::str::FromStr for VersionScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "semver" => Ok(VersionScheme::Semver),
+            "pep440" => Ok(VersionScheme::Pep440),
+            "dotnet" => Ok(VersionScheme::DotNet),
+            _ => bail!(
+                "unrecognized version scheme `{}` (expected `semver`, `pep440`, or `dotnet`)",
+                s
+            ),
+        }
+    }
+}
+
+/// A kind of prerelease, used by the prerelease bump schemes. Kinds are
+/// ordered from least to most mature.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum PrereleaseKind {
+    /// An alpha release: `-alpha.N` in semver, `aN` in PEP-440.
+    Alpha,
+
+    /// A beta release: `-beta.N` in semver, `bN` in PEP-440.
+    Beta,
+
+    /// A release candidate: `-rc.N` in semver, `rcN` in PEP-440.
+    Rc,
+}
+
+impl Display for PrereleaseKind {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            PrereleaseKind::Alpha => write!(f, "alpha"),
+            PrereleaseKind::Beta => write!(f, "beta"),
+            PrereleaseKind::Rc => write!(f, "rc"),
+        }
+    }
+}
+
+impl std::str::FromStr for PrereleaseKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "alpha" => Ok(PrereleaseKind::Alpha),
+            "beta" => Ok(PrereleaseKind::Beta),
+            "rc" => Ok(PrereleaseKind::Rc),
+            _ => bail!(
+                "unrecognized prerelease kind `{}` (expected `alpha`, `beta`, or `rc`)",
+                s
+            ),
+        }
+    }
+}
+
 /// A scheme for assigning a new version number to a project.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum VersionBumpScheme {
     /// Assigns a development-mode version (likely 0.0.0) with a YYYYMMDD date code included.
     DevDatecode,
 
+    /// Assigns a development-mode version consisting of the current version
+    /// (presumably that of the most recent release) with a development marker
+    /// added.
+    PostReleaseDev,
+
     /// Increment the third-most-significant version number, resetting any
     /// less-significant entries.
     MicroBump,
@@ -145,6 +279,15 @@ pub enum VersionBumpScheme {
     /// less-significant entries.
     MajorBump,
 
+    /// Set or advance a prerelease identifier of the specified kind. If the
+    /// version is already a prerelease of this kind, its counter is
+    /// incremented; if it is a less mature prerelease, the counter restarts at
+    /// 1; and if it is not a prerelease at all, a micro bump is applied first.
+    PreRelease(PrereleaseKind),
+
+    /// Drop the prerelease identifier of the version, finalizing it.
+    Finalize,
+
     /// Force the version to the specified value.
     Force(String),
 }
@@ -156,9 +299,12 @@ impl VersionBumpScheme {
         // versioning systems, so it gets a little hairy.
         return match self {
             VersionBumpScheme::DevDatecode => apply_dev_datecode(version),
+            VersionBumpScheme::PostReleaseDev => apply_post_release_dev(version),
             VersionBumpScheme::MicroBump => apply_micro_bump(version),
             VersionBumpScheme::MinorBump => apply_minor_bump(version),
             VersionBumpScheme::MajorBump => apply_major_bump(version),
+            VersionBumpScheme::PreRelease(kind) => apply_prerelease(version, *kind),
+            VersionBumpScheme::Finalize => apply_finalize(version),
             VersionBumpScheme::Force(ref t) => apply_force(version, t),
         };
 
@@ -193,6 +339,32 @@ impl VersionBumpScheme {
             Ok(())
         }
 
+        #[allow(clippy::unnecessary_wraps)]
+        fn apply_post_release_dev(version: &mut Version) -> Result<()> {
+            match version {
+                Version::Semver(v) => {
+                    v.pre = semver::Prerelease::new("dev").unwrap();
+                    v.build = semver::BuildMetadata::EMPTY;
+                }
+
+                Version::Pep440(v) => {
+                    v.pre_release = None;
+                    v.post_release = None;
+                    v.dev_release = Some(0);
+                    v.local_identifier = None;
+                }
+
+                Version::DotNet(v) => {
+                    // There's no way to attach a textual marker, so we use
+                    // the maximum allowed revision number, which won't be
+                    // produced by any of the other bump schemes.
+                    v.revision = 65534;
+                }
+            }
+
+            Ok(())
+        }
+
         #[allow(clippy::unnecessary_wraps)]
         fn apply_micro_bump(version: &mut Version) -> Result<()> {
             match version {
@@ -298,6 +470,130 @@ impl VersionBumpScheme {
             Ok(())
         }
 
+        fn apply_prerelease(version: &mut Version, kind: PrereleaseKind) -> Result<()> {
+            // Returns the counter for the new prerelease, given the current
+            // one, if any. A version without a prerelease gets a micro bump so
+            // that the new version sorts after it.
+            fn next_counter(
+                current: Option<(PrereleaseKind, u64)>,
+                kind: PrereleaseKind,
+            ) -> Result<Option<u64>> {
+                Ok(match current {
+                    None => None,
+                    Some((k, n)) if k == kind => Some(n + 1),
+                    Some((k, _)) if k < kind => Some(1),
+                    Some((k, n)) => bail!(
+                        "cannot apply a `{} bump` to a version that is already at `{}{}`, \
+                         since that would move it backwards",
+                        kind,
+                        k,
+                        n
+                    ),
+                })
+            }
+
+            match version {
+                Version::Semver(v) => {
+                    let current = if v.pre.is_empty() {
+                        None
+                    } else {
+                        let parsed = v.pre.split_once('.').and_then(|(label, n)| {
+                            let k = label.parse::<PrereleaseKind>().ok()?;
+                            Some((k, n.parse::<u64>().ok()?))
+                        });
+
+                        match parsed {
+                            Some(p) => Some(p),
+                            None => bail!(
+                                "cannot apply a `{} bump` to version {}: its prerelease \
+                                 identifier isn't of the form `{}.N`",
+                                kind,
+                                v,
+                                kind
+                            ),
+                        }
+                    };
+
+                    let n = match next_counter(current, kind)? {
+                        Some(n) => n,
+                        None => {
+                            v.patch += 1;
+                            1
+                        }
+                    };
+
+                    v.pre = semver::Prerelease::new(&format!("{}.{}", kind, n)).unwrap();
+                    v.build = semver::BuildMetadata::EMPTY;
+                }
+
+                Version::Pep440(v) => {
+                    let current = v.pre_release.map(|p| match p {
+                        Pep440Prerelease::Alpha(n) => (PrereleaseKind::Alpha, n as u64),
+                        Pep440Prerelease::Beta(n) => (PrereleaseKind::Beta, n as u64),
+                        Pep440Prerelease::Rc(n) => (PrereleaseKind::Rc, n as u64),
+                    });
+
+                    let n = match next_counter(current, kind)? {
+                        Some(n) => n as usize,
+                        None => {
+                            while v.segments.len() < 3 {
+                                v.segments.push(0);
+                            }
+
+                            v.segments[2] += 1;
+                            v.segments.truncate(3);
+                            1
+                        }
+                    };
+
+                    v.pre_release = Some(match kind {
+                        PrereleaseKind::Alpha => Pep440Prerelease::Alpha(n),
+                        PrereleaseKind::Beta => Pep440Prerelease::Beta(n),
+                        PrereleaseKind::Rc => Pep440Prerelease::Rc(n),
+                    });
+                    v.post_release = None;
+                    v.dev_release = None;
+                    v.local_identifier = None;
+                }
+
+                Version::DotNet(_) => {
+                    bail!(".NET versions cannot express prereleases");
+                }
+            }
+
+            Ok(())
+        }
+
+        fn apply_finalize(version: &mut Version) -> Result<()> {
+            match version {
+                Version::Semver(v) => {
+                    if v.pre.is_empty() {
+                        bail!("cannot finalize version {}: it is not a prerelease", v);
+                    }
+
+                    v.pre = semver::Prerelease::EMPTY;
+                    v.build = semver::BuildMetadata::EMPTY;
+                }
+
+                Version::Pep440(v) => {
+                    if v.pre_release.is_none() && v.dev_release.is_none() {
+                        bail!("cannot finalize version {}: it is not a prerelease", v);
+                    }
+
+                    v.pre_release = None;
+                    v.post_release = None;
+                    v.dev_release = None;
+                    v.local_identifier = None;
+                }
+
+                Version::DotNet(_) => {
+                    bail!(".NET versions cannot express prereleases");
+                }
+            }
+
+            Ok(())
+        }
+
         fn apply_force(version: &mut Version, text: &str) -> Result<()> {
             *version = version.parse_like(text)?;
             Ok(())
@@ -316,22 +612,75 @@ mod dotnet {
     ///
     /// These versions are simple: they have the form
     /// `{major}.{minor}.{build}.{revision}`. Each term must be between 0 and
-    /// 65534.
-    #[derive(Clone, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+    /// 65534. The build and revision terms may be omitted when parsing, in
+    /// which case they are zero; we remember how many terms were given so that
+    /// the version is written back out the same way.
+    #[derive(Clone, Debug)]
     pub struct DotNetVersion {
         pub major: i32,
         pub minor: i32,
         pub build: i32,
         pub revision: i32,
+
+        /// The number of terms in the textual form of the version, between 2
+        /// and 4. Terms that are nonzero are always written out.
+        pub n_components: usize,
+    }
+
+    impl DotNetVersion {
+        fn key(&self) -> [i32; 4] {
+            [self.major, self.minor, self.build, self.revision]
+        }
+    }
+
+    impl Default for DotNetVersion {
+        fn default() -> Self {
+            DotNetVersion {
+                major: 0,
+                minor: 0,
+                build: 0,
+                revision: 0,
+                n_components: 4,
+            }
+        }
+    }
+
+    // Equality and ordering ignore the number of components, so that `1.2`
+    // and `1.2.0.0` are the same version.
+
+    impl PartialEq for DotNetVersion {
+        fn eq(&self, other: &Self) -> bool {
+            self.key() == other.key()
+        }
+    }
+
+    impl Eq for DotNetVersion {}
+
+    impl PartialOrd for DotNetVersion {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for DotNetVersion {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.key().cmp(&other.key())
+        }
     }
 
     impl Display for DotNetVersion {
         fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
-            write!(
-                f,
-                "{}.{}.{}.{}",
-                self.major, self.minor, self.build, self.revision
-            )
+            write!(f, "{}.{}", self.major, self.minor)?;
+
+            if self.n_components > 2 || self.build != 0 || self.revision != 0 {
+                write!(f, ".{}", self.build)?;
+            }
+
+            if self.n_components > 3 || self.revision != 0 {
+                write!(f, ".{}", self.revision)?;
+            }
+
+            Ok(())
         }
     }
 
@@ -341,18 +690,20 @@ mod dotnet {
         fn from_str(s: &str) -> Result<Self> {
             let pieces: std::result::Result<Vec<_>, _> = s.split('.').map(|s| s.parse()).collect();
 
-            match pieces.as_ref().map(|v| v.len()) {
-                Ok(4) => {}
+            let mut pieces = match pieces {
+                Ok(p) if (2..=4).contains(&p.len()) => p,
                 _ => bail!("failed to parse `{}` as a .NET version", s),
-            }
+            };
 
-            let pieces = pieces.unwrap();
+            let n_components = pieces.len();
+            pieces.resize(4, 0);
 
             Ok(DotNetVersion {
                 major: pieces[0],
                 minor: pieces[1],
                 build: pieces[2],
                 revision: pieces[3],
+                n_components,
             })
         }
     }
@@ -377,6 +728,34 @@ mod dotnet {
                 assert!(greater > lesser);
             }
         }
+
+        #[test]
+        fn component_counts() {
+            const CASES: &[(&str, [i32; 4])] = &[
+                ("1.2", [1, 2, 0, 0]),
+                ("1.2.3", [1, 2, 3, 0]),
+                ("1.2.3.4", [1, 2, 3, 4]),
+            ];
+
+            for (text, expected) in CASES {
+                let v = text.parse::<DotNetVersion>().unwrap();
+                assert_eq!(v.key(), *expected);
+                assert_eq!(v.to_string(), *text);
+            }
+
+            assert_eq!(
+                "1.2".parse::<DotNetVersion>().unwrap(),
+                "1.2.0.0".parse::<DotNetVersion>().unwrap()
+            );
+
+            let mut v = "1.2.3".parse::<DotNetVersion>().unwrap();
+            v.revision = 5;
+            assert_eq!(v.to_string(), "1.2.3.5");
+
+            for text in &["1", "1.2.3.4.5", "1.2.3-beta", ""] {
+                assert!(text.parse::<DotNetVersion>().is_err());
+            }
+        }
     }
 }
 
@@ -1118,3 +1497,51 @@ mod pep440 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bump(template: &Version, text: &str, spec: &str) -> Result<String> {
+        let mut v = template.parse_like(text)?;
+        template.parse_bump_scheme(spec)?.apply(&mut v)?;
+        Ok(v.to_string())
+    }
+
+    #[test]
+    fn prerelease_bumps_semver() {
+        let t = Version::Semver(semver::Version::new(0, 0, 0));
+
+        assert_eq!(bump(&t, "1.1.0", "rc bump").unwrap(), "1.1.1-rc.1");
+        assert_eq!(bump(&t, "1.2.0-rc.1", "rc bump").unwrap(), "1.2.0-rc.2");
+        assert_eq!(bump(&t, "1.2.0-rc.2", "rc bump").unwrap(), "1.2.0-rc.3");
+        assert_eq!(
+            bump(&t, "1.2.0-alpha.3", "beta bump").unwrap(),
+            "1.2.0-beta.1"
+        );
+        assert_eq!(bump(&t, "1.2.0-rc.2", "finalize").unwrap(), "1.2.0");
+        assert!(bump(&t, "1.2.0-rc.1", "alpha bump").is_err());
+        assert!(bump(&t, "1.2.0-dev.1", "rc bump").is_err());
+        assert!(bump(&t, "1.2.0", "finalize").is_err());
+    }
+
+    #[test]
+    fn prerelease_bumps_pep440() {
+        let t = Version::Pep440(Pep440Version::default());
+
+        assert_eq!(bump(&t, "1.1", "rc bump").unwrap(), "1.1.1.rc1");
+        assert_eq!(bump(&t, "1.2.0rc1", "rc bump").unwrap(), "1.2.0.rc2");
+        assert_eq!(bump(&t, "1.2.0a2", "alpha bump").unwrap(), "1.2.0.a3");
+        assert_eq!(bump(&t, "1.2.0b2", "rc bump").unwrap(), "1.2.0.rc1");
+        assert_eq!(bump(&t, "1.2.0rc2", "finalize").unwrap(), "1.2.0");
+        assert!(bump(&t, "1.2.0rc1", "beta bump").is_err());
+        assert!(bump(&t, "1.2.0", "finalize").is_err());
+    }
+
+    #[test]
+    fn prerelease_bumps_dotnet() {
+        let t = Version::DotNet(DotNetVersion::default());
+        assert!(t.parse_bump_scheme("rc bump").is_err());
+        assert!(t.parse_bump_scheme("finalize").is_err());
+    }
+}
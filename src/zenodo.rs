@@ -11,27 +11,104 @@ use serde::{Deserialize, Serialize};
 use serde_json::{self, Map, Value};
 use std::{
     fs::{self, File},
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
 };
 use structopt::StructOpt;
 
 use super::Command;
 use crate::{
-    a_ok_or, app::AppSession, atry, env::require_var, errors::Result, project::Project, write_crlf,
+    a_ok_or,
+    app::AppSession,
+    atry,
+    env::{maybe_var, require_secret, secret_is_set},
+    errors::Result,
+    http,
+    line_endings::{LineEnding, LineEndingWriter},
+    project::Project,
 };
 
+/// The default base URL of the Zenodo service. This can be overridden with
+/// the `ZENODO_BASE_URL` environment variable, e.g. to use the sandbox at
+/// `https://sandbox.zenodo.org/`.
+const DEFAULT_BASE_URL: &str = "https://zenodo.org/";
+
 /// A type for interacting with the Zenodo REST API.
 #[derive(Debug)]
 struct ZenodoService {
     token: String,
+    max_attempts: u32,
+    base_url: String,
 }
 
 impl ZenodoService {
     fn new() -> Result<Self> {
-        let token = require_var("ZENODO_TOKEN")?;
+        let token = require_secret("ZENODO_TOKEN")?;
+
+        let max_attempts = match maybe_var("ZENODO_MAX_ATTEMPTS")? {
+            Some(t) => {
+                let n = atry!(
+                    t.parse::<u32>();
+                    ["failed to parse environment variable ZENODO_MAX_ATTEMPTS as an integer"]
+                );
+                ensure!(n > 0, "ZENODO_MAX_ATTEMPTS must be at least 1");
+                n
+            }
+            None => http::DEFAULT_MAX_ATTEMPTS,
+        };
+
+        let mut base_url = maybe_var("ZENODO_BASE_URL")?
+            .filter(|u| !u.is_empty())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_owned());
+
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+
+        let svc = ZenodoService {
+            token,
+            max_attempts,
+            base_url,
+        };
 
-        Ok(ZenodoService { token })
+        if svc.is_sandbox() {
+            info!("using the Zenodo sandbox at {}", svc.base_url);
+        }
+
+        Ok(svc)
+    }
+
+    /// Send an API request, retrying with exponential backoff if it fails in
+    /// a way that seems transient. See [`http::send_with_retry`].
+    fn send_with_retry<F>(
+        &self,
+        desc: &str,
+        idempotent: bool,
+        make_req: F,
+    ) -> Result<reqwest::blocking::Response>
+    where
+        F: Fn() -> reqwest::blocking::RequestBuilder,
+    {
+        http::send_with_retry("Zenodo", desc, self.max_attempts, idempotent, make_req)
+    }
+
+    /// Like [`Self::send_with_retry`], but building the request can fail. See
+    /// [`http::try_send_with_retry`].
+    fn try_send_with_retry<F>(
+        &self,
+        desc: &str,
+        idempotent: bool,
+        make_req: F,
+    ) -> Result<reqwest::blocking::Response>
+    where
+        F: Fn() -> Result<reqwest::blocking::RequestBuilder>,
+    {
+        http::try_send_with_retry("Zenodo", desc, self.max_attempts, idempotent, make_req)
     }
 
     fn make_blocking_client(&self) -> Result<reqwest::blocking::Client> {
@@ -54,7 +131,18 @@ impl ZenodoService {
     }
 
     fn api_url(&self, rest: &str) -> String {
-        format!("https://zenodo.org/api/{}", rest)
+        format!("{}api/{}", self.base_url, rest)
+    }
+
+    /// Get the URL of the web page for viewing a record.
+    fn record_url(&self, rec_id: &str) -> String {
+        format!("{}record/{}", self.base_url, rec_id)
+    }
+
+    /// Determine whether we're talking to the Zenodo sandbox, which issues
+    /// DOIs that are not actually registered.
+    fn is_sandbox(&self) -> bool {
+        self.base_url.contains("sandbox.zenodo.org")
     }
 }
 
@@ -69,12 +157,28 @@ struct ZenodoWorkflow<'a> {
 #[derive(Debug)]
 enum ZenodoMode {
     Development,
+    DryRun,
     Release(ZenodoService),
 }
 
 impl<'a> ZenodoWorkflow<'a> {
-    fn new(proj: &'a Project, dev_mode: bool, proj_is_released: bool) -> Result<Self> {
-        let mode = if dev_mode {
+    fn new(
+        proj: &'a Project,
+        dev_mode: bool,
+        proj_is_released: bool,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let mode = if dry_run {
+            // Unlike development mode, we don't care whether $ZENODO_TOKEN is
+            // set: the whole point is to let people check their setup locally,
+            // where they might well have it around. We just never use it.
+            info!(
+                "faking Zenodo workflow for project `{}` as a dry run",
+                &proj.user_facing_name
+            );
+
+            ZenodoMode::DryRun
+        } else if dev_mode {
             info!(
                 "faking Zenodo workflow for project `{}` in development mode",
                 &proj.user_facing_name
@@ -83,9 +187,9 @@ impl<'a> ZenodoWorkflow<'a> {
             // Make sure that $ZENODO_TOKEN is *not* available in the environment, since
             // this command should be run during both pull-request processing (where the
             // input is untrusted) and release processing (where it is).
-            if std::env::var_os("ZENODO_TOKEN").is_some() {
+            if secret_is_set("ZENODO_TOKEN") {
                 error!(
-                    "the environment variable ZENODO_TOKEN is set during this development-mode job"
+                    "the environment variable ZENODO_TOKEN (or ZENODO_TOKEN_FILE) is set during this development-mode job"
                 );
                 error!("... this could be a security risk given a malicious pull request");
                 error!("... if this is a CI job, fix your configuration to only provide the variable for trusted release jobs");
@@ -163,16 +267,23 @@ impl<'a> ZenodoWorkflow<'a> {
         // one.
 
         let new_concept = match &self.mode {
-            ZenodoMode::Development => {
-                md.concept_doi =
-                    format!("xx.xxxx/dev-build.{}.concept", &self.proj.user_facing_name);
+            ZenodoMode::Development | ZenodoMode::DryRun => {
+                let (rec_tag, doi_tag) = match self.mode {
+                    ZenodoMode::DryRun => ("dry-run", "dry-run"),
+                    _ => ("dev", "dev-build"),
+                };
+
+                md.concept_doi = format!(
+                    "xx.xxxx/{}.{}.concept",
+                    doi_tag, &self.proj.user_facing_name
+                );
                 md.version_rec_id = format!(
-                    "dev.{}.v{}",
-                    &self.proj.user_facing_name, &self.proj.version
+                    "{}.{}.v{}",
+                    rec_tag, &self.proj.user_facing_name, &self.proj.version
                 );
                 md.version_doi = format!(
-                    "xx.xxxx/dev-build.{}.v{}",
-                    &self.proj.user_facing_name, &self.proj.version
+                    "xx.xxxx/{}.{}.v{}",
+                    doi_tag, &self.proj.user_facing_name, &self.proj.version
                 );
                 false
             }
@@ -231,16 +342,18 @@ impl<'a> ZenodoWorkflow<'a> {
         // Rewrite the metadata file with the new info.
 
         {
-            let mut f = atry!(
+            let ending = LineEnding::for_file(metadata_path)?;
+            let f = atry!(
                 File::create(metadata_path);
                 ["failed to open `{}` for rewriting", metadata_path.display()]
             );
+            let mut f = LineEndingWriter::new(f, ending);
             atry!(
                 serde_json::to_writer_pretty(&mut f, &md);
                 ["failed to overwrite JSON file `{}`", metadata_path.display()]
             );
             atry!(
-                write_crlf!(f, "");
+                writeln!(f);
                 ["failed to overwrite JSON file `{}`", metadata_path.display()]
             );
         }
@@ -271,7 +384,7 @@ impl<'a> ZenodoWorkflow<'a> {
     fn preregister_new_concept(&self, svc: &ZenodoService, md: &mut ZenodoMetadata) -> Result<()> {
         let client = svc.make_blocking_client()?;
         let url = svc.api_url("deposit/depositions");
-        self.send_metadata_and_slurp(&client, &url, true, md)
+        self.send_metadata_and_slurp(svc, &client, &url, true, md)
             .map(|_info| ())
     }
 
@@ -286,7 +399,7 @@ impl<'a> ZenodoWorkflow<'a> {
         let client = svc.make_blocking_client()?;
         let url = svc.api_url(&format!("records/{}", &md.concept_rec_id));
 
-        let resp = client.get(url).send()?;
+        let resp = svc.send_with_retry("query concept record", true, || client.get(&url))?;
         let status = resp.status();
         let mut parsed = json::parse(&resp.text()?)?;
 
@@ -321,7 +434,7 @@ impl<'a> ZenodoWorkflow<'a> {
             "deposit/depositions/{}/actions/newversion",
             &last_rec_id
         ));
-        let resp = client.post(url).send()?;
+        let resp = svc.send_with_retry("create new version", false, || client.post(&url))?;
         let status = resp.status();
         let mut parsed = json::parse(&resp.text()?)?;
 
@@ -343,7 +456,7 @@ impl<'a> ZenodoWorkflow<'a> {
         // previous versions, so if a field is supposed to be deleted in this
         // new version, I believe that it will linger.
 
-        let mut info = self.send_metadata_and_slurp(&client, &new_rec_url, false, md)?;
+        let mut info = self.send_metadata_and_slurp(svc, &client, &new_rec_url, false, md)?;
 
         // Continuing the theme of the above: artifacts associated with previous
         // versions will linger too. We emphatically do not want that, so let's
@@ -357,13 +470,22 @@ impl<'a> ZenodoWorkflow<'a> {
 
                 if let Some(url) = fileinfo["links"]["self"].take_string() {
                     info!("deleting propagated artifact file `{}` ...", name);
-                    let resp = client.delete(&url).send()?;
-                    let status = resp.status();
-                    if !status.is_success() {
-                        let t = resp.text().unwrap_or_else(|_e| {
-                            "(unable to parse server response body)".to_owned()
-                        });
-                        warn!("failed to delete {}: {}", url, t);
+
+                    match svc.send_with_retry("delete artifact file", true, || client.delete(&url))
+                    {
+                        Ok(resp) => {
+                            let status = resp.status();
+                            if !status.is_success() {
+                                let t = resp.text().unwrap_or_else(|_e| {
+                                    "(unable to parse server response body)".to_owned()
+                                });
+                                warn!("failed to delete {}: {}", url, t);
+                            }
+                        }
+
+                        Err(e) => {
+                            warn!("failed to delete {}: {}", url, e);
+                        }
                     }
                 } else {
                     info!("not deleting propagated artifact file `{}` because `links.self` was not specified", name);
@@ -376,6 +498,7 @@ impl<'a> ZenodoWorkflow<'a> {
 
     fn send_metadata_and_slurp(
         &self,
+        svc: &ZenodoService,
         client: &reqwest::blocking::Client,
         url: &str,
         do_post: bool,
@@ -391,15 +514,16 @@ impl<'a> ZenodoWorkflow<'a> {
 
         // Send the request.
 
-        let req = if do_post {
-            client.post(url)
-        } else {
-            client.put(url)
-        };
-        let resp = req
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(body)
-            .send()?;
+        let resp = svc.send_with_retry("upload metadata", !do_post, || {
+            let req = if do_post {
+                client.post(url)
+            } else {
+                client.put(url)
+            };
+
+            req.header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+        })?;
         let status = resp.status();
         let mut parsed = json::parse(&resp.text()?)?;
 
@@ -466,7 +590,16 @@ impl<'a> ZenodoWorkflow<'a> {
         if maybe_concept_doi.is_empty() {
             warn!("fabricating Zenodo concept DOI for first-time registration");
             warn!("... it could be incorrect if Zenodo changes their DOI implementation");
-            maybe_concept_doi = format!("10.5281/zenodo.{}", &md.concept_rec_id);
+
+            // The sandbox issues DOIs under the DataCite test prefix.
+            let prefix = if svc.is_sandbox() {
+                warn!("... running against the Zenodo sandbox, so it will not be a real DOI");
+                "10.5072"
+            } else {
+                "10.5281"
+            };
+
+            maybe_concept_doi = format!("{}/zenodo.{}", prefix, &md.concept_rec_id);
         }
 
         md.concept_doi = maybe_concept_doi;
@@ -483,10 +616,14 @@ impl<'a> ZenodoWorkflow<'a> {
         );
         let cur_reader = BufReader::new(cur_f);
 
+        let ending = LineEnding::for_file(path)?;
+
         let new_af =
             atomicwrites::AtomicFile::new(path, atomicwrites::OverwriteBehavior::AllowOverwrite);
 
         let r = new_af.write(|new_f| {
+            let mut new_f = LineEndingWriter::new(new_f, ending);
+
             for line in cur_reader.lines() {
                 let mut line = atry!(
                     line;
@@ -505,7 +642,7 @@ impl<'a> ZenodoWorkflow<'a> {
                 }
 
                 atry!(
-                    write_crlf!(new_f, "{}", line);
+                    writeln!(new_f, "{}", line);
                     ["error writing data to `{}`", new_af.path().display()]
                 );
             }
@@ -663,6 +800,12 @@ pub struct PreregisterCommand {
     )]
     force: bool,
 
+    #[structopt(
+        long = "dry-run",
+        help = "Rewrite files with placeholder DOIs without contacting Zenodo"
+    )]
+    dry_run: bool,
+
     #[structopt(
         long = "metadata",
         help = "The path to a JSON5 file containing Zenodo deposition metadata.",
@@ -689,8 +832,8 @@ impl Command for PreregisterCommand {
 
         // Set up correct versions. This will print out version assignments.
 
-        let (dev_mode, rci) = sess.ensure_ci_rc_mode(self.force)?;
-        sess.apply_versions(&rci)?;
+        let (dev_mode, rci) = sess.ensure_ci_rc_mode(self.force || self.dry_run)?;
+        sess.apply_versions(&rci, dev_mode)?;
 
         // Get information about the project being released and set up the workflow.
 
@@ -701,7 +844,7 @@ impl Command for PreregisterCommand {
 
         let proj = sess.graph().lookup(ident);
         let proj_is_released = rci.lookup_project(proj).is_some();
-        let wf = ZenodoWorkflow::new(proj, dev_mode, proj_is_released)?;
+        let wf = ZenodoWorkflow::new(proj, dev_mode, proj_is_released, self.dry_run)?;
 
         // Go!
 
@@ -761,11 +904,12 @@ impl Command for PublishCommand {
         let body = format!("{{\"metadata\":{}}}", md_body);
 
         let url = svc.api_url(&format!("deposit/depositions/{}", &md.version_rec_id));
-        let resp = client
-            .put(url)
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(body)
-            .send()?;
+        let resp = svc.send_with_retry("update metadata", true, || {
+            client
+                .put(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+        })?;
         let status = resp.status();
         let parsed = json::parse(&resp.text()?)?;
 
@@ -780,7 +924,7 @@ impl Command for PublishCommand {
             "deposit/depositions/{}/actions/publish",
             &md.version_rec_id
         ));
-        let resp = client.post(url).send()?;
+        let resp = svc.send_with_retry("publish", false, || client.post(&url))?;
         let status = resp.status();
         let parsed = json::parse(&resp.text()?)?;
 
@@ -790,8 +934,8 @@ impl Command for PublishCommand {
         }
 
         info!(
-            "publication successful - view at https://zenodo.org/record/{}",
-            &md.version_rec_id
+            "publication successful - view at {}",
+            svc.record_url(&md.version_rec_id)
         );
         Ok(0)
     }
@@ -814,10 +958,65 @@ pub struct UploadArtifactsCommand {
     )]
     metadata_path: PathBuf,
 
+    #[structopt(
+        long = "concurrency",
+        help = "The maximum number of files to upload simultaneously",
+        default_value = "4"
+    )]
+    concurrency: usize,
+
     #[structopt(help = "The path(s) to the file(s) to upload", required = true)]
     paths: Vec<PathBuf>,
 }
 
+impl UploadArtifactsCommand {
+    /// Upload a single artifact file into the deposit's bucket.
+    fn upload_one(
+        svc: &ZenodoService,
+        client: &reqwest::blocking::Client,
+        md: &ZenodoMetadata,
+        path: &Path,
+    ) -> Result<()> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("input file has no name component??"))?
+            .to_str()
+            .ok_or_else(|| anyhow!("input file name cannot be stringified"))?
+            .to_owned();
+
+        let enc = percent_encoding::utf8_percent_encode(&name, percent_encoding::NON_ALPHANUMERIC);
+        info!("uploading `{}` => {}", path.display(), &name);
+
+        let url = format!("{}/{}", md.bucket_link, enc);
+
+        // The body can't be reused, so the file is reopened for every attempt.
+        // Re-uploading a file to the bucket just replaces it, so this is safe
+        // to retry.
+        let resp = svc.try_send_with_retry("upload artifact", true, || {
+            let file = atry!(
+                File::open(path);
+                ["failed to open file `{}`", path.display()]
+            );
+
+            Ok(client
+                .put(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .body(file))
+        })?;
+        let status = resp.status();
+        let parsed = json::parse(&resp.text()?)?;
+
+        if !status.is_success() {
+            error!("Zenodo API response: {}", parsed);
+            bail!("creation of asset `{}` failed", name);
+        }
+
+        // On success, we don't have anything important to do with the
+        // response.
+        Ok(())
+    }
+}
+
 impl Command for UploadArtifactsCommand {
     fn execute(self) -> Result<i32> {
         let sess = AppSession::initialize_default()?;
@@ -840,41 +1039,45 @@ impl Command for UploadArtifactsCommand {
         let svc = ZenodoService::new()?;
         let client = svc.make_blocking_client()?;
 
-        // Ready to go
+        // Ready to go. Worker threads pull files off of a shared counter
+        // until they're all handled, or until something fails.
+
+        let n_workers = self.concurrency.max(1).min(self.paths.len());
+        let next_index = AtomicUsize::new(0);
+        let failures = Mutex::new(Vec::new());
 
-        for path in &self.paths {
-            // Make sure the file exists!
-            let file = File::open(path)?;
+        thread::scope(|s| {
+            for _ in 0..n_workers {
+                s.spawn(|| loop {
+                    let i = next_index.fetch_add(1, Ordering::SeqCst);
 
-            let name = path
-                .file_name()
-                .ok_or_else(|| anyhow!("input file has no name component??"))?
-                .to_str()
-                .ok_or_else(|| anyhow!("input file name cannot be stringified"))?
-                .to_owned();
+                    if i >= self.paths.len() || !failures.lock().unwrap().is_empty() {
+                        break;
+                    }
 
-            let enc =
-                percent_encoding::utf8_percent_encode(&name, percent_encoding::NON_ALPHANUMERIC);
-            info!("uploading `{}` => {}", path.display(), &name);
+                    let path = &self.paths[i];
 
-            let url = format!("{}/{}", md.bucket_link, enc);
-            let resp = client
-                .put(&url)
-                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
-                .body(file)
-                .send()?;
-            let status = resp.status();
-            let parsed = json::parse(&resp.text()?)?;
-
-            if !status.is_success() {
-                error!("Zenodo API response: {}", parsed);
-                bail!("creation of asset `{}` failed", name);
+                    if let Err(e) = Self::upload_one(&svc, &client, &md, path) {
+                        failures.lock().unwrap().push((path, e));
+                    }
+                });
             }
+        });
 
-            // On success, we don't have anything important to do with the
-            // response.
+        let failures = failures.into_inner().unwrap();
+
+        if failures.is_empty() {
+            return Ok(0);
         }
 
-        Ok(0)
+        for (path, e) in &failures {
+            error!("failed to upload `{}`: {:#}", path.display(), e);
+        }
+
+        let names: Vec<_> = failures
+            .iter()
+            .map(|(path, _e)| format!("`{}`", path.display()))
+            .collect();
+        bail!("failed to upload artifact(s): {}", names.join(", "))
     }
 }